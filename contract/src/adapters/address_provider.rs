@@ -0,0 +1,17 @@
+use cosmwasm_std::{Addr, QuerierWrapper, StdResult};
+use mars_core::address_provider::msg::QueryMsg;
+use mars_core::address_provider::MarsContract;
+
+/// Helper functions for interacting with Mars protocol's address provider contract
+pub struct AddressProvider<'a>(pub &'a Addr);
+
+impl<'a> AddressProvider<'a> {
+    /// Query the address of the specified Mars contract
+    pub fn query_address(
+        &self,
+        querier: &QuerierWrapper,
+        contract: MarsContract,
+    ) -> StdResult<Addr> {
+        querier.query_wasm_smart(self.0.clone(), &QueryMsg::Address { contract })
+    }
+}
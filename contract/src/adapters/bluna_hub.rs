@@ -1,7 +1,9 @@
+use std::cmp;
 use std::str::FromStr;
 
 use basset::hub::{
-    Cw20HookMsg, ExecuteMsg, QueryMsg, UnbondRequestsResponse, WithdrawableUnbondedResponse,
+    AllHistoryResponse, CurrentBatchResponse, Cw20HookMsg, ExecuteMsg, Parameters, QueryMsg,
+    StateResponse, UnbondHistory, UnbondRequestsResponse, WithdrawableUnbondedResponse,
 };
 use cosmwasm_std::{
     to_binary, Addr, CosmosMsg, Event, QuerierWrapper, StdError, StdResult, Uint128, WasmMsg,
@@ -10,6 +12,9 @@ use cw_asset::Asset;
 
 use super::helpers::event_contains_attr;
 
+/// The maximum number of unbonding batches that bLuna Hub returns per history query
+const MAX_HISTORY_LIMIT: u64 = 100;
+
 /// Helper functions for interacting with Anchor protocol's bLuna Hub contract
 pub struct Hub<'a>(pub &'a Addr);
 
@@ -75,4 +80,58 @@ impl<'a> Hub<'a> {
             },
         )
     }
+
+    /// Query bLuna Hub's state, including the exchange rate between bLuna and Luna
+    pub fn query_state(&self, querier: &QuerierWrapper) -> StdResult<StateResponse> {
+        querier.query_wasm_smart(self.0.to_string(), &QueryMsg::State {})
+    }
+
+    /// Query bLuna Hub's parameters, including the epoch and unbonding periods
+    pub fn query_parameters(&self, querier: &QuerierWrapper) -> StdResult<Parameters> {
+        querier.query_wasm_smart(self.0.to_string(), &QueryMsg::Parameters {})
+    }
+
+    /// Query the unbonding batch that is currently accepting unbonding requests
+    pub fn query_current_batch(&self, querier: &QuerierWrapper) -> StdResult<CurrentBatchResponse> {
+        querier.query_wasm_smart(self.0.to_string(), &QueryMsg::CurrentBatch {})
+    }
+
+    /// Query the history of unbonding batches, starting after the specified batch id
+    pub fn query_unbond_history(
+        &self,
+        querier: &QuerierWrapper,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    ) -> StdResult<AllHistoryResponse> {
+        querier.query_wasm_smart(
+            self.0.to_string(),
+            &QueryMsg::AllHistory {
+                start_from: start_after,
+                limit,
+            },
+        )
+    }
+
+    /// Query the history of unbonding batches from the first to the last specified batch id,
+    /// inclusive, in as many pages as bLuna Hub's maximum page size requires
+    pub fn query_unbond_history_range(
+        &self,
+        querier: &QuerierWrapper,
+        first_batch_id: u64,
+        last_batch_id: u64,
+    ) -> StdResult<Vec<UnbondHistory>> {
+        let mut history: Vec<UnbondHistory> = vec![];
+        let mut start_after = first_batch_id.saturating_sub(1);
+        while start_after < last_batch_id {
+            let limit = cmp::min(last_batch_id - start_after, MAX_HISTORY_LIMIT);
+            let page =
+                self.query_unbond_history(querier, Some(start_after), Some(limit as u32))?.history;
+            match page.last() {
+                Some(last) => start_after = last.batch_id,
+                None => break,
+            }
+            history.extend(page);
+        }
+        Ok(history)
+    }
 }
@@ -0,0 +1,19 @@
+use basset::reward::ExecuteMsg;
+use cosmwasm_std::{to_binary, Addr, CosmosMsg, StdResult, WasmMsg};
+
+/// Helper functions for interacting with Anchor protocol's bLuna reward contract
+pub struct Reward<'a>(pub &'a Addr);
+
+impl<'a> Reward<'a> {
+    /// Create a message for claiming the UST rewards accrued on bLuna held, to be sent to the
+    /// specified recipient
+    pub fn claim_rewards_msg(&self, recipient: &Addr) -> StdResult<CosmosMsg> {
+        Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: self.0.to_string(),
+            msg: to_binary(&ExecuteMsg::ClaimRewards {
+                recipient: Some(recipient.to_string()),
+            })?,
+            funds: vec![],
+        }))
+    }
+}
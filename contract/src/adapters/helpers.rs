@@ -1,4 +1,7 @@
-use cosmwasm_std::Event;
+use std::str::FromStr;
+
+use cosmwasm_std::{Api, Event, StdError, StdResult, Uint128};
+use cw_asset::Asset;
 
 /// Returns `true` if the event contains the specified key-value pair
 pub fn event_contains_attr(event: &Event, key: &str, value: &str) -> bool {
@@ -7,3 +10,39 @@ pub fn event_contains_attr(event: &Event, key: &str, value: &str) -> bool {
         .iter()
         .any(|attr| attr.key == key && attr.value == value)
 }
+
+/// Parse an Astroport pair's `swap` event to find out the returned asset and its amount
+pub fn parse_swap_event(api: &dyn Api, event: &Event) -> StdResult<Asset> {
+    let ask_asset_str = event
+        .attributes
+        .iter()
+        .cloned()
+        .find(|attr| attr.key == "ask_asset")
+        .ok_or_else(|| StdError::generic_err("cannot find `ask_asset` attribute"))?
+        .value;
+
+    let return_amount_str = event
+        .attributes
+        .iter()
+        .cloned()
+        .find(|attr| attr.key == "return_amount")
+        .ok_or_else(|| StdError::generic_err("cannot find `return_amount` attribute"))?
+        .value;
+
+    let return_amount = Uint128::from_str(&return_amount_str)?;
+
+    // If the asset's label can be parsed into an `Addr`, then we assume it is a CW20; otherwise,
+    // we assume it is a native coin.
+    //
+    // Not a perfectly safe implementation; as native coins can have arbitrary denoms, it is
+    // possible to create an native coin whose denom is a valid Terra address. However, since
+    // Terra does not allow minting arbitrary native coins, this risk is clsoe to non-existent.
+    //
+    // If only Astroport had used `cw-asset`... There wouldn't have been this ambiguity!
+    let return_asset = match api.addr_validate(&ask_asset_str) {
+        Ok(contract_addr) => Asset::cw20(contract_addr, return_amount),
+        _ => Asset::native(ask_asset_str, return_amount),
+    };
+
+    Ok(return_asset)
+}
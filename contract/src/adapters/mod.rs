@@ -1,8 +1,14 @@
+mod address_provider;
 mod bluna_hub;
+mod bluna_reward;
 mod helpers;
 mod pair;
 mod red_bank;
+mod router;
 
+pub use address_provider::AddressProvider;
 pub use bluna_hub::Hub;
+pub use bluna_reward::Reward;
 pub use pair::Pair;
 pub use red_bank::RedBank;
+pub use router::Router;
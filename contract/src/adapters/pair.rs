@@ -1,11 +1,13 @@
 use std::convert::TryInto;
-use std::str::FromStr;
 
-use astroport::pair::{Cw20HookMsg, ExecuteMsg};
-use cosmwasm_std::{to_binary, Addr, Api, CosmosMsg, Event, StdError, StdResult, Uint128, WasmMsg};
+use astroport::asset::PairInfo;
+use astroport::pair::{Cw20HookMsg, ExecuteMsg, QueryMsg, SimulationResponse};
+use cosmwasm_std::{
+    to_binary, Addr, Api, CosmosMsg, Decimal, Event, QuerierWrapper, StdError, StdResult, WasmMsg,
+};
 use cw_asset::{Asset, AssetInfo};
 
-use super::helpers::event_contains_attr;
+use super::helpers::{event_contains_attr, parse_swap_event};
 
 /// Helper function for interacting with Astroport pair contract
 pub struct Pair<'a>(pub &'a Addr);
@@ -13,13 +15,25 @@ pub struct Pair<'a>(pub &'a Addr);
 impl<'a> Pair<'a> {
     /// Create a `SubMsg` that swaps the specified asset
     pub fn swap_msg(&self, asset: &Asset) -> StdResult<CosmosMsg> {
+        self.swap_msg_with_options(asset, None, None)
+    }
+
+    /// Create a `SubMsg` that swaps the specified asset, optionally with a maximum spread, and
+    /// optionally sending the returned asset to a recipient other than the sender
+    pub fn swap_msg_with_options(
+        &self,
+        asset: &Asset,
+        max_spread: Option<Decimal>,
+        to: Option<&Addr>,
+    ) -> StdResult<CosmosMsg> {
+        let to = to.map(|addr| addr.to_string());
         match &asset.info {
             AssetInfo::Cw20(_) => asset.send_msg(
                 self.0,
                 to_binary(&Cw20HookMsg::Swap {
                     belief_price: None,
-                    max_spread: None,
-                    to: None,
+                    max_spread,
+                    to,
                 })?,
             ),
             AssetInfo::Native(_) => Ok(CosmosMsg::Wasm(WasmMsg::Execute {
@@ -27,8 +41,8 @@ impl<'a> Pair<'a> {
                 msg: to_binary(&ExecuteMsg::Swap {
                     offer_asset: asset.clone().into(),
                     belief_price: None,
-                    max_spread: None,
-                    to: None,
+                    max_spread,
+                    to,
                 })?,
                 funds: vec![asset.try_into()?],
             })),
@@ -42,37 +56,25 @@ impl<'a> Pair<'a> {
             .find(|event| event_contains_attr(event, "action", "swap"))
             .ok_or_else(|| StdError::generic_err("cannot find `swap` event"))?;
 
-        let ask_asset_str = event
-            .attributes
-            .iter()
-            .cloned()
-            .find(|attr| attr.key == "ask_asset")
-            .ok_or_else(|| StdError::generic_err("cannot find `ask_asset` attribute"))?
-            .value;
-
-        let return_amount_str = event
-            .attributes
-            .iter()
-            .cloned()
-            .find(|attr| attr.key == "return_amount")
-            .ok_or_else(|| StdError::generic_err("cannot find `return_amount` attribute"))?
-            .value;
-
-        let return_amount = Uint128::from_str(&return_amount_str)?;
+        parse_swap_event(api, event)
+    }
 
-        // If the asset's label can be parsed into an `Addr`, then we assume it is a CW20; otherwise,
-        // we assume it is a native coin.
-        //
-        // Not a perfectly safe implementation; as native coins can have arbitrary denoms, it is
-        // possible to create an native coin whose denom is a valid Terra address. However, since
-        // Terra does not allow minting arbitrary native coins, this risk is clsoe to non-existent.
-        //
-        // If only Astroport had used `cw-asset`... There wouldn't have been this ambiguity!
-        let return_asset = match api.addr_validate(&ask_asset_str) {
-            Ok(contract_addr) => Asset::cw20(contract_addr, return_amount),
-            _ => Asset::native(ask_asset_str, return_amount),
-        };
+    /// Query the amount of asset that would be returned if the specified asset is swapped
+    pub fn query_simulation(
+        &self,
+        querier: &QuerierWrapper,
+        asset: &Asset,
+    ) -> StdResult<SimulationResponse> {
+        querier.query_wasm_smart(
+            self.0.to_string(),
+            &QueryMsg::Simulation {
+                offer_asset: asset.clone().into(),
+            },
+        )
+    }
 
-        Ok(return_asset)
+    /// Query the assets traded by the pair
+    pub fn query_pair(&self, querier: &QuerierWrapper) -> StdResult<PairInfo> {
+        querier.query_wasm_smart(self.0.to_string(), &QueryMsg::Pair {})
     }
 }
@@ -1,10 +1,11 @@
 use std::convert::TryInto;
 
-use cosmwasm_std::{to_binary, Addr, CosmosMsg, QuerierWrapper, StdResult, WasmMsg};
-use cw20::Cw20ExecuteMsg;
+use cosmwasm_std::{to_binary, Addr, CosmosMsg, QuerierWrapper, StdResult, Uint128, WasmMsg};
+use cw20::{BalanceResponse, Cw20ExecuteMsg};
 use cw_asset::{Asset, AssetInfo};
+use mars_core::ma_token::msg::QueryMsg as MaTokenQueryMsg;
 use mars_core::red_bank::msg::{ExecuteMsg, QueryMsg, ReceiveMsg};
-use mars_core::red_bank::UserAssetDebtResponse;
+use mars_core::red_bank::{ConfigResponse, Market, UserAssetDebtResponse};
 
 /// Helper functions for interacting with Mars protocol's Red Bank contract
 pub struct RedBank<'a>(pub &'a Addr);
@@ -45,6 +46,42 @@ impl<'a> RedBank<'a> {
         })
     }
 
+    /// Create a message to deposit the specified asset into Red Bank
+    pub fn deposit_msg(&self, asset: &Asset) -> StdResult<CosmosMsg> {
+        Ok(match &asset.info {
+            AssetInfo::Cw20(_) => asset.send_msg(
+                self.0,
+                to_binary(&ReceiveMsg::DepositCw20 { on_behalf_of: None })?,
+            )?,
+            AssetInfo::Native(denom) => CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: self.0.to_string(),
+                msg: to_binary(&ExecuteMsg::DepositNative {
+                    denom: denom.into(),
+                    on_behalf_of: None,
+                })?,
+                funds: vec![asset.try_into()?],
+            }),
+        })
+    }
+
+    /// Create a message to withdraw the specified asset from Red Bank. If amount is not provided,
+    /// the entire deposit is withdrawn.
+    pub fn withdraw_msg(
+        &self,
+        asset_info: &AssetInfo,
+        amount: Option<Uint128>,
+    ) -> StdResult<CosmosMsg> {
+        Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: self.0.to_string(),
+            msg: to_binary(&ExecuteMsg::Withdraw {
+                asset: asset_info.into(),
+                amount,
+                recipient: None,
+            })?,
+            funds: vec![],
+        }))
+    }
+
     /// Query the user's debt of the specified asset at Red Bank
     pub fn query_user_asset_debt(
         &self,
@@ -60,4 +97,41 @@ impl<'a> RedBank<'a> {
             },
         )
     }
+
+    /// Query Red Bank's config
+    pub fn query_config(&self, querier: &QuerierWrapper) -> StdResult<ConfigResponse> {
+        querier.query_wasm_smart(self.0.clone(), &QueryMsg::Config {})
+    }
+
+    /// Query Red Bank's market of the specified asset
+    pub fn query_market(
+        &self,
+        querier: &QuerierWrapper,
+        asset_info: &AssetInfo,
+    ) -> StdResult<Market> {
+        querier.query_wasm_smart(
+            self.0.clone(),
+            &QueryMsg::Market {
+                asset: asset_info.into(),
+            },
+        )
+    }
+
+    /// Query the amount of the specified asset the user has deposited at Red Bank, including
+    /// accrued interest. This is the underlying asset balance of the user's maTokens.
+    pub fn query_user_deposit(
+        &self,
+        querier: &QuerierWrapper,
+        user_addr: &Addr,
+        asset_info: &AssetInfo,
+    ) -> StdResult<Uint128> {
+        let market = self.query_market(querier, asset_info)?;
+        let res: BalanceResponse = querier.query_wasm_smart(
+            market.ma_token_address,
+            &MaTokenQueryMsg::UnderlyingAssetBalance {
+                address: user_addr.to_string(),
+            },
+        )?;
+        Ok(res.balance)
+    }
 }
@@ -0,0 +1,79 @@
+use std::convert::TryInto;
+
+use astroport::router::{ExecuteMsg, QueryMsg, SimulateSwapOperationsResponse, SwapOperation};
+use cosmwasm_std::{
+    to_binary, Addr, Api, CosmosMsg, Decimal, Event, QuerierWrapper, StdError, StdResult, Uint128,
+    WasmMsg,
+};
+use cw_asset::{Asset, AssetInfo};
+
+use super::helpers::{event_contains_attr, parse_swap_event};
+
+/// Helper functions for interacting with Astroport router contract. Routes are specified as the
+/// assets swapped through, starting with the offer asset and ending with the ask asset; each hop
+/// is a swap at the Astroport pair of the two assets.
+pub struct Router<'a>(pub &'a Addr);
+
+impl<'a> Router<'a> {
+    /// Create a message that swaps the specified native asset along the specified route
+    pub fn swap_msg(
+        &self,
+        asset: &Asset,
+        route: &[AssetInfo],
+        max_spread: Option<Decimal>,
+    ) -> StdResult<CosmosMsg> {
+        match &asset.info {
+            AssetInfo::Native(_) => Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: self.0.to_string(),
+                msg: to_binary(&ExecuteMsg::ExecuteSwapOperations {
+                    operations: operations(route),
+                    minimum_receive: None,
+                    to: None,
+                    max_spread,
+                })?,
+                funds: vec![asset.try_into()?],
+            })),
+            AssetInfo::Cw20(_) => Err(StdError::generic_err("only native assets can be routed")),
+        }
+    }
+
+    /// When handling the response of a routed swap, parse the events to find out the asset returned
+    /// by the last hop and its amount
+    pub fn parse_swap_events(&self, api: &dyn Api, events: &[Event]) -> StdResult<Asset> {
+        let event = events
+            .iter()
+            .rev()
+            .find(|event| event_contains_attr(event, "action", "swap"))
+            .ok_or_else(|| StdError::generic_err("cannot find `swap` event"))?;
+
+        parse_swap_event(api, event)
+    }
+
+    /// Query the amount of the ask asset that would be returned if the specified amount of the
+    /// offer asset is swapped along the specified route
+    pub fn query_simulation(
+        &self,
+        querier: &QuerierWrapper,
+        offer_amount: Uint128,
+        route: &[AssetInfo],
+    ) -> StdResult<Uint128> {
+        let res: SimulateSwapOperationsResponse = querier.query_wasm_smart(
+            self.0.to_string(),
+            &QueryMsg::SimulateSwapOperations {
+                offer_amount,
+                operations: operations(route),
+            },
+        )?;
+        Ok(res.amount)
+    }
+}
+
+fn operations(route: &[AssetInfo]) -> Vec<SwapOperation> {
+    route
+        .windows(2)
+        .map(|hop| SwapOperation::AstroSwap {
+            offer_asset_info: hop[0].clone().into(),
+            ask_asset_info: hop[1].clone().into(),
+        })
+        .collect()
+}
@@ -1,66 +1,539 @@
-use cosmwasm_std::{Addr, Api, Decimal, StdError, StdResult};
+use std::str::FromStr;
+
+use astroport::asset::AssetInfo;
+use astroport::pair::MAX_ALLOWED_SLIPPAGE;
+use cosmwasm_std::{
+    from_binary, Addr, Api, Binary, Decimal, QuerierWrapper, StdError, StdResult, Uint128,
+};
 use schemars::JsonSchema;
+use serde::de::IgnoredAny;
 use serde::{Deserialize, Serialize};
 
+use crate::adapters::Pair;
+
+/// How profit is paid out to recipients and owner
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutMode {
+    /// Profit is sent to recipients and owner when positions are settled
+    Push,
+    /// Profit is credited to recipients and owner when positions are settled, and they need to
+    /// claim it themselves
+    Pull,
+}
+
+/// An account to receive profit, and how the profit is to be delivered to it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Recipient<T> {
+    /// Profit is sent to the address with a plain transfer
+    Address(T),
+    /// The contract is executed with the message, with profit attached as funds. If the execution
+    /// fails, profit is sent to the contract with a plain transfer instead. Only supported if
+    /// payout mode is `Push`.
+    Contract {
+        contract: T,
+        msg: Binary,
+    },
+}
+
+impl<T> Recipient<T> {
+    /// Address of the account that receives the profit
+    pub fn address(&self) -> &T {
+        match self {
+            Recipient::Address(address) => address,
+            Recipient::Contract { contract, .. } => contract,
+        }
+    }
+}
+
+impl From<Recipient<Addr>> for Recipient<String> {
+    fn from(recipient: Recipient<Addr>) -> Self {
+        match recipient {
+            Recipient::Address(address) => Recipient::Address(address.to_string()),
+            Recipient::Contract { contract, msg } => Recipient::Contract {
+                contract: contract.to_string(),
+                msg,
+            },
+        }
+    }
+}
+
+impl Recipient<String> {
+    pub fn check(&self, api: &dyn Api) -> StdResult<Recipient<Addr>> {
+        match self {
+            Recipient::Address(address) => Ok(Recipient::Address(api.addr_validate(address)?)),
+            Recipient::Contract { contract, msg } => {
+                // We can't know the contract's message schema, but we can at least make sure the
+                // message is valid JSON, so that the payout doesn't fail for a malformed message
+                from_binary::<IgnoredAny>(msg).map_err(|_| {
+                    StdError::generic_err(format!(
+                        "message for contract {} is not valid JSON",
+                        contract
+                    ))
+                })?;
+                Ok(Recipient::Contract {
+                    contract: api.addr_validate(contract)?,
+                    msg: msg.clone(),
+                })
+            },
+        }
+    }
+}
+
+/// An account's share of the profit, and the asset it prefers to be paid in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ProfitShare<T> {
+    /// Account to receive the share
+    pub recipient: Recipient<T>,
+    /// Percentage share of the profit
+    pub share: Decimal,
+    /// If provided, the recipient's Luna is converted to another asset before being sent. Only
+    /// supported if payout mode is `Push`, and not supported for contract recipients. If the
+    /// conversion fails, e.g. due to slippage, the recipient is sent Luna instead.
+    pub conversion: Option<Conversion<T>>,
+}
+
+/// How to convert a recipient's Luna before sending it. Only swaps through an Astroport pair are
+/// supported; native swaps through Terra's market module, e.g. Luna to UST, are not.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Conversion<T> {
+    /// Address of an Astroport pair of Luna and the preferred asset. Must trade Luna.
+    pub pair: T,
+    /// The maximum spread allowed when swapping Luna for the preferred asset
+    pub max_spread: Decimal,
+}
+
+impl From<ProfitShare<Addr>> for ProfitShare<String> {
+    fn from(profit_share: ProfitShare<Addr>) -> Self {
+        Self {
+            recipient: profit_share.recipient.into(),
+            share: profit_share.share,
+            conversion: profit_share.conversion.map(|conversion| Conversion {
+                pair: conversion.pair.to_string(),
+                max_spread: conversion.max_spread,
+            }),
+        }
+    }
+}
+
+impl ProfitShare<String> {
+    pub fn check(&self, api: &dyn Api, querier: &QuerierWrapper) -> StdResult<ProfitShare<Addr>> {
+        let conversion = match &self.conversion {
+            Some(_) if matches!(self.recipient, Recipient::Contract { .. }) => {
+                return Err(StdError::generic_err(format!(
+                    "conversion is not supported for contract recipient {}",
+                    self.recipient.address()
+                )));
+            },
+            Some(conversion) => {
+                let max_allowed_spread = Decimal::from_str(MAX_ALLOWED_SLIPPAGE)?;
+                if conversion.max_spread > max_allowed_spread {
+                    return Err(StdError::generic_err(format!(
+                        "max spread {} is greater than {}",
+                        conversion.max_spread, max_allowed_spread
+                    )));
+                }
+                let pair = api.addr_validate(&conversion.pair)?;
+                let luna = AssetInfo::NativeToken {
+                    denom: String::from("uluna"),
+                };
+                if !Pair(&pair).query_pair(querier)?.asset_infos.contains(&luna) {
+                    return Err(StdError::generic_err(
+                        format!("conversion pair {} does not trade uluna", pair)
+                    ));
+                }
+                Some(Conversion {
+                    pair,
+                    max_spread: conversion.max_spread,
+                })
+            },
+            None => None,
+        };
+
+        Ok(ProfitShare {
+            recipient: self.recipient.check(api)?,
+            share: self.share,
+            conversion,
+        })
+    }
+}
+
+/// Fee charged by Mars protocol on realised profit, as required by the credit line agreement
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ProtocolFee<T> {
+    /// Portion of realised profit charged as the fee, as agreed with Mars. Must be less or equal to
+    /// one.
+    pub rate: Decimal,
+    /// Address to receive the fee. If not provided, Mars treasury's address is queried from Mars
+    /// address provider.
+    pub treasury: Option<T>,
+}
+
+impl From<ProtocolFee<Addr>> for ProtocolFee<String> {
+    fn from(protocol_fee: ProtocolFee<Addr>) -> Self {
+        Self {
+            rate: protocol_fee.rate,
+            treasury: protocol_fee.treasury.map(|treasury| treasury.to_string()),
+        }
+    }
+}
+
+impl ProtocolFee<String> {
+    pub fn check(&self, api: &dyn Api) -> StdResult<ProtocolFee<Addr>> {
+        if self.rate > Decimal::one() {
+            return Err(StdError::generic_err(
+                format!("protocol fee rate {} is greater than one", self.rate)
+            ));
+        }
+
+        Ok(ProtocolFee {
+            rate: self.rate,
+            treasury: self
+                .treasury
+                .as_ref()
+                .map(|treasury| api.addr_validate(treasury))
+                .transpose()?,
+        })
+    }
+}
+
+/// Reward paid to whoever calls `FinalizeArb`, so that positions are settled and debt repaid in
+/// time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct KeeperBounty {
+    /// Portion of realised profit, net of protocol fee, paid to the caller. Must be less or equal
+    /// to one.
+    pub rate: Decimal,
+    /// The maximum amount of Luna paid to the caller per call
+    pub cap: Uint128,
+}
+
+/// Parameters within which anyone can trigger an arbitrage operation
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Strategy {
+    /// The minimum profit (bLuna amount / Luna amount - 1) that a triggered arb must make
+    pub minimum_profit: Decimal,
+    /// The maximum amount of Luna to borrow in a triggered arb
+    pub max_size: Uint128,
+    /// The minimum number of seconds between two triggered arbs
+    pub cooldown: u64,
+    /// Amount of Luna paid from the reserve fund to the caller of a successful trigger
+    pub bounty: Uint128,
+}
+
+/// Instead of unbonding swapped bLuna right away, hold it until shortly before bLuna Hub's next
+/// unbonding batch, so that it keeps earning bLuna rewards while waiting for the batch
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BatchUnbonding {
+    /// Number of seconds before bLuna Hub's next batch time from which held bLuna can be submitted
+    /// for unbonding
+    pub submit_window: u64,
+}
+
+/// Parameters of the liquidity desk, which buys bLuna from anyone for Luna borrowed from Red Bank,
+/// at a discount to bLuna Hub's exchange rate, and unbonds it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Desk {
+    /// Discount to the Luna value of the bLuna at bLuna Hub's exchange rate at which bLuna is
+    /// bought. Must be less than one.
+    pub discount: Decimal,
+    /// The maximum amount of Luna to pay for bLuna per fill
+    pub max_size: Uint128,
+    /// The maximum amount of Luna paid for bLuna bought by the desk that can be outstanding, i.e.
+    /// owed to Red Bank while the bLuna is being unbonded
+    pub max_debt: Uint128,
+}
+
+/// Parameters for opening positions with UST borrowed from Red Bank, for when Red Bank's Luna
+/// liquidity is thin. The UST is swapped for bLuna through Astroport router, and the unbonded Luna
+/// is swapped back to UST to repay the debt.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UstBorrowing<T> {
+    /// Address of Astroport router contract
+    pub router: T,
+    /// The maximum spread of each hop of a routed swap
+    pub max_spread: Decimal,
+    /// The maximum amount of UST debt owed to Red Bank. As the debt is repaid with unbonded Luna,
+    /// this bounds the exposure to Luna's price while the bLuna is unbonding.
+    pub max_debt: Uint128,
+}
+
+impl From<UstBorrowing<Addr>> for UstBorrowing<String> {
+    fn from(ust_borrowing: UstBorrowing<Addr>) -> Self {
+        Self {
+            router: ust_borrowing.router.to_string(),
+            max_spread: ust_borrowing.max_spread,
+            max_debt: ust_borrowing.max_debt,
+        }
+    }
+}
+
+impl UstBorrowing<String> {
+    pub fn check(&self, api: &dyn Api) -> StdResult<UstBorrowing<Addr>> {
+        Ok(UstBorrowing {
+            router: api.addr_validate(&self.router)?,
+            max_spread: self.max_spread,
+            max_debt: self.max_debt,
+        })
+    }
+}
+
+/// Contracts for arbing between bLuna and stLuna when they trade at different discounts. Borrowed
+/// Luna buys the cheaper of the two, which is swapped for the dearer one and sold back for Luna,
+/// all within the same transaction.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CrossLsd<T> {
+    /// Address of stLuna token
+    pub stluna_token: T,
+    /// Address of Astroport stLuna/Luna pair
+    pub stluna_pair: T,
+    /// Address of Astroport bLuna/stLuna pair
+    pub lsd_pair: T,
+}
+
+impl From<CrossLsd<Addr>> for CrossLsd<String> {
+    fn from(cross_lsd: CrossLsd<Addr>) -> Self {
+        Self {
+            stluna_token: cross_lsd.stluna_token.to_string(),
+            stluna_pair: cross_lsd.stluna_pair.to_string(),
+            lsd_pair: cross_lsd.lsd_pair.to_string(),
+        }
+    }
+}
+
+impl CrossLsd<String> {
+    pub fn check(&self, api: &dyn Api) -> StdResult<CrossLsd<Addr>> {
+        Ok(CrossLsd {
+            stluna_token: api.addr_validate(&self.stluna_token)?,
+            stluna_pair: api.addr_validate(&self.stluna_pair)?,
+            lsd_pair: api.addr_validate(&self.lsd_pair)?,
+        })
+    }
+}
+
+/// An address that can take over ownership if the owner is inactive for too long, e.g. when the
+/// owner's key is lost
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Recovery<T> {
+    /// Address that can claim ownership
+    pub address: T,
+    /// Number of seconds since the owner's last execute after which ownership can be claimed
+    pub inactivity_timeout: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config<T> {
     /// Owner of this contract
     pub owner: T,
+    /// If provided, this address can claim ownership once the owner has been inactive for long
+    pub recovery: Option<Recovery<T>>,
     /// Address of the bLuna token
     pub bluna_token: T,
     /// Address of Astroport bLuna-Luna pair
     pub bluna_pair: T,
     /// Address of Anchor protocol bLuna Hub contract
     pub bluna_hub: T,
+    /// If provided, address of Anchor protocol bLuna reward contract, from which the UST rewards
+    /// accrued on bLuna held by the contract are claimed for the owner. Required if batch unbonding
+    /// is enabled.
+    pub bluna_reward: Option<T>,
     /// Address of Mars protocol lending market contract
     pub red_bank: T,
-    /// Accounts to receive portions of the profit, and their percentage shares. The sum of the
-    /// shares must be less or equal to one. Remaining profit goes to the owner.
-    pub profit_shares: Vec<(T, Decimal)>,
+    /// If provided, this address can force an unwind of the contract's positions, in addition to
+    /// Red Bank
+    pub council: Option<T>,
+    /// If provided, this address can trigger an emergency exit, in addition to the owner
+    pub guardian: Option<T>,
+    /// Fee paid to Mars protocol, charged on realised profit before anything else is taken from it
+    pub protocol_fee: ProtocolFee<T>,
+    /// Reward paid to the caller of `FinalizeArb` if any unbonded Luna is withdrawn
+    pub keeper_bounty: KeeperBounty,
+    /// If provided, anyone can trigger an arbitrage operation within these parameters
+    pub strategy: Option<Strategy>,
+    /// Accounts to receive portions of the profit, their percentage shares, and the assets they
+    /// prefer to be paid in. The sum of the shares must be less or equal to one. Remaining profit
+    /// goes to the owner.
+    pub profit_shares: Vec<ProfitShare<T>>,
+    /// Portion of the profit to be retained in the reserve fund before distribution. The reserve
+    /// fund is used to cover future shortfalls. Must be less or equal to one.
+    pub reserve_ratio: Decimal,
+    /// Profit is no longer retained once the reserve fund reaches this amount of Luna
+    pub reserve_target: Uint128,
+    /// Whether profit is sent to recipients and owner, or credited to them to be claimed
+    pub payout_mode: PayoutMode,
+    /// Whether the reserve fund is used to prepay the debt of open positions after each withdrawal
+    /// of unbonded Luna
+    pub auto_prepay: bool,
+    /// Whether idle Luna, such as the reserve fund and unclaimed profit, can be deposited in Red
+    /// Bank to earn interest between arbs
+    pub park_idle_luna: bool,
+    /// If provided, new borrows are refused if they would push the coverage ratio, i.e. the Luna
+    /// value of pending unbonds, withdrawable Luna and the reserve fund over the debt, below it
+    pub coverage_floor: Option<Decimal>,
+    /// If provided, swapped bLuna is held and submitted for unbonding in batches, shortly before
+    /// bLuna Hub's next batch time
+    pub batch_unbonding: Option<BatchUnbonding>,
+    /// If provided, anyone can sell bLuna to the contract for Luna at these parameters
+    pub desk: Option<Desk>,
+    /// If provided, arbs can be executed with UST borrowed from Red Bank
+    pub ust_borrowing: Option<UstBorrowing<T>>,
+    /// If provided, arbs can be executed between bLuna and stLuna
+    pub cross_lsd: Option<CrossLsd<T>>,
 }
 
 impl From<Config<Addr>> for Config<String> {
     fn from(config: Config<Addr>) -> Self {
         Self {
             owner: config.owner.to_string(),
+            recovery: config.recovery.map(|recovery| Recovery {
+                address: recovery.address.to_string(),
+                inactivity_timeout: recovery.inactivity_timeout,
+            }),
             bluna_token: config.bluna_token.to_string(),
             bluna_pair: config.bluna_pair.to_string(),
             bluna_hub: config.bluna_hub.to_string(),
+            bluna_reward: config.bluna_reward.map(|bluna_reward| bluna_reward.to_string()),
             red_bank: config.red_bank.to_string(),
-            profit_shares: config
-                .profit_shares
-                .iter()
-                .map(|(acct, share)| (acct.to_string(), *share))
-                .collect(),
+            council: config.council.map(|council| council.to_string()),
+            guardian: config.guardian.map(|guardian| guardian.to_string()),
+            protocol_fee: config.protocol_fee.into(),
+            keeper_bounty: config.keeper_bounty,
+            strategy: config.strategy,
+            profit_shares: config.profit_shares.into_iter().map(Into::into).collect(),
+            reserve_ratio: config.reserve_ratio,
+            reserve_target: config.reserve_target,
+            payout_mode: config.payout_mode,
+            auto_prepay: config.auto_prepay,
+            park_idle_luna: config.park_idle_luna,
+            coverage_floor: config.coverage_floor,
+            batch_unbonding: config.batch_unbonding,
+            desk: config.desk,
+            ust_borrowing: config.ust_borrowing.map(Into::into),
+            cross_lsd: config.cross_lsd.map(Into::into),
         }
     }
 }
 
 impl Config<String> {
-    pub fn check(&self, api: &dyn Api) -> StdResult<Config<Addr>> {
+    pub fn check(&self, api: &dyn Api, querier: &QuerierWrapper) -> StdResult<Config<Addr>> {
         // 1. The sum of the shares must be equal or less than one
         let total_shares: Decimal = self
             .profit_shares
             .iter()
-            .fold(Decimal::zero(), |acc, (_, share)| acc + *share);
+            .fold(Decimal::zero(), |acc, profit_share| acc + profit_share.share);
         if total_shares > Decimal::one() {
             return Err(StdError::generic_err(
                 format!("total shares {} is greater than one", total_shares)
             ));
         }
 
-        // 2. All addresses must be valid
+        // 2. The reserve ratio must be equal or less than one
+        if self.reserve_ratio > Decimal::one() {
+            return Err(StdError::generic_err(
+                format!("reserve ratio {} is greater than one", self.reserve_ratio)
+            ));
+        }
+
+        // 3. The keeper bounty rate must be equal or less than one
+        if self.keeper_bounty.rate > Decimal::one() {
+            return Err(StdError::generic_err(
+                format!("keeper bounty rate {} is greater than one", self.keeper_bounty.rate)
+            ));
+        }
+
+        // 4. Contract recipients can't claim profit themselves, so they are only supported if
+        // profit is pushed to recipients
+        if self.payout_mode == PayoutMode::Pull {
+            if let Some(profit_share) = self
+                .profit_shares
+                .iter()
+                .find(|profit_share| matches!(profit_share.recipient, Recipient::Contract { .. }))
+            {
+                return Err(StdError::generic_err(format!(
+                    "contract recipient {} is not supported in pull payout mode",
+                    profit_share.recipient.address()
+                )));
+            }
+
+            // Profit is credited in Luna, so conversions are not supported either
+            if let Some(profit_share) =
+                self.profit_shares.iter().find(|profit_share| profit_share.conversion.is_some())
+            {
+                return Err(StdError::generic_err(format!(
+                    "conversion for recipient {} is not supported in pull payout mode",
+                    profit_share.recipient.address()
+                )));
+            }
+        }
+
+        // 5. The desk's discount must be less than one
+        if let Some(desk) = &self.desk {
+            if desk.discount >= Decimal::one() {
+                return Err(StdError::generic_err(
+                    format!("desk discount {} is not less than one", desk.discount)
+                ));
+            }
+        }
+
+        // 6. Rewards accrued on bLuna held for batch unbonding are claimed from bLuna reward
+        // contract, so it must be provided if batch unbonding is enabled
+        if self.batch_unbonding.is_some() && self.bluna_reward.is_none() {
+            return Err(StdError::generic_err("bluna reward is required for batch unbonding"));
+        }
+
+        // 7. All addresses must be valid, the protocol fee rate must be equal or less than one,
+        // conversions must have valid spreads and pairs, and contract recipients must have valid
+        // messages
         Ok(Config {
             owner: api.addr_validate(&self.owner)?,
+            recovery: self
+                .recovery
+                .as_ref()
+                .map(|recovery| -> StdResult<_> {
+                    Ok(Recovery {
+                        address: api.addr_validate(&recovery.address)?,
+                        inactivity_timeout: recovery.inactivity_timeout,
+                    })
+                })
+                .transpose()?,
             bluna_token: api.addr_validate(&self.bluna_token)?,
             bluna_pair: api.addr_validate(&self.bluna_pair)?,
             bluna_hub: api.addr_validate(&self.bluna_hub)?,
+            bluna_reward: self
+                .bluna_reward
+                .as_ref()
+                .map(|bluna_reward| api.addr_validate(bluna_reward))
+                .transpose()?,
             red_bank: api.addr_validate(&self.red_bank)?,
+            council: self.council.as_ref().map(|council| api.addr_validate(council)).transpose()?,
+            guardian: self
+                .guardian
+                .as_ref()
+                .map(|guardian| api.addr_validate(guardian))
+                .transpose()?,
+            protocol_fee: self.protocol_fee.check(api)?,
+            keeper_bounty: self.keeper_bounty.clone(),
+            strategy: self.strategy.clone(),
             profit_shares: self
                 .profit_shares
                 .iter()
-                .map(|(acct, share)| Ok((api.addr_validate(acct)?, *share)))
-                .collect::<StdResult<Vec<(Addr, Decimal)>>>()?,
+                .map(|profit_share| profit_share.check(api, querier))
+                .collect::<StdResult<Vec<ProfitShare<Addr>>>>()?,
+            reserve_ratio: self.reserve_ratio,
+            reserve_target: self.reserve_target,
+            payout_mode: self.payout_mode,
+            auto_prepay: self.auto_prepay,
+            park_idle_luna: self.park_idle_luna,
+            coverage_floor: self.coverage_floor,
+            batch_unbonding: self.batch_unbonding.clone(),
+            desk: self.desk.clone(),
+            ust_borrowing: self
+                .ust_borrowing
+                .as_ref()
+                .map(|ust_borrowing| ust_borrowing.check(api))
+                .transpose()?,
+            cross_lsd: self.cross_lsd.as_ref().map(|cross_lsd| cross_lsd.check(api)).transpose()?,
         })
     }
 }
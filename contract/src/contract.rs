@@ -1,15 +1,47 @@
 use std::cmp;
+use std::str::FromStr;
 
+use astroport::pair::MAX_ALLOWED_SLIPPAGE;
 use cosmwasm_std::{
-    entry_point, to_binary, Binary, CosmosMsg, Decimal, Deps, DepsMut, Env, MessageInfo, Reply,
-    Response, StdError, StdResult, SubMsg, SubMsgExecutionResponse, Uint128,
+    entry_point, from_binary, to_binary, Addr, Binary, Coin, ContractResult, CosmosMsg, Decimal,
+    Deps, DepsMut, Env, MessageInfo, Order, Reply, Response, StdError, StdResult, Storage, SubMsg,
+    SubMsgExecutionResponse, Uint128, WasmMsg,
 };
-use cw_asset::{Asset, AssetInfo};
+use cw20::Cw20ReceiveMsg;
+use cw_asset::{Asset, AssetInfo, AssetUnchecked};
+use cw_storage_plus::{Bound, Item, U64Key};
+use mars_core::address_provider::MarsContract;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
-use crate::adapters::{Hub, Pair, RedBank};
-use crate::config::Config;
-use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, StatusResponse};
-use crate::state::{CONFIG, MINIMUM_RECEIVE};
+use crate::adapters::{AddressProvider, Hub, Pair, RedBank, Reward, Router};
+use crate::config::{Config, Conversion, PayoutMode, Recipient, Strategy};
+use crate::msg::{
+    ClaimableResponse, CreditReportResponse, ExecuteMsg, FinalizableResponse, InstantiateMsg,
+    Maturity, MigrateMsg, QueryMsg, QuoteResponse, ReceiveMsg, ReserveResponse, ScheduleResponse,
+    StatusResponse, Tranche, UstRoute,
+};
+use crate::plan::{ArbPlan, PlanStatus};
+use crate::position::{Exit, Inventory, Position, Settlement};
+use crate::reserve::{ReserveChange, ReserveChangeKind};
+use crate::state::{
+    ARB_PLAN, BAD_DEBT, CARRIED_LOSS, CLAIMABLE, CLOSED_POSITIONS, CONFIG, DEPOSITED,
+    DESK_POSITION_IDS, FIRST_LOSS_CAPITAL, FIRST_LOSS_DRAWN, INVENTORY, KEEPER, LAST_TRIGGERED,
+    MINIMUM_RECEIVE, OWNER_LAST_ACTIVE, PAUSED, PENDING_BORROWED, PENDING_EXIT, PENDING_HOLD_RATIO,
+    PENDING_LEGS, PENDING_PAYOUTS, PENDING_SALE, PENDING_UST_BORROWED, PENDING_UST_REPAYMENT,
+    POSITIONS, POSITION_COUNT, PREPAID, RESERVE, RESERVE_CHANGE_COUNT, RESERVE_HISTORY,
+    ROLLOVER_PARAMS, UST_BAD_DEBT,
+};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+/// Number of iterations of binary search when sizing a triggered arb; each iteration narrows the
+/// range to half of its size, so the result is within 0.1% of strategy's maximum size
+const SIZING_ITERATIONS: u32 = 10;
+
+/// Number of seconds in a year, over which Red Bank's borrow rate applies
+const SECONDS_PER_YEAR: u64 = 31_536_000;
 
 //--------------------------------------------------------------------------------------------------
 // Instantiate
@@ -18,11 +50,25 @@ use crate::state::{CONFIG, MINIMUM_RECEIVE};
 #[entry_point]
 pub fn instantiate(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
-    CONFIG.save(deps.storage, &msg.check(deps.api)?)?;
+    CONFIG.save(deps.storage, &msg.check(deps.api, &deps.querier)?)?;
+    POSITION_COUNT.save(deps.storage, &0)?;
+    FIRST_LOSS_CAPITAL.save(deps.storage, &Uint128::zero())?;
+    FIRST_LOSS_DRAWN.save(deps.storage, &Uint128::zero())?;
+    BAD_DEBT.save(deps.storage, &Uint128::zero())?;
+    CARRIED_LOSS.save(deps.storage, &Uint128::zero())?;
+    RESERVE.save(deps.storage, &Uint128::zero())?;
+    RESERVE_CHANGE_COUNT.save(deps.storage, &0)?;
+    PREPAID.save(deps.storage, &Uint128::zero())?;
+    DEPOSITED.save(deps.storage, &Uint128::zero())?;
+    INVENTORY.save(deps.storage, &Inventory::default())?;
+    DESK_POSITION_IDS.save(deps.storage, &vec![])?;
+    UST_BAD_DEBT.save(deps.storage, &Uint128::zero())?;
+    PAUSED.save(deps.storage, &false)?;
+    OWNER_LAST_ACTIVE.save(deps.storage, &env.block.time.seconds())?;
     Ok(Response::new())
 }
 
@@ -33,24 +79,90 @@ pub fn instantiate(
 #[entry_point]
 pub fn execute(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> StdResult<Response> {
+    // Track the owner's activity, so that the recovery address can take over if the owner has been
+    // inactive for too long
+    if info.sender == CONFIG.load(deps.storage)?.owner {
+        OWNER_LAST_ACTIVE.save(deps.storage, &env.block.time.seconds())?;
+    }
+
     match msg {
         ExecuteMsg::ExecuteArb {
             amount,
             minimum_profit,
-        } => execute_execute_arb(deps, info, amount, minimum_profit),
-        ExecuteMsg::FinializeArb {} => execute_finalize_arb(deps),
+            hold_ratio,
+        } => execute_execute_arb(deps, env, info, amount, minimum_profit, hold_ratio),
+        ExecuteMsg::ExecuteUstArb {
+            amount,
+            minimum_profit,
+            route,
+        } => execute_execute_ust_arb(deps, env, info, amount, minimum_profit, route),
+        ExecuteMsg::CrossLsdArb {
+            amount,
+            minimum_profit,
+        } => execute_cross_lsd_arb(deps, env, info, amount, minimum_profit),
+        ExecuteMsg::TriggerArb {} => execute_trigger_arb(deps, env, info),
+        ExecuteMsg::ScheduleArb {
+            total_amount,
+            tranche_size,
+            min_profit,
+            interval_blocks,
+        } => execute_schedule_arb(
+            deps,
+            env,
+            info,
+            total_amount,
+            tranche_size,
+            min_profit,
+            interval_blocks,
+        ),
+        ExecuteMsg::ExecuteTranche {} => execute_execute_tranche(deps, env),
+        ExecuteMsg::CancelArbPlan {} => execute_cancel_arb_plan(deps, info),
+        ExecuteMsg::FinializeArb {} => execute_finalize_arb(deps, env, info),
+        ExecuteMsg::Rollover {
+            minimum_profit,
+            reinvest_ratio,
+        } => execute_rollover(deps, env, info, minimum_profit, reinvest_ratio),
+        ExecuteMsg::DepositFirstLoss {} => execute_deposit_first_loss(deps, env, info),
+        ExecuteMsg::UpdateConfig { config } => execute_update_config(deps, info, config),
+        ExecuteMsg::ClaimProfit {} => execute_claim_profit(deps, env, info),
+        ExecuteMsg::ForceUnwind { sell_bluna } => execute_force_unwind(deps, env, info, sell_bluna),
+        ExecuteMsg::Resume {} => execute_resume(deps, info),
+        ExecuteMsg::EmergencyExit { max_spread } => {
+            execute_emergency_exit(deps, env, info, max_spread)
+        },
+        ExecuteMsg::ClaimOwnership {} => execute_claim_ownership(deps, env, info),
+        ExecuteMsg::Prepay { amount } => execute_prepay(deps, env, info, amount),
+        ExecuteMsg::RepayUstBadDebt { amount } => {
+            execute_repay_ust_bad_debt(deps, env, info, amount)
+        },
+        ExecuteMsg::Park {} => execute_park(deps, env),
+        ExecuteMsg::SubmitUnbonds {} => execute_submit_unbonds(deps, env),
+        ExecuteMsg::ClaimRewards {} => execute_claim_rewards(deps),
+        ExecuteMsg::UnbondInventory { amount } => {
+            execute_unbond_inventory(deps, env, info, amount)
+        },
+        ExecuteMsg::SellInventory {
+            amount,
+            max_spread,
+        } => execute_sell_inventory(deps, env, info, amount, max_spread),
+        ExecuteMsg::Receive(cw20_msg) => execute_receive(deps, env, info, cw20_msg),
+        ExecuteMsg::Sweep { asset, recipient } => {
+            execute_sweep(deps, env, info, asset, recipient)
+        },
     }
 }
 
 fn execute_execute_arb(
-    deps: DepsMut,
+    mut deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     amount: Uint128,
     minimum_profit: Decimal,
+    hold_ratio: Option<Decimal>,
 ) -> StdResult<Response> {
     let config = CONFIG.load(deps.storage)?;
 
@@ -58,12 +170,29 @@ fn execute_execute_arb(
         return Err(StdError::generic_err("sender is not owner"));
     }
 
+    if PAUSED.load(deps.storage)? {
+        return Err(StdError::generic_err("contract is paused"));
+    }
+
+    if let Some(hold_ratio) = hold_ratio {
+        if hold_ratio >= Decimal::one() {
+            return Err(StdError::generic_err(
+                format!("hold ratio {} is not less than one", hold_ratio)
+            ));
+        }
+        PENDING_HOLD_RATIO.save(deps.storage, &hold_ratio)?;
+    }
+
     let asset_to_offer = Asset::native("uluna", amount);
 
     let minimum_receive = amount.checked_add(amount * minimum_profit)?;
+    check_coverage(deps.as_ref(), &env, &config, amount, minimum_receive)?;
     MINIMUM_RECEIVE.save(deps.storage, &minimum_receive)?;
+    PENDING_BORROWED.save(deps.storage, &amount)?;
 
     Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any
+        .add_messages(unpark(deps.branch(), &env, &config)?)
         // Borrow Luna of specified amount from Red Bank
         .add_message(RedBank(&config.red_bank).borrow_msg(&asset_to_offer)?)
         // Swap borrowed Luna for bLuna; handle the reply
@@ -75,134 +204,3232 @@ fn execute_execute_arb(
         .add_attribute("asset_offered", asset_to_offer.to_string()))
 }
 
-fn execute_finalize_arb(deps: DepsMut) -> StdResult<Response> {
+fn execute_execute_ust_arb(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    minimum_profit: Decimal,
+    route: UstRoute,
+) -> StdResult<Response> {
     let config = CONFIG.load(deps.storage)?;
 
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    if PAUSED.load(deps.storage)? {
+        return Err(StdError::generic_err("contract is paused"));
+    }
+
+    let ust_borrowing = config
+        .ust_borrowing
+        .clone()
+        .ok_or_else(|| StdError::generic_err("ust borrowing is disabled"))?;
+
+    // The UST debt is repaid with unbonded Luna, so its size bounds the exposure to Luna's price
+    let ust = AssetInfo::native("uusd");
+    let debt = RedBank(&config.red_bank)
+        .query_user_asset_debt(&deps.querier, &env.contract.address, &ust)?
+        .amount;
+    let debt_after = debt.checked_add(amount)?;
+    if debt_after > ust_borrowing.max_debt {
+        return Err(StdError::generic_err(
+            format!("ust debt {} would exceed max debt {}", debt_after, ust_borrowing.max_debt)
+        ));
+    }
+
+    // Profit is measured against the amount of Luna the UST would buy at Astroport
+    let router = Router(&ust_borrowing.router);
+    let luna_equivalent =
+        router.query_simulation(&deps.querier, amount, &[ust.clone(), AssetInfo::native("uluna")])?;
+    let minimum_receive = luna_equivalent.checked_add(luna_equivalent * minimum_profit)?;
+
+    // The UST borrowed counts towards the debt at its Luna equivalent
+    check_coverage(deps.as_ref(), &env, &config, luna_equivalent, minimum_receive)?;
+    MINIMUM_RECEIVE.save(deps.storage, &minimum_receive)?;
+    PENDING_UST_BORROWED.save(deps.storage, &amount)?;
+
+    let bluna = AssetInfo::cw20(config.bluna_token.clone());
+    let route = match route {
+        UstRoute::ViaLuna => vec![ust, AssetInfo::native("uluna"), bluna],
+        UstRoute::Direct => vec![ust, bluna],
+    };
+    let asset_to_offer = Asset::native("uusd", amount);
+
     Ok(Response::new()
-        // Withdraw unbonded Luna from bLuna Hub
+        // Borrow UST of specified amount from Red Bank
+        .add_message(RedBank(&config.red_bank).borrow_msg(&asset_to_offer)?)
+        // Swap borrowed UST for bLuna along the route; handle the reply
         .add_submessage(SubMsg::reply_on_success(
-            Hub(&config.bluna_hub).withdraw_msg()?,
-            2,
+            router.swap_msg(&asset_to_offer, &route, Some(ust_borrowing.max_spread))?,
+            8,
         ))
-        .add_attribute("action", "bluna_lev_arb/execute/finalize_arb"))
+        .add_attribute("action", "bluna_lev_arb/execute/execute_ust_arb")
+        .add_attribute("asset_offered", asset_to_offer.to_string()))
 }
 
-//--------------------------------------------------------------------------------------------------
-// Reply
-//--------------------------------------------------------------------------------------------------
+fn execute_cross_lsd_arb(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    minimum_profit: Decimal,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
 
-#[entry_point]
-pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> StdResult<Response> {
-    match reply.id {
-        1 => after_swap(deps, unwrap_reply(reply)?),
-        2 => after_withdrawal(deps, env, unwrap_reply(reply)?),
-        id => Err(StdError::generic_err(format!("invalid reply id: {}", id))),
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
     }
+
+    if PAUSED.load(deps.storage)? {
+        return Err(StdError::generic_err("contract is paused"));
+    }
+
+    let cross_lsd = config
+        .cross_lsd
+        .clone()
+        .ok_or_else(|| StdError::generic_err("cross-lsd arb is disabled"))?;
+
+    // Buy whichever LSD is cheaper, i.e. take the direction around the triangle that returns more
+    // Luna at current prices
+    let legs_via_bluna = [
+        (config.bluna_pair.clone(), AssetInfo::cw20(config.bluna_token.clone())),
+        (cross_lsd.lsd_pair.clone(), AssetInfo::cw20(cross_lsd.stluna_token.clone())),
+        (cross_lsd.stluna_pair.clone(), AssetInfo::native("uluna")),
+    ];
+    let legs_via_stluna = [
+        (cross_lsd.stluna_pair.clone(), AssetInfo::cw20(cross_lsd.stluna_token.clone())),
+        (cross_lsd.lsd_pair.clone(), AssetInfo::cw20(config.bluna_token.clone())),
+        (config.bluna_pair.clone(), AssetInfo::native("uluna")),
+    ];
+    let asset_to_offer = Asset::native("uluna", amount);
+    let return_via_bluna = simulate_legs(deps.as_ref(), &asset_to_offer, &legs_via_bluna)?;
+    let return_via_stluna = simulate_legs(deps.as_ref(), &asset_to_offer, &legs_via_stluna)?;
+    let legs = if return_via_bluna >= return_via_stluna {
+        legs_via_bluna
+    } else {
+        legs_via_stluna
+    };
+
+    // The first leg is executed now; the rest are executed as each swap returns
+    let pairs: Vec<Addr> = legs.iter().map(|(pair, _)| pair.clone()).collect();
+    let first_pair = pairs[0].clone();
+    PENDING_LEGS.save(deps.storage, &pairs)?;
+
+    let minimum_receive = amount.checked_add(amount * minimum_profit)?;
+    MINIMUM_RECEIVE.save(deps.storage, &minimum_receive)?;
+    PENDING_BORROWED.save(deps.storage, &amount)?;
+
+    Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any
+        .add_messages(unpark(deps.branch(), &env, &config)?)
+        // Borrow Luna of specified amount from Red Bank
+        .add_message(RedBank(&config.red_bank).borrow_msg(&asset_to_offer)?)
+        // Swap borrowed Luna for the cheaper LSD; handle the reply
+        .add_submessage(SubMsg::reply_on_success(
+            Pair(&first_pair).swap_msg(&asset_to_offer)?,
+            10,
+        ))
+        .add_attribute("action", "bluna_lev_arb/execute/cross_lsd_arb")
+        .add_attribute("asset_offered", asset_to_offer.to_string())
+        .add_attribute("first_pair", first_pair.to_string()))
+}
+
+/// Simulate swapping the specified asset through the specified pairs in order, each returning the
+/// specified asset. Returns the amount returned by the last pair.
+fn simulate_legs(deps: Deps, asset: &Asset, legs: &[(Addr, AssetInfo)]) -> StdResult<Uint128> {
+    let mut asset = asset.clone();
+    for (pair, info) in legs {
+        let return_amount = Pair(pair).query_simulation(&deps.querier, &asset)?.return_amount;
+        asset = Asset::new(info.clone(), return_amount);
+    }
+    Ok(asset.amount)
 }
 
-fn after_swap(deps: DepsMut, response: SubMsgExecutionResponse) -> StdResult<Response> {
+fn execute_trigger_arb(mut deps: DepsMut, env: Env, info: MessageInfo) -> StdResult<Response> {
     let config = CONFIG.load(deps.storage)?;
 
-    let asset_received = Pair(&config.bluna_pair).parse_swap_events(deps.api, &response.events)?;
+    let strategy = config
+        .strategy
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("strategy is not set"))?;
 
-    let minimum_receive = MINIMUM_RECEIVE.load(deps.storage)?;
-    MINIMUM_RECEIVE.remove(deps.storage);
+    if PAUSED.load(deps.storage)? {
+        return Err(StdError::generic_err("contract is paused"));
+    }
 
-    if asset_received.amount < minimum_receive {
+    let now = env.block.time.seconds();
+    if let Some(last_triggered) = LAST_TRIGGERED.may_load(deps.storage)? {
+        let cooldown_end = last_triggered + strategy.cooldown;
+        if now < cooldown_end {
+            return Err(StdError::generic_err(format!("cooldown ends at {}", cooldown_end)));
+        }
+    }
+    LAST_TRIGGERED.save(deps.storage, &now)?;
+
+    let amount = size_arb(deps.as_ref(), &config, strategy)?;
+    if amount.is_zero() {
         return Err(StdError::generic_err(
-            format!("too little received from swap: {}, expecting at least {}", asset_received, minimum_receive)
+            format!("no arb makes minimum profit {}", strategy.minimum_profit)
         ));
     }
 
-    Ok(Response::new()
-        .add_message(Hub(&config.bluna_hub).unbond_msg(&asset_received)?)
-        .add_attribute("action", "bluna_lev_arb/reply/after_swap")
-        .add_attribute("asset_received", asset_received.to_string()))
+    let asset_to_offer = Asset::native("uluna", amount);
+
+    let minimum_receive = amount.checked_add(amount * strategy.minimum_profit)?;
+    check_coverage(deps.as_ref(), &env, &config, amount, minimum_receive)?;
+    MINIMUM_RECEIVE.save(deps.storage, &minimum_receive)?;
+    PENDING_BORROWED.save(deps.storage, &amount)?;
+
+    // The bounty is paid from the reserve fund, as far as the reserve allows
+    let amount_bounty = cmp::min(strategy.bounty, RESERVE.load(deps.storage)?);
+    let asset_bounty = Asset::native("uluna", amount_bounty);
+    draw_from_reserve(deps.storage, &env, ReserveChangeKind::BountyPaid, asset_bounty.amount)?;
+
+    let mut res = Response::new()
+        // Withdraw idle Luna from Red Bank, if any
+        .add_messages(unpark(deps.branch(), &env, &config)?)
+        // Borrow Luna of the sized amount from Red Bank
+        .add_message(RedBank(&config.red_bank).borrow_msg(&asset_to_offer)?)
+        // Swap borrowed Luna for bLuna; handle the reply
+        .add_submessage(SubMsg::reply_on_success(
+            Pair(&config.bluna_pair).swap_msg(&asset_to_offer)?,
+            1,
+        ));
+    if !asset_bounty.amount.is_zero() {
+        res = res.add_message(asset_bounty.transfer_msg(&info.sender)?);
+    }
+
+    Ok(res
+        .add_attribute("action", "bluna_lev_arb/execute/trigger_arb")
+        .add_attribute("asset_offered", asset_to_offer.to_string())
+        .add_attribute("bounty", asset_bounty.to_string()))
 }
 
-fn after_withdrawal(
+fn execute_schedule_arb(
     deps: DepsMut,
     env: Env,
-    response: SubMsgExecutionResponse,
+    info: MessageInfo,
+    total_amount: Uint128,
+    tranche_size: Uint128,
+    min_profit: Decimal,
+    interval_blocks: u64,
 ) -> StdResult<Response> {
     let config = CONFIG.load(deps.storage)?;
 
-    // Find how much unbonded Luna was received
-    let asset_received = Hub(&config.bluna_hub).parse_withdraw_events(&response.events)?;
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
 
-    // Query current debt amount and build a message to repay debt
-    let debt_info = RedBank(&config.red_bank).query_user_asset_debt(
-        &deps.querier,
-        &env.contract.address,
-        &AssetInfo::native("uluna"),
-    )?;
-    let asset_to_repay = Asset::native("uluna", cmp::min(asset_received.amount, debt_info.amount));
+    if total_amount.is_zero() || tranche_size.is_zero() {
+        return Err(StdError::generic_err("total amount and tranche size must be non-zero"));
+    }
 
-    // For the remaining assets, we first build messages to send shares to recipients
-    // NOTE: Unlike CW20 transfer, `BankMsg` allows zero amount, so we don't need to check whether
-    // the amount is zero.
-    let amount_available = asset_received.amount - asset_to_repay.amount;
-    let mut amount_shared = Uint128::zero();
-    let mut msgs: Vec<CosmosMsg> = vec![];
-    for (recipient, share) in &config.profit_shares {
-        let asset = Asset::native("uluna", amount_available * *share);
-        msgs.push(asset.transfer_msg(recipient)?);
-        amount_shared += asset.amount;
+    if min_profit.is_zero() || min_profit >= Decimal::one() {
+        return Err(StdError::generic_err(
+            format!("min profit {} must be greater than zero and less than one", min_profit)
+        ));
     }
 
-    // Lastly, send the remaining profit to owner
-    let profit = Asset::native("uluna", amount_available - amount_shared);
-    msgs.push(profit.transfer_msg(&config.owner)?);
+    let plan = ArbPlan {
+        total_amount,
+        tranche_size,
+        min_profit,
+        interval_blocks,
+        executed: Uint128::zero(),
+        tranches_executed: 0,
+        scheduled_at: env.block.height,
+        last_executed_at: None,
+        status: PlanStatus::Active,
+    };
+    ARB_PLAN.save(deps.storage, &plan)?;
 
     Ok(Response::new()
-        .add_message(RedBank(&config.red_bank).repay_msg(&asset_to_repay)?)
-        .add_messages(msgs)
-        .add_attribute("action", "bluna_lev_arb/reply/after_withdrawal")
-        .add_attribute("asset_received", asset_received.to_string())
-        .add_attribute("asset_repaid", asset_to_repay.to_string())
-        .add_attribute("profit", profit.to_string()))
+        .add_attribute("action", "bluna_lev_arb/execute/schedule_arb")
+        .add_attribute("total_amount", Asset::native("uluna", total_amount).to_string())
+        .add_attribute("tranche_size", Asset::native("uluna", tranche_size).to_string()))
 }
 
-fn unwrap_reply(reply: Reply) -> StdResult<SubMsgExecutionResponse> {
-    reply.result.into_result().map_err(StdError::generic_err)
+fn execute_execute_tranche(mut deps: DepsMut, env: Env) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let mut plan = ARB_PLAN
+        .may_load(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("no arb plan is scheduled"))?;
+    if plan.status != PlanStatus::Active {
+        return Err(StdError::generic_err("arb plan is not active"));
+    }
+
+    if PAUSED.load(deps.storage)? {
+        return Err(StdError::generic_err("contract is paused"));
+    }
+
+    let height = env.block.height;
+    if let Some(last_executed_at) = plan.last_executed_at {
+        let next_height = last_executed_at + plan.interval_blocks;
+        if height < next_height {
+            return Err(StdError::generic_err(
+                format!("next tranche can be executed at height {}", next_height)
+            ));
+        }
+    }
+
+    let amount = cmp::min(plan.tranche_size, plan.total_amount - plan.executed);
+    let asset_to_offer = Asset::native("uluna", amount);
+    let minimum_receive = amount.checked_add(amount * plan.min_profit)?;
+
+    // If the discount has closed, end the plan rather than executing a tranche that would fail
+    let return_amount = Pair(&config.bluna_pair)
+        .query_simulation(&deps.querier, &asset_to_offer)?
+        .return_amount;
+    if return_amount < minimum_receive {
+        plan.status = PlanStatus::DiscountClosed;
+        ARB_PLAN.save(deps.storage, &plan)?;
+
+        return Ok(Response::new()
+            .add_attribute("action", "bluna_lev_arb/execute/execute_tranche")
+            .add_attribute("status", "discount_closed"));
+    }
+
+    check_coverage(deps.as_ref(), &env, &config, amount, minimum_receive)?;
+    MINIMUM_RECEIVE.save(deps.storage, &minimum_receive)?;
+    PENDING_BORROWED.save(deps.storage, &amount)?;
+
+    plan.executed += amount;
+    plan.tranches_executed += 1;
+    plan.last_executed_at = Some(height);
+    if plan.executed == plan.total_amount {
+        plan.status = PlanStatus::Finished;
+    }
+    ARB_PLAN.save(deps.storage, &plan)?;
+
+    Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any
+        .add_messages(unpark(deps.branch(), &env, &config)?)
+        // Borrow Luna of the tranche's amount from Red Bank
+        .add_message(RedBank(&config.red_bank).borrow_msg(&asset_to_offer)?)
+        // Swap borrowed Luna for bLuna; handle the reply
+        .add_submessage(SubMsg::reply_on_success(
+            Pair(&config.bluna_pair).swap_msg(&asset_to_offer)?,
+            1,
+        ))
+        .add_attribute("action", "bluna_lev_arb/execute/execute_tranche")
+        .add_attribute("asset_offered", asset_to_offer.to_string())
+        .add_attribute("executed", Asset::native("uluna", plan.executed).to_string())
+        .add_attribute("tranches_executed", plan.tranches_executed.to_string()))
 }
 
-//--------------------------------------------------------------------------------------------------
-// Query
-//--------------------------------------------------------------------------------------------------
+fn execute_cancel_arb_plan(deps: DepsMut, info: MessageInfo) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
 
-#[entry_point]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::Config {} => to_binary(&query_config(deps)?),
-        QueryMsg::Status {} => to_binary(&query_status(deps, env)?),
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
     }
+
+    let mut plan = ARB_PLAN
+        .may_load(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("no arb plan is scheduled"))?;
+    if plan.status != PlanStatus::Active {
+        return Err(StdError::generic_err("arb plan is not active"));
+    }
+
+    plan.status = PlanStatus::Cancelled;
+    ARB_PLAN.save(deps.storage, &plan)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "bluna_lev_arb/execute/cancel_arb_plan")
+        .add_attribute("executed", Asset::native("uluna", plan.executed).to_string()))
 }
 
-fn query_config(deps: Deps) -> StdResult<Config<String>> {
-    Ok(CONFIG.load(deps.storage)?.into())
+fn execute_finalize_arb(mut deps: DepsMut, env: Env, info: MessageInfo) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    KEEPER.save(deps.storage, &info.sender)?;
+
+    Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any, so that the reserve is available for covering
+        // shortfalls
+        .add_messages(unpark(deps.branch(), &env, &config)?)
+        // Withdraw unbonded Luna from bLuna Hub
+        .add_submessage(SubMsg::reply_on_success(
+            Hub(&config.bluna_hub).withdraw_msg()?,
+            2,
+        ))
+        .add_attribute("action", "bluna_lev_arb/execute/finalize_arb"))
 }
 
-fn query_status(deps: Deps, env: Env) -> StdResult<StatusResponse> {
+fn execute_rollover(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    minimum_profit: Decimal,
+    reinvest_ratio: Decimal,
+) -> StdResult<Response> {
     let config = CONFIG.load(deps.storage)?;
-    Ok(StatusResponse {
-        debt: RedBank(&config.red_bank).query_user_asset_debt(
-            &deps.querier,
-            &env.contract.address,
-            &cw_asset::AssetInfoBase::native("uluna"),
-        )?,
-        unbond_requests: Hub(&config.bluna_hub)
-            .query_unbond_requests(&deps.querier, &env.contract.address)?,
-        withdrawable_unbonded: Hub(&config.bluna_hub)
-            .query_withdrawable_unbonded(&deps.querier, &env.contract.address)?,
-    })
+
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    if PAUSED.load(deps.storage)? {
+        return Err(StdError::generic_err("contract is paused"));
+    }
+
+    if reinvest_ratio > Decimal::one() {
+        return Err(StdError::generic_err(
+            format!("reinvest ratio {} is greater than one", reinvest_ratio)
+        ));
+    }
+
+    ROLLOVER_PARAMS.save(deps.storage, &(minimum_profit, reinvest_ratio))?;
+
+    Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any
+        .add_messages(unpark(deps.branch(), &env, &config)?)
+        // Withdraw unbonded Luna from bLuna Hub; handle the reply
+        .add_submessage(SubMsg::reply_on_success(
+            Hub(&config.bluna_hub).withdraw_msg()?,
+            3,
+        ))
+        .add_attribute("action", "bluna_lev_arb/execute/rollover"))
 }
 
-//--------------------------------------------------------------------------------------------------
-// Migrate
-//--------------------------------------------------------------------------------------------------
+fn execute_deposit_first_loss(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
 
-#[entry_point]
-pub fn migrate(_deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
-    Ok(Response::new())
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    let amount = info
+        .funds
+        .iter()
+        .find(|coin| coin.denom == "uluna")
+        .map(|coin| coin.amount)
+        .unwrap_or_else(Uint128::zero);
+    if amount.is_zero() {
+        return Err(StdError::generic_err("no luna deposited"));
+    }
+
+    // The deposited Luna is first used to repay bad debt, if any; the rest is kept as first-loss
+    // capital for covering future shortfalls
+    let bad_debt = BAD_DEBT.load(deps.storage)?;
+    let asset_to_repay = Asset::native("uluna", cmp::min(amount, bad_debt));
+    BAD_DEBT.save(deps.storage, &(bad_debt - asset_to_repay.amount))?;
+    FIRST_LOSS_CAPITAL.update(deps.storage, |capital| -> StdResult<_> {
+        Ok(capital + amount - asset_to_repay.amount)
+    })?;
+    FIRST_LOSS_DRAWN.update(deps.storage, |drawn| -> StdResult<_> {
+        Ok(drawn + asset_to_repay.amount)
+    })?;
+
+    // Withdraw idle Luna from Red Bank, if any, as first-loss capital is not to be parked
+    let mut res = Response::new().add_messages(unpark(deps.branch(), &env, &config)?);
+    if !asset_to_repay.amount.is_zero() {
+        res = res.add_message(RedBank(&config.red_bank).repay_msg(&asset_to_repay)?);
+    }
+
+    Ok(res
+        .add_attribute("action", "bluna_lev_arb/execute/deposit_first_loss")
+        .add_attribute("asset_deposited", Asset::native("uluna", amount).to_string())
+        .add_attribute("asset_repaid", asset_to_repay.to_string()))
+}
+
+fn execute_update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    new_config: Config<String>,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    // Red Bank and council's recourse against the contract, and Mars protocol's fee, are fixed at
+    // instantiation
+    let new_config = new_config.check(deps.api, &deps.querier)?;
+    if new_config.red_bank != config.red_bank
+        || new_config.council != config.council
+        || new_config.protocol_fee.rate != config.protocol_fee.rate
+    {
+        return Err(StdError::generic_err(
+            "red bank, council and protocol fee rate cannot be changed"
+        ));
+    }
+
+    CONFIG.save(deps.storage, &new_config)?;
+
+    Ok(Response::new().add_attribute("action", "bluna_lev_arb/execute/update_config"))
+}
+
+fn execute_claim_profit(mut deps: DepsMut, env: Env, info: MessageInfo) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let amount = CLAIMABLE
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_else(Uint128::zero);
+    if amount.is_zero() {
+        return Err(StdError::generic_err("no profit to claim"));
+    }
+
+    CLAIMABLE.remove(deps.storage, &info.sender);
+
+    let asset_to_claim = Asset::native("uluna", amount);
+
+    Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any
+        .add_messages(unpark(deps.branch(), &env, &config)?)
+        .add_message(asset_to_claim.transfer_msg(&info.sender)?)
+        .add_attribute("action", "bluna_lev_arb/execute/claim_profit")
+        .add_attribute("asset_claimed", asset_to_claim.to_string()))
+}
+
+fn execute_force_unwind(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    sell_bluna: bool,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if !is_credit_provider(&config, &info.sender) {
+        return Err(StdError::generic_err("sender is not red bank or council"));
+    }
+
+    PAUSED.save(deps.storage, &true)?;
+
+    // Withdraw idle Luna from Red Bank, if any, so that the reserve is available for covering
+    // shortfalls
+    let mut res = Response::new().add_messages(unpark(deps.branch(), &env, &config)?);
+
+    // Withdraw unbonded Luna from bLuna Hub, if any; handle the reply. bLuna Hub rejects the
+    // withdrawal if there is nothing to withdraw, so we need to check first.
+    let hub = Hub(&config.bluna_hub);
+    let withdrawable =
+        hub.query_withdrawable_unbonded(&deps.querier, &env.contract.address)?.withdrawable;
+    if !withdrawable.is_zero() {
+        res = res.add_submessage(SubMsg::reply_on_success(hub.withdraw_msg()?, 2));
+    }
+
+    // Sell bLuna held by the contract for Luna, if requested; handle the reply
+    let mut asset_to_sell = Asset::cw20(config.bluna_token.clone(), Uint128::zero());
+    if sell_bluna {
+        asset_to_sell.amount =
+            asset_to_sell.info.query_balance(&deps.querier, &env.contract.address)?;
+        if !asset_to_sell.amount.is_zero() {
+            PENDING_EXIT.save(deps.storage, &asset_to_sell.amount)?;
+            res = res.add_submessage(SubMsg::reply_on_success(
+                Pair(&config.bluna_pair).swap_msg(&asset_to_sell)?,
+                5,
+            ));
+        }
+    }
+
+    Ok(res
+        .add_attribute("action", "bluna_lev_arb/execute/force_unwind")
+        .add_attribute("asset_withdrawable", Asset::native("uluna", withdrawable).to_string())
+        .add_attribute("asset_sold", asset_to_sell.to_string()))
+}
+
+fn execute_resume(deps: DepsMut, info: MessageInfo) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if !is_credit_provider(&config, &info.sender) {
+        return Err(StdError::generic_err("sender is not red bank or council"));
+    }
+
+    PAUSED.save(deps.storage, &false)?;
+
+    Ok(Response::new().add_attribute("action", "bluna_lev_arb/execute/resume"))
+}
+
+fn execute_emergency_exit(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    max_spread: Decimal,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner && config.guardian.as_ref() != Some(&info.sender) {
+        return Err(StdError::generic_err("sender is not owner or guardian"));
+    }
+
+    let max_allowed_spread = Decimal::from_str(MAX_ALLOWED_SLIPPAGE)?;
+    if max_spread > max_allowed_spread {
+        return Err(StdError::generic_err(
+            format!("max spread {} is greater than {}", max_spread, max_allowed_spread)
+        ));
+    }
+
+    let bluna = AssetInfo::cw20(config.bluna_token.clone());
+    let bluna_balance = bluna.query_balance(&deps.querier, &env.contract.address)?;
+    let asset_to_sell = Asset::new(bluna, bluna_balance);
+    if asset_to_sell.amount.is_zero() {
+        return Err(StdError::generic_err("no bluna to sell"));
+    }
+
+    PENDING_EXIT.save(deps.storage, &asset_to_sell.amount)?;
+
+    Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any, so that it is at hand during the exit
+        .add_messages(unpark(deps.branch(), &env, &config)?)
+        // Sell bLuna for Luna; handle the reply
+        .add_submessage(SubMsg::reply_on_success(
+            Pair(&config.bluna_pair).swap_msg_with_options(
+                &asset_to_sell,
+                Some(max_spread),
+                None,
+            )?,
+            6,
+        ))
+        .add_attribute("action", "bluna_lev_arb/execute/emergency_exit")
+        .add_attribute("asset_sold", asset_to_sell.to_string()))
+}
+
+fn execute_claim_ownership(deps: DepsMut, env: Env, info: MessageInfo) -> StdResult<Response> {
+    let mut config = CONFIG.load(deps.storage)?;
+
+    let recovery = config
+        .recovery
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("recovery address is not set"))?;
+
+    if info.sender != recovery.address {
+        return Err(StdError::generic_err("sender is not recovery address"));
+    }
+
+    let timeout_end = OWNER_LAST_ACTIVE.load(deps.storage)? + recovery.inactivity_timeout;
+    if env.block.time.seconds() < timeout_end {
+        return Err(StdError::generic_err(
+            format!("owner inactivity timeout ends at {}", timeout_end)
+        ));
+    }
+
+    // Redirect profit credited to the previous owner but not yet claimed
+    let previous_owner = config.owner;
+    if let Some(amount) = CLAIMABLE.may_load(deps.storage, &previous_owner)? {
+        CLAIMABLE.remove(deps.storage, &previous_owner);
+        CLAIMABLE.update(deps.storage, &info.sender, |claimable| -> StdResult<_> {
+            Ok(claimable.unwrap_or_else(Uint128::zero) + amount)
+        })?;
+    }
+
+    config.owner = info.sender;
+    CONFIG.save(deps.storage, &config)?;
+    OWNER_LAST_ACTIVE.save(deps.storage, &env.block.time.seconds())?;
+
+    Ok(Response::new()
+        .add_attribute("action", "bluna_lev_arb/execute/claim_ownership")
+        .add_attribute("previous_owner", previous_owner)
+        .add_attribute("new_owner", config.owner))
+}
+
+/// Returns `true` if the address is Red Bank or the council, who can force an unwind
+fn is_credit_provider(config: &Config<Addr>, addr: &Addr) -> bool {
+    addr == &config.red_bank || config.council.as_ref() == Some(addr)
+}
+
+fn execute_prepay(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("prepay amount is zero"));
+    }
+
+    // Withdraw idle Luna from Red Bank, if any, as the reserve may have been deposited there
+    let unpark_msgs = unpark(deps.branch(), &env, &config)?;
+
+    let reserve = RESERVE.load(deps.storage)?;
+    if amount > reserve {
+        return Err(StdError::generic_err(
+            format!("amount {} is greater than reserve {}", amount, reserve)
+        ));
+    }
+
+    let prepayable = prepayable_debt(deps.as_ref(), &env, &config, Uint128::zero())?;
+    if amount > prepayable {
+        return Err(StdError::generic_err(
+            format!("amount {} is greater than prepayable debt {}", amount, prepayable)
+        ));
+    }
+
+    let asset_to_prepay = Asset::native("uluna", amount);
+    record_prepayment(deps.storage, &env, asset_to_prepay.amount)?;
+
+    Ok(Response::new()
+        .add_messages(unpark_msgs)
+        .add_message(RedBank(&config.red_bank).repay_msg(&asset_to_prepay)?)
+        .add_attribute("action", "bluna_lev_arb/execute/prepay")
+        .add_attribute("asset_prepaid", asset_to_prepay.to_string()))
+}
+
+fn execute_repay_ust_bad_debt(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("repay amount is zero"));
+    }
+
+    if UST_BAD_DEBT.load(deps.storage)?.is_zero() {
+        return Err(StdError::generic_err("no ust bad debt to repay"));
+    }
+
+    // Withdraw idle Luna from Red Bank, if any, as the reserve may have been deposited there
+    let unpark_msgs = unpark(deps.branch(), &env, &config)?;
+
+    let reserve = RESERVE.load(deps.storage)?;
+    if amount > reserve {
+        return Err(StdError::generic_err(
+            format!("amount {} is greater than reserve {}", amount, reserve)
+        ));
+    }
+
+    draw_from_reserve(deps.storage, &env, ReserveChangeKind::UstBadDebtRepaid, amount)?;
+    let msgs = repay_ust_debt(deps.storage, &config, amount, Uint128::zero())?;
+
+    Ok(Response::new()
+        .add_messages(unpark_msgs)
+        // Swap Luna for UST; handle the reply
+        .add_submessages(msgs)
+        .add_attribute("action", "bluna_lev_arb/execute/repay_ust_bad_debt")
+        .add_attribute("asset_offered", Asset::native("uluna", amount).to_string()))
+}
+
+fn execute_park(deps: DepsMut, env: Env) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if !config.park_idle_luna {
+        return Err(StdError::generic_err("parking idle luna is disabled"));
+    }
+
+    // Luna set aside as first-loss capital, or credited to recipients but not yet claimed, stays
+    // in the contract
+    let luna = AssetInfo::native("uluna");
+    let balance = luna.query_balance(&deps.querier, &env.contract.address)?;
+    let amount_set_aside = FIRST_LOSS_CAPITAL.load(deps.storage)? + total_claimable(deps.storage)?;
+    let asset_to_park = Asset::new(luna, balance.saturating_sub(amount_set_aside));
+    if asset_to_park.amount.is_zero() {
+        return Err(StdError::generic_err("no idle luna to park"));
+    }
+
+    DEPOSITED.update(deps.storage, |deposited| -> StdResult<_> {
+        Ok(deposited + asset_to_park.amount)
+    })?;
+
+    Ok(Response::new()
+        .add_message(RedBank(&config.red_bank).deposit_msg(&asset_to_park)?)
+        .add_attribute("action", "bluna_lev_arb/execute/park")
+        .add_attribute("asset_parked", asset_to_park.to_string()))
+}
+
+fn execute_submit_unbonds(deps: DepsMut, env: Env) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let batch_unbonding = config
+        .batch_unbonding
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("batch unbonding is not enabled"))?;
+
+    // bLuna Hub submits the batch currently accepting requests once an epoch has passed since the
+    // last batch; held bLuna is submitted shortly before that
+    let hub = Hub(&config.bluna_hub);
+    let state = hub.query_state(&deps.querier)?;
+    let parameters = hub.query_parameters(&deps.querier)?;
+    let submit_from = (state.last_unbonded_time + parameters.epoch_period)
+        .saturating_sub(batch_unbonding.submit_window);
+    if env.block.time.seconds() < submit_from {
+        return Err(StdError::generic_err(format!("unbonds can be submitted from {}", submit_from)));
+    }
+
+    let held_positions: Vec<Position> = POSITIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, position)| position))
+        .collect::<StdResult<Vec<Position>>>()?
+        .into_iter()
+        .filter(|position| position.held)
+        .collect();
+    if held_positions.is_empty() {
+        return Err(StdError::generic_err("no bluna held for unbonding"));
+    }
+
+    let batch_id = hub.query_current_batch(&deps.querier)?.id;
+    let mut asset_to_unbond = Asset::cw20(config.bluna_token.clone(), Uint128::zero());
+    for mut position in held_positions {
+        asset_to_unbond.amount += position.unbonded;
+        position.held = false;
+        position.batch_id = batch_id;
+        POSITIONS.save(deps.storage, U64Key::new(position.id), &position)?;
+    }
+
+    // Claim the rewards accrued on the held bLuna before it stops earning rewards
+    let mut res = Response::new();
+    if let Some(bluna_reward) = &config.bluna_reward {
+        res = res.add_message(Reward(bluna_reward).claim_rewards_msg(&config.owner)?);
+    }
+
+    Ok(res
+        .add_message(hub.unbond_msg(&asset_to_unbond)?)
+        .add_attribute("action", "bluna_lev_arb/execute/submit_unbonds")
+        .add_attribute("asset_unbonded", asset_to_unbond.to_string())
+        .add_attribute("batch_id", batch_id.to_string()))
+}
+
+fn execute_claim_rewards(deps: DepsMut) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let bluna_reward = config
+        .bluna_reward
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("bluna reward contract is not set"))?;
+
+    Ok(Response::new()
+        .add_message(Reward(bluna_reward).claim_rewards_msg(&config.owner)?)
+        .add_attribute("action", "bluna_lev_arb/execute/claim_rewards"))
+}
+
+fn execute_unbond_inventory(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    let cost = take_from_inventory(deps.storage, amount)?;
+    let asset_to_unbond = Asset::cw20(config.bluna_token.clone(), amount);
+    let (id, msgs) =
+        open_position(deps, &env, &config, cost, Uint128::zero(), &asset_to_unbond)?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "bluna_lev_arb/execute/unbond_inventory")
+        .add_attribute("asset_unbonded", asset_to_unbond.to_string())
+        .add_attribute("position_id", id.to_string()))
+}
+
+fn execute_sell_inventory(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    max_spread: Decimal,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    let max_allowed_spread = Decimal::from_str(MAX_ALLOWED_SLIPPAGE)?;
+    if max_spread > max_allowed_spread {
+        return Err(StdError::generic_err(
+            format!("max spread {} is greater than {}", max_spread, max_allowed_spread)
+        ));
+    }
+
+    let cost = take_from_inventory(deps.storage, amount)?;
+    let asset_to_sell = Asset::cw20(config.bluna_token.clone(), amount);
+    PENDING_SALE.save(deps.storage, &(amount, cost))?;
+
+    Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any, as the settlement may draw on the reserve
+        .add_messages(unpark(deps.branch(), &env, &config)?)
+        // Sell bLuna for Luna; handle the reply
+        .add_submessage(SubMsg::reply_on_success(
+            Pair(&config.bluna_pair).swap_msg_with_options(
+                &asset_to_sell,
+                Some(max_spread),
+                None,
+            )?,
+            7,
+        ))
+        .add_attribute("action", "bluna_lev_arb/execute/sell_inventory")
+        .add_attribute("asset_sold", asset_to_sell.to_string()))
+}
+
+/// Take the specified amount of bLuna out of the inventory, along with its portion of the cost
+/// basis. Returns the cost basis taken.
+fn take_from_inventory(storage: &mut dyn Storage, amount: Uint128) -> StdResult<Uint128> {
+    let inventory = INVENTORY.load(storage)?;
+    if amount.is_zero() || amount > inventory.amount {
+        return Err(StdError::generic_err(
+            format!("amount {} is not between zero and inventory {}", amount, inventory.amount)
+        ));
+    }
+
+    let cost = inventory.cost_basis.multiply_ratio(amount, inventory.amount);
+    INVENTORY.save(
+        storage,
+        &Inventory {
+            amount: inventory.amount - amount,
+            cost_basis: inventory.cost_basis - cost,
+        },
+    )?;
+
+    Ok(cost)
+}
+
+fn execute_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    cw20_msg: Cw20ReceiveMsg,
+) -> StdResult<Response> {
+    let seller = deps.api.addr_validate(&cw20_msg.sender)?;
+    match from_binary(&cw20_msg.msg)? {
+        ReceiveMsg::Sell { minimum_receive } => {
+            execute_sell(deps, env, info, seller, cw20_msg.amount, minimum_receive)
+        },
+    }
+}
+
+fn execute_sell(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    seller: Addr,
+    amount: Uint128,
+    minimum_receive: Option<Uint128>,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.bluna_token {
+        return Err(StdError::generic_err("only bluna can be sold"));
+    }
+
+    if PAUSED.load(deps.storage)? {
+        return Err(StdError::generic_err("contract is paused"));
+    }
+
+    let quote = quote(deps.as_ref(), &config, amount)?;
+    let asset_to_pay = Asset::native("uluna", quote.luna_amount);
+    if asset_to_pay.amount.is_zero() {
+        return Err(StdError::generic_err("amount is too small to be filled"));
+    }
+    if let Some(minimum_receive) = minimum_receive {
+        if asset_to_pay.amount < minimum_receive {
+            return Err(StdError::generic_err(format!(
+                "too little to be paid: {}, expecting at least {}",
+                asset_to_pay, minimum_receive
+            )));
+        }
+    }
+
+    // The Luna paid is backed by the bLuna bought
+    check_coverage(deps.as_ref(), &env, &config, asset_to_pay.amount, amount)?;
+
+    let asset_bought = Asset::cw20(config.bluna_token.clone(), amount);
+    let unpark_msgs = unpark(deps.branch(), &env, &config)?;
+    let (id, msgs) = open_position(
+        deps.branch(),
+        &env,
+        &config,
+        asset_to_pay.amount,
+        Uint128::zero(),
+        &asset_bought,
+    )?;
+
+    // Keep track of the desk's positions, dropping those that have since been closed
+    let mut desk_position_ids: Vec<u64> = open_desk_positions(deps.storage)?
+        .into_iter()
+        .map(|position| position.id)
+        .collect();
+    desk_position_ids.push(id);
+    DESK_POSITION_IDS.save(deps.storage, &desk_position_ids)?;
+
+    Ok(Response::new()
+        // Withdraw idle Luna from Red Bank, if any
+        .add_messages(unpark_msgs)
+        // Borrow the Luna to pay from Red Bank, and pay the seller
+        .add_message(RedBank(&config.red_bank).borrow_msg(&asset_to_pay)?)
+        .add_message(asset_to_pay.transfer_msg(&seller)?)
+        // Unbond the bLuna bought, unless held for batch unbonding
+        .add_messages(msgs)
+        .add_attribute("action", "bluna_lev_arb/execute/sell")
+        .add_attribute("seller", seller)
+        .add_attribute("asset_bought", asset_bought.to_string())
+        .add_attribute("asset_paid", asset_to_pay.to_string())
+        .add_attribute("position_id", id.to_string()))
+}
+
+fn execute_sweep(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    asset: AssetUnchecked,
+    recipient: String,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(StdError::generic_err("sender is not owner"));
+    }
+
+    let asset_to_sweep = asset.check(deps.api, None)?;
+    let recipient_addr = deps.api.addr_validate(&recipient)?;
+
+    let red_bank = RedBank(&config.red_bank);
+    let luna = AssetInfo::native("uluna");
+    let ust = AssetInfo::native("uusd");
+    let bluna = AssetInfo::cw20(config.bluna_token.clone());
+
+    // maLuna received for parking idle Luna in Red Bank is set aside the same way as Luna
+    let parked = !DEPOSITED.load(deps.storage)?.is_zero();
+    if parked {
+        let ma_luna = red_bank.query_market(&deps.querier, &luna)?.ma_token_address;
+        if asset_to_sweep.info == AssetInfo::cw20(ma_luna) {
+            return Err(StdError::generic_err("cannot sweep maluna while luna is parked"));
+        }
+    }
+
+    // Withdraw idle Luna from Red Bank, if any, before sweeping Luna; the deposit counts towards
+    // the balance, as it is withdrawn before the Luna is sent
+    let mut unpark_msgs = vec![];
+    let mut amount_parked = Uint128::zero();
+    if asset_to_sweep.info == luna && parked {
+        amount_parked = red_bank.query_user_deposit(&deps.querier, &env.contract.address, &luna)?;
+        unpark_msgs = unpark(deps.branch(), &env, &config)?;
+    }
+
+    // Assets the contract owes or holds on behalf of others must stay in the contract: Luna needed
+    // to repay the debt owed to Red Bank, set aside in the reserve fund or first-loss capital, or
+    // credited to recipients but not yet claimed; bLuna held for batch unbonding or retained in the
+    // inventory, which backs debt; and UST needed to repay the UST debt owed to Red Bank
+    let amount_owed = if asset_to_sweep.info == luna {
+        let debt = red_bank.query_user_asset_debt(&deps.querier, &env.contract.address, &luna)?;
+        Some(
+            debt.amount
+                + RESERVE.load(deps.storage)?
+                + FIRST_LOSS_CAPITAL.load(deps.storage)?
+                + total_claimable(deps.storage)?,
+        )
+    } else if asset_to_sweep.info == bluna {
+        let held = POSITIONS
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, position)| position))
+            .collect::<StdResult<Vec<Position>>>()?
+            .iter()
+            .filter(|position| position.held)
+            .map(|position| position.unbonded)
+            .sum::<Uint128>();
+        Some(held + INVENTORY.load(deps.storage)?.amount)
+    } else if asset_to_sweep.info == ust {
+        Some(red_bank.query_user_asset_debt(&deps.querier, &env.contract.address, &ust)?.amount)
+    } else {
+        None
+    };
+
+    if let Some(amount_owed) = amount_owed {
+        let balance = asset_to_sweep.info.query_balance(&deps.querier, &env.contract.address)?
+            + amount_parked;
+        let sweepable = balance.saturating_sub(amount_owed);
+        if asset_to_sweep.amount > sweepable {
+            return Err(StdError::generic_err(format!(
+                "amount {} is greater than sweepable amount {}",
+                asset_to_sweep.amount, sweepable
+            )));
+        }
+    }
+
+    Ok(Response::new()
+        .add_messages(unpark_msgs)
+        .add_message(asset_to_sweep.transfer_msg(&recipient_addr)?)
+        .add_attribute("action", "bluna_lev_arb/execute/sweep")
+        .add_attribute("asset_swept", asset_to_sweep.to_string())
+        .add_attribute("recipient", recipient_addr))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Reply
+//--------------------------------------------------------------------------------------------------
+
+#[entry_point]
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> StdResult<Response> {
+    match reply.id {
+        1 => after_swap(deps, env, unwrap_reply(reply)?),
+        2 => after_withdrawal(deps, env, unwrap_reply(reply)?),
+        3 => after_rollover_withdrawal(deps, env, unwrap_reply(reply)?),
+        4 => after_payout(deps, reply.result),
+        5 => after_unwind_swap(deps, env, unwrap_reply(reply)?),
+        6 => after_exit_swap(deps, env, unwrap_reply(reply)?),
+        7 => after_sale_swap(deps, env, unwrap_reply(reply)?),
+        8 => after_ust_swap(deps, env, unwrap_reply(reply)?),
+        9 => after_ust_repayment_swap(deps, env, reply.result),
+        10 => after_leg_swap(deps, env, unwrap_reply(reply)?),
+        id => Err(StdError::generic_err(format!("invalid reply id: {}", id))),
+    }
+}
+
+fn after_swap(deps: DepsMut, env: Env, response: SubMsgExecutionResponse) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let asset_received = Pair(&config.bluna_pair).parse_swap_events(deps.api, &response.events)?;
+
+    let minimum_receive = MINIMUM_RECEIVE.load(deps.storage)?;
+    MINIMUM_RECEIVE.remove(deps.storage);
+
+    if asset_received.amount < minimum_receive {
+        return Err(StdError::generic_err(
+            format!("too little received from swap: {}, expecting at least {}", asset_received, minimum_receive)
+        ));
+    }
+
+    let borrowed = PENDING_BORROWED.load(deps.storage)?;
+    PENDING_BORROWED.remove(deps.storage);
+
+    // Retain the specified portion of the bLuna as inventory, along with the same portion of the
+    // debt as its cost basis
+    let hold_ratio = PENDING_HOLD_RATIO.may_load(deps.storage)?.unwrap_or_else(Decimal::zero);
+    PENDING_HOLD_RATIO.remove(deps.storage);
+
+    let asset_retained =
+        Asset::cw20(config.bluna_token.clone(), asset_received.amount * hold_ratio);
+    let mut cost_retained = Uint128::zero();
+    if !asset_retained.amount.is_zero() {
+        cost_retained = borrowed.multiply_ratio(asset_retained.amount, asset_received.amount);
+        INVENTORY.update(deps.storage, |inventory| -> StdResult<_> {
+            Ok(Inventory {
+                amount: inventory.amount + asset_retained.amount,
+                cost_basis: inventory.cost_basis + cost_retained,
+            })
+        })?;
+    }
+    let asset_to_unbond =
+        Asset::cw20(config.bluna_token.clone(), asset_received.amount - asset_retained.amount);
+
+    let (id, msgs) = open_position(
+        deps,
+        &env,
+        &config,
+        borrowed - cost_retained,
+        Uint128::zero(),
+        &asset_to_unbond,
+    )?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "bluna_lev_arb/reply/after_swap")
+        .add_attribute("asset_received", asset_received.to_string())
+        .add_attribute("asset_retained", asset_retained.to_string())
+        .add_attribute("position_id", id.to_string()))
+}
+
+fn after_ust_swap(
+    deps: DepsMut,
+    env: Env,
+    response: SubMsgExecutionResponse,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+    let ust_borrowing = config
+        .ust_borrowing
+        .clone()
+        .ok_or_else(|| StdError::generic_err("ust borrowing is disabled"))?;
+
+    let asset_received =
+        Router(&ust_borrowing.router).parse_swap_events(deps.api, &response.events)?;
+
+    let minimum_receive = MINIMUM_RECEIVE.load(deps.storage)?;
+    MINIMUM_RECEIVE.remove(deps.storage);
+
+    if asset_received.amount < minimum_receive {
+        return Err(StdError::generic_err(
+            format!("too little received from swap: {}, expecting at least {}", asset_received, minimum_receive)
+        ));
+    }
+
+    let ust_borrowed = PENDING_UST_BORROWED.load(deps.storage)?;
+    PENDING_UST_BORROWED.remove(deps.storage);
+
+    let (id, msgs) =
+        open_position(deps, &env, &config, Uint128::zero(), ust_borrowed, &asset_received)?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "bluna_lev_arb/reply/after_ust_swap")
+        .add_attribute("asset_received", asset_received.to_string())
+        .add_attribute("position_id", id.to_string()))
+}
+
+fn after_withdrawal(
+    mut deps: DepsMut,
+    env: Env,
+    response: SubMsgExecutionResponse,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    // Find how much unbonded Luna was received
+    let asset_received = Hub(&config.bluna_hub).parse_withdraw_events(&response.events)?;
+
+    // Settle the positions that have finished unbonding
+    let (settlement, positions, ust) =
+        settle_positions(deps.branch(), &env, &config, asset_received.amount)?;
+
+    let res = Response::new()
+        .add_attribute("action", "bluna_lev_arb/reply/after_withdrawal")
+        .add_attribute("asset_received", asset_received.to_string());
+    realise_settlement(deps, &env, &config, &settlement, &positions, &ust, res)
+}
+
+/// Apply the outcome of settling positions: reimburse the reserve fund, cover shortfalls, recover
+/// carried losses, charge protocol fee, pay the keeper bounty, retain and distribute the profit,
+/// and repay the debt, swapping Luna for UST to repay the UST debt of the positions backed by it.
+/// Adds the resulting messages and attributes to the specified response.
+fn realise_settlement(
+    mut deps: DepsMut,
+    env: &Env,
+    config: &Config<Addr>,
+    settlement: &Settlement,
+    positions: &[Position],
+    ust: &(Settlement, Uint128),
+    mut res: Response,
+) -> StdResult<Response> {
+    // Reimburse the reserve fund for prepaid debt, and cover the shortfall if possible
+    let amount_unreimbursed = reimburse_prepayment(deps.storage, env, settlement)?;
+    let (luna_covered, luna_loss_uncovered) =
+        cover_shortfall(deps.storage, env, settlement.shortfall - ust.0.shortfall)?;
+    let (ust_covered, ust_loss_uncovered, ust_msgs) =
+        settle_ust_debt(deps.storage, env, config, ust)?;
+    let amount_covered = luna_covered + ust_covered;
+
+    // Recover losses carried forward before anything else
+    let (amount_recovered, bad_debt_repaid) = carry_forward_loss(
+        deps.storage,
+        env,
+        luna_loss_uncovered + ust_loss_uncovered + amount_unreimbursed,
+        settlement.profit,
+    )?;
+
+    // During a forced unwind, the profit goes towards repaying the remaining debt first
+    let amount_unwound = if PAUSED.load(deps.storage)? {
+        repay_with_profit(
+            deps.branch(),
+            env,
+            config,
+            settlement.profit - amount_recovered,
+            settlement.repaid + luna_covered + bad_debt_repaid,
+        )?
+    } else {
+        Uint128::zero()
+    };
+    let amount_realised = settlement.profit - amount_recovered - amount_unwound;
+
+    // Charge Mars protocol fee on the realised profit
+    let (fee_charged, fee_msg) = charge_protocol_fee(deps.as_ref(), config, amount_realised)?;
+
+    // Reward the caller of `FinalizeArb`, but only if there was actually something to withdraw
+    let keeper = KEEPER.may_load(deps.storage)?;
+    KEEPER.remove(deps.storage);
+    let asset_bounty = match &keeper {
+        Some(_) if !settlement.received.is_zero() => {
+            Asset::native("uluna", keeper_bounty(config, amount_realised - fee_charged))
+        },
+        _ => Asset::native("uluna", Uint128::zero()),
+    };
+    let amount_available = amount_realised - fee_charged - asset_bounty.amount;
+
+    // Retain a portion of the profit in the reserve; distribute the rest to recipients and owner
+    let amount_retained = retain_profit(deps.storage, env, config, amount_available)?;
+    let (msgs, profit) =
+        distribute_profit(deps.storage, config, positions, amount_available - amount_retained)?;
+
+    // If enabled, put the reserve fund to work by prepaying the debt of the remaining positions
+    let amount_being_repaid = settlement.repaid + luna_covered + bad_debt_repaid + amount_unwound;
+    let amount_prepaid = if config.auto_prepay {
+        let prepayable = prepayable_debt(deps.as_ref(), env, config, amount_being_repaid)?;
+        let amount_prepaid = cmp::min(prepayable, RESERVE.load(deps.storage)?);
+        record_prepayment(deps.storage, env, amount_prepaid)?;
+        amount_prepaid
+    } else {
+        Uint128::zero()
+    };
+
+    // Swap Luna for UST to repay UST debt; handle the reply
+    res = res.add_submessages(ust_msgs);
+
+    let asset_to_repay = Asset::native("uluna", amount_being_repaid + amount_prepaid);
+    if !asset_to_repay.amount.is_zero() {
+        res = res.add_message(RedBank(&config.red_bank).repay_msg(&asset_to_repay)?);
+    }
+    if let Some(fee_msg) = fee_msg {
+        res = res.add_message(fee_msg);
+    }
+    if let Some(keeper) = keeper.filter(|_| !asset_bounty.amount.is_zero()) {
+        res = res.add_message(asset_bounty.transfer_msg(&keeper)?);
+    }
+
+    Ok(res
+        .add_submessages(msgs)
+        .add_attribute("asset_repaid", asset_to_repay.to_string())
+        .add_attribute("shortfall", Asset::native("uluna", settlement.shortfall).to_string())
+        .add_attribute("shortfall_covered", Asset::native("uluna", amount_covered).to_string())
+        .add_attribute("loss_recovered", Asset::native("uluna", amount_recovered).to_string())
+        .add_attribute("profit_unwound", Asset::native("uluna", amount_unwound).to_string())
+        .add_attribute("protocol_fee", Asset::native("uluna", fee_charged).to_string())
+        .add_attribute("keeper_bounty", asset_bounty.to_string())
+        .add_attribute("profit_retained", Asset::native("uluna", amount_retained).to_string())
+        .add_attribute("debt_prepaid", Asset::native("uluna", amount_prepaid).to_string())
+        .add_attribute("profit", profit.to_string()))
+}
+
+fn after_rollover_withdrawal(
+    mut deps: DepsMut,
+    env: Env,
+    response: SubMsgExecutionResponse,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let (minimum_profit, reinvest_ratio) = ROLLOVER_PARAMS.load(deps.storage)?;
+    ROLLOVER_PARAMS.remove(deps.storage);
+
+    // Find how much unbonded Luna was received
+    let asset_received = Hub(&config.bluna_hub).parse_withdraw_events(&response.events)?;
+
+    // Settle the positions that have finished unbonding, and cover their shortfall if possible.
+    // The UST debt of positions backed by it is repaid rather than rolled over.
+    let (settlement, positions, ust) =
+        settle_positions(deps.branch(), &env, &config, asset_received.amount)?;
+    let amount_unreimbursed = reimburse_prepayment(deps.storage, &env, &settlement)?;
+    let (luna_covered, luna_loss_uncovered) =
+        cover_shortfall(deps.storage, &env, settlement.shortfall - ust.0.shortfall)?;
+    let (ust_covered, ust_loss_uncovered, ust_msgs) =
+        settle_ust_debt(deps.storage, &env, &config, &ust)?;
+    let amount_covered = luna_covered + ust_covered;
+
+    // Instead of repaying the debt and borrowing it again, the Luna that would have been used for
+    // repayment is rolled into the new swap, so the net repayment is zero
+    let amount_to_roll = settlement.repaid + luna_covered;
+
+    // Recover losses carried forward before anything else
+    let (amount_recovered, bad_debt_repaid) = carry_forward_loss(
+        deps.storage,
+        &env,
+        luna_loss_uncovered + ust_loss_uncovered + amount_unreimbursed,
+        settlement.profit,
+    )?;
+    let amount_realised = settlement.profit - amount_recovered;
+
+    // Charge Mars protocol fee on the realised profit
+    let (fee_charged, fee_msg) = charge_protocol_fee(deps.as_ref(), &config, amount_realised)?;
+    let amount_available = amount_realised - fee_charged;
+
+    // A portion of the profit is retained in the reserve, and a portion of the rest is reinvested;
+    // what remains is distributed to recipients and owner
+    let amount_retained = retain_profit(deps.storage, &env, &config, amount_available)?;
+    let amount_reinvested = (amount_available - amount_retained) * reinvest_ratio;
+    let (msgs, profit) = distribute_profit(
+        deps.storage,
+        &config,
+        &positions,
+        amount_available - amount_retained - amount_reinvested,
+    )?;
+
+    let asset_to_offer = Asset::native("uluna", amount_to_roll + amount_reinvested);
+    if asset_to_offer.amount.is_zero() {
+        return Err(StdError::generic_err("no unbonded luna to roll over"));
+    }
+
+    let minimum_receive =
+        asset_to_offer.amount.checked_add(asset_to_offer.amount * minimum_profit)?;
+    MINIMUM_RECEIVE.save(deps.storage, &minimum_receive)?;
+    PENDING_BORROWED.save(deps.storage, &amount_to_roll)?;
+
+    // Bad debt is not backed by any position, so it is repaid rather than rolled over
+    let mut res = Response::new();
+    if !bad_debt_repaid.is_zero() {
+        let asset_to_repay = Asset::native("uluna", bad_debt_repaid);
+        res = res.add_message(RedBank(&config.red_bank).repay_msg(&asset_to_repay)?);
+    }
+    if let Some(fee_msg) = fee_msg {
+        res = res.add_message(fee_msg);
+    }
+
+    Ok(res
+        // Swap rolled-over Luna for bLuna; handle the reply
+        .add_submessage(SubMsg::reply_on_success(
+            Pair(&config.bluna_pair).swap_msg(&asset_to_offer)?,
+            1,
+        ))
+        .add_submessages(ust_msgs)
+        .add_submessages(msgs)
+        .add_attribute("action", "bluna_lev_arb/reply/after_rollover_withdrawal")
+        .add_attribute("asset_received", asset_received.to_string())
+        .add_attribute("asset_reinvested", Asset::native("uluna", amount_reinvested).to_string())
+        .add_attribute("asset_offered", asset_to_offer.to_string())
+        .add_attribute("shortfall", Asset::native("uluna", settlement.shortfall).to_string())
+        .add_attribute("shortfall_covered", Asset::native("uluna", amount_covered).to_string())
+        .add_attribute("loss_recovered", Asset::native("uluna", amount_recovered).to_string())
+        .add_attribute("protocol_fee", Asset::native("uluna", fee_charged).to_string())
+        .add_attribute("profit_retained", Asset::native("uluna", amount_retained).to_string())
+        .add_attribute("profit", profit.to_string()))
+}
+
+/// Record a position backed by the specified amounts of Luna and UST debt, and unbond its bLuna.
+/// The bLuna is unbonded in the batch currently accepting requests, so we can later find out
+/// whether the position has finished unbonding by looking up the batch. If batch unbonding is
+/// enabled, the bLuna is instead held until `SubmitUnbonds` is called. Returns the position's id,
+/// and the message to unbond the bLuna, if any.
+fn open_position(
+    deps: DepsMut,
+    env: &Env,
+    config: &Config<Addr>,
+    borrowed: Uint128,
+    ust_borrowed: Uint128,
+    asset_to_unbond: &Asset,
+) -> StdResult<(u64, Vec<CosmosMsg>)> {
+    let hub = Hub(&config.bluna_hub);
+    let held = config.batch_unbonding.is_some();
+
+    let id = POSITION_COUNT.load(deps.storage)? + 1;
+    let position = Position {
+        id,
+        borrowed,
+        ust_borrowed,
+        unbonded: asset_to_unbond.amount,
+        batch_id: if held {
+            0
+        } else {
+            hub.query_current_batch(&deps.querier)?.id
+        },
+        held,
+        opened_at: env.block.time.seconds(),
+        profit_shares: config.profit_shares.clone(),
+        settlement: None,
+        exit: None,
+    };
+    POSITION_COUNT.save(deps.storage, &id)?;
+    POSITIONS.save(deps.storage, U64Key::new(id), &position)?;
+
+    if held {
+        Ok((id, vec![]))
+    } else {
+        Ok((id, vec![hub.unbond_msg(asset_to_unbond)?]))
+    }
+}
+
+/// Withdraw idle Luna deposited in Red Bank, if any, so that it is available to the operation being
+/// executed. The interest earned on the deposit is added to the reserve fund until the reserve
+/// reaches its target, counting Luna drawn to prepay debt the same way as when retaining profit;
+/// the rest stays in the contract as idle Luna, which the owner can sweep. Returns the message to
+/// withdraw the deposit, if there is one.
+fn unpark(deps: DepsMut, env: &Env, config: &Config<Addr>) -> StdResult<Vec<CosmosMsg>> {
+    let principal = DEPOSITED.load(deps.storage)?;
+    if principal.is_zero() {
+        return Ok(vec![]);
+    }
+
+    let red_bank = RedBank(&config.red_bank);
+    let luna = AssetInfo::native("uluna");
+    let deposited = red_bank.query_user_deposit(&deps.querier, &env.contract.address, &luna)?;
+    let interest = deposited.saturating_sub(principal);
+    let reserve = RESERVE.load(deps.storage)? + PREPAID.load(deps.storage)?;
+    let interest_retained = cmp::min(interest, config.reserve_target.saturating_sub(reserve));
+    add_to_reserve(deps.storage, env, ReserveChangeKind::InterestEarned, interest_retained)?;
+    DEPOSITED.save(deps.storage, &Uint128::zero())?;
+
+    Ok(vec![red_bank.withdraw_msg(&luna, None)?])
+}
+
+/// Total amount of Luna credited to recipients and owner but not yet claimed
+fn total_claimable(storage: &dyn Storage) -> StdResult<Uint128> {
+    CLAIMABLE
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, amount)| amount))
+        .sum()
+}
+
+/// During a forced unwind, use the specified profit to repay the debt that is not already being
+/// repaid, bad debt first. Returns the amount used.
+fn repay_with_profit(
+    deps: DepsMut,
+    env: &Env,
+    config: &Config<Addr>,
+    profit: Uint128,
+    amount_being_repaid: Uint128,
+) -> StdResult<Uint128> {
+    let debt = RedBank(&config.red_bank)
+        .query_user_asset_debt(&deps.querier, &env.contract.address, &AssetInfo::native("uluna"))?
+        .amount;
+    let amount_to_repay = cmp::min(profit, debt.saturating_sub(amount_being_repaid));
+
+    let bad_debt = BAD_DEBT.load(deps.storage)?;
+    BAD_DEBT.save(deps.storage, &(bad_debt - cmp::min(amount_to_repay, bad_debt)))?;
+
+    Ok(amount_to_repay)
+}
+
+/// Settle the positions that have finished unbonding, given the amount of unbonded Luna withdrawn
+/// from bLuna Hub. Returns the sum of the settlement outcomes, the settled positions, and the sum
+/// of the outcomes of the positions backed by UST debt along with the UST debt attributed to them.
+///
+/// Only the Luna received, the shortfall and the profit of positions backed by UST debt are
+/// included in the sum of all outcomes; their debt is repaid by swapping Luna for UST.
+fn settle_positions(
+    mut deps: DepsMut,
+    env: &Env,
+    config: &Config<Addr>,
+    amount_received: Uint128,
+) -> StdResult<(Settlement, Vec<Position>, (Settlement, Uint128))> {
+    let positions = POSITIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, position)| position))
+        .collect::<StdResult<Vec<Position>>>()?;
+
+    // A position has finished unbonding if its batch no longer shows up in the contract's unbonding
+    // requests, as bLuna Hub removes batches from the list once they are withdrawn. Positions whose
+    // bLuna is held have not started unbonding.
+    let unbond_requests = Hub(&config.bluna_hub)
+        .query_unbond_requests(&deps.querier, &env.contract.address)?
+        .requests;
+    let matured_positions: Vec<Position> = positions
+        .into_iter()
+        .filter(|position| {
+            !position.held
+                && !unbond_requests.iter().any(|(batch_id, _)| *batch_id == position.batch_id)
+        })
+        .collect();
+    if matured_positions.is_empty() {
+        return Err(StdError::generic_err("no position has finished unbonding"));
+    }
+
+    // The unbonded Luna is split between positions backed by Luna and UST debt by the amount of
+    // bLuna unbonded
+    let (ust_positions, luna_positions): (Vec<Position>, Vec<Position>) = matured_positions
+        .into_iter()
+        .partition(|position| !position.ust_borrowed.is_zero());
+    let luna_unbonded: Uint128 = luna_positions.iter().map(|position| position.unbonded).sum();
+    let ust_unbonded: Uint128 = ust_positions.iter().map(|position| position.unbonded).sum();
+    let ust_received = amount_received.multiply_ratio(ust_unbonded, luna_unbonded + ust_unbonded);
+
+    let (mut total, mut settled_positions) = if luna_positions.is_empty() {
+        (Settlement::default(), vec![])
+    } else {
+        settle(deps.branch(), env, config, luna_positions, amount_received - ust_received)?
+    };
+    total.settled_at = env.block.time.seconds();
+
+    let ust = if ust_positions.is_empty() {
+        no_ust_debt()
+    } else {
+        let (ust_total, ust_settled_positions, ust_debt) =
+            settle_ust(deps, env, config, ust_positions, ust_received)?;
+        total.received += ust_total.received;
+        total.shortfall += ust_total.shortfall;
+        total.profit += ust_total.profit;
+        settled_positions.extend(ust_settled_positions);
+        (ust_total, ust_debt)
+    };
+
+    Ok((total, settled_positions, ust))
+}
+
+/// Settle the specified open positions, given the amount of Luna received for their bLuna. Returns
+/// the sum of the settlement outcomes, and the settled positions.
+fn settle(
+    deps: DepsMut,
+    env: &Env,
+    config: &Config<Addr>,
+    matured_positions: Vec<Position>,
+    amount_received: Uint128,
+) -> StdResult<(Settlement, Vec<Position>)> {
+    // Debt that has gone bad is not backed by any position
+    let debt_info = RedBank(&config.red_bank).query_user_asset_debt(
+        &deps.querier,
+        &env.contract.address,
+        &AssetInfo::native("uluna"),
+    )?;
+    let debt_attributable = debt_info.amount.saturating_sub(BAD_DEBT.load(deps.storage)?);
+
+    // Debt is attributed among open positions and the inventory, by the amount borrowed. Positions
+    // backed by UST debt are settled separately.
+    let inventory = INVENTORY.load(deps.storage)?;
+    let mut total_borrowed = inventory.cost_basis;
+    let mut total_positions = 0;
+    for item in POSITIONS.range(deps.storage, None, None, Order::Ascending) {
+        let (_, position) = item?;
+        if position.ust_borrowed.is_zero() {
+            total_borrowed += position.borrowed;
+            total_positions += 1;
+        }
+    }
+    let total_unbonded: Uint128 = matured_positions.iter().map(|position| position.unbonded).sum();
+
+    let total_prepaid = PREPAID.load(deps.storage)?;
+    let settles_all = matured_positions.len() == total_positions && inventory.cost_basis.is_zero();
+
+    let mut total = Settlement {
+        settled_at: env.block.time.seconds(),
+        received: Uint128::zero(),
+        debt: Uint128::zero(),
+        repaid: Uint128::zero(),
+        shortfall: Uint128::zero(),
+        prepaid: Uint128::zero(),
+        reimbursed: Uint128::zero(),
+        profit: Uint128::zero(),
+    };
+    let mut settled_positions: Vec<Position> = vec![];
+    let last_index = matured_positions.len() - 1;
+    for (index, mut position) in matured_positions.into_iter().enumerate() {
+        // The unbonded Luna is allocated pro-rata by the amount of bLuna unbonded; the last
+        // position takes the remainder so that nothing is left unallocated due to rounding
+        let received = if index == last_index {
+            amount_received - total.received
+        } else {
+            amount_received.multiply_ratio(position.unbonded, total_unbonded)
+        };
+
+        // The debt, including accrued interest, is attributed pro-rata by the amount borrowed, and
+        // so is the prepaid debt. If all open positions are being settled, the last position takes
+        // the remainder of the prepaid debt.
+        let debt = if total_borrowed.is_zero() {
+            Uint128::zero()
+        } else {
+            debt_attributable.multiply_ratio(position.borrowed, total_borrowed)
+        };
+        let prepaid = if settles_all && index == last_index {
+            total_prepaid - total.prepaid
+        } else if total_borrowed.is_zero() {
+            Uint128::zero()
+        } else {
+            total_prepaid.multiply_ratio(position.borrowed, total_borrowed)
+        };
+
+        // The debt owed to Red Bank is repaid first, then the reserve fund is reimbursed
+        let repaid = cmp::min(received, debt);
+        let reimbursed = cmp::min(received - repaid, prepaid);
+        let settlement = Settlement {
+            settled_at: total.settled_at,
+            received,
+            debt: debt + prepaid,
+            repaid,
+            shortfall: debt - repaid,
+            prepaid,
+            reimbursed,
+            profit: received - repaid - reimbursed,
+        };
+
+        total.received += settlement.received;
+        total.debt += settlement.debt;
+        total.repaid += settlement.repaid;
+        total.shortfall += settlement.shortfall;
+        total.prepaid += settlement.prepaid;
+        total.reimbursed += settlement.reimbursed;
+        total.profit += settlement.profit;
+
+        position.settlement = Some(settlement);
+        POSITIONS.remove(deps.storage, U64Key::new(position.id));
+        CLOSED_POSITIONS.save(deps.storage, U64Key::new(position.id), &position)?;
+        settled_positions.push(position);
+    }
+
+    PREPAID.save(deps.storage, &(total_prepaid - total.prepaid))?;
+
+    Ok((total, settled_positions))
+}
+
+/// Settle the specified open positions backed by UST debt, given the amount of Luna received for
+/// their bLuna. The Luna needed to repay a position's share of the UST debt is priced by simulating
+/// a swap of all the Luna received through Astroport router, and the settlement is recorded in
+/// terms of Luna. Returns the sum of the settlement outcomes, the settled positions, and the UST
+/// debt attributed to them.
+fn settle_ust(
+    deps: DepsMut,
+    env: &Env,
+    config: &Config<Addr>,
+    matured_positions: Vec<Position>,
+    amount_received: Uint128,
+) -> StdResult<(Settlement, Vec<Position>, Uint128)> {
+    let ust_borrowing = config
+        .ust_borrowing
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("ust borrowing is disabled"))?;
+
+    // UST debt that has gone bad is not backed by any position
+    let debt_info = RedBank(&config.red_bank).query_user_asset_debt(
+        &deps.querier,
+        &env.contract.address,
+        &AssetInfo::native("uusd"),
+    )?;
+    let debt_attributable = debt_info.amount.saturating_sub(UST_BAD_DEBT.load(deps.storage)?);
+
+    // UST debt is attributed among open positions by the amount of UST borrowed
+    let mut total_ust_borrowed = Uint128::zero();
+    for item in POSITIONS.range(deps.storage, None, None, Order::Ascending) {
+        let (_, position) = item?;
+        total_ust_borrowed += position.ust_borrowed;
+    }
+    let total_unbonded: Uint128 = matured_positions.iter().map(|position| position.unbonded).sum();
+
+    // Swapping less than all the Luna received returns at least the same price, so the Luna
+    // equivalent of the debt is enough to repay it
+    let router = Router(&ust_borrowing.router);
+    let route = [AssetInfo::native("uluna"), AssetInfo::native("uusd")];
+    let ust_for_received = if amount_received.is_zero() {
+        Uint128::zero()
+    } else {
+        router.query_simulation(&deps.querier, amount_received, &route)?
+    };
+
+    let mut total = Settlement {
+        settled_at: env.block.time.seconds(),
+        ..Settlement::default()
+    };
+    let mut total_ust_debt = Uint128::zero();
+    let mut settled_positions: Vec<Position> = vec![];
+    let last_index = matured_positions.len() - 1;
+    for (index, mut position) in matured_positions.into_iter().enumerate() {
+        let received = if index == last_index {
+            amount_received - total.received
+        } else {
+            amount_received.multiply_ratio(position.unbonded, total_unbonded)
+        };
+
+        // If the Luna received is worth nothing, none of the UST debt can be repaid
+        let ust_debt = debt_attributable.multiply_ratio(position.ust_borrowed, total_ust_borrowed);
+        let debt = if ust_for_received.is_zero() {
+            Uint128::zero()
+        } else {
+            ust_debt.multiply_ratio(amount_received, ust_for_received)
+        };
+        let repaid = cmp::min(received, debt);
+        total_ust_debt += ust_debt;
+
+        let settlement = Settlement {
+            settled_at: total.settled_at,
+            received,
+            debt,
+            repaid,
+            shortfall: debt - repaid,
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: received - repaid,
+        };
+
+        total.received += settlement.received;
+        total.debt += settlement.debt;
+        total.repaid += settlement.repaid;
+        total.shortfall += settlement.shortfall;
+        total.profit += settlement.profit;
+
+        position.settlement = Some(settlement);
+        POSITIONS.remove(deps.storage, U64Key::new(position.id));
+        CLOSED_POSITIONS.save(deps.storage, U64Key::new(position.id), &position)?;
+        settled_positions.push(position);
+    }
+
+    Ok((total, settled_positions, total_ust_debt))
+}
+
+/// Swap the Luna repaid by settled positions backed by UST debt for UST to repay their debt. Their
+/// shortfall is covered the same way as other shortfalls, except that the Luna covered is swapped
+/// for UST too, and the UST debt left unpaid is recorded as UST bad debt. Returns the amount
+/// covered, the loss not absorbed by the reserve fund, and the submsg to swap the Luna.
+fn settle_ust_debt(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &Config<Addr>,
+    ust: &(Settlement, Uint128),
+) -> StdResult<(Uint128, Uint128, Vec<SubMsg>)> {
+    let (settlement, ust_debt) = ust;
+    let (amount_covered, loss_uncovered) = draw_for_shortfall(storage, env, settlement.shortfall)?;
+    let msgs = repay_ust_debt(storage, config, settlement.repaid + amount_covered, *ust_debt)?;
+    Ok((amount_covered, loss_uncovered, msgs))
+}
+
+/// Swap the specified amount of Luna for UST through Astroport router to repay UST debt, of which
+/// the specified amount is owed by positions being closed. The part of the latter that the swap
+/// does not repay is recorded as UST bad debt when handling the reply; if there is no Luna to swap,
+/// it is recorded right away. Returns the submsg to swap the Luna, if any.
+fn repay_ust_debt(
+    storage: &mut dyn Storage,
+    config: &Config<Addr>,
+    luna_amount: Uint128,
+    ust_debt: Uint128,
+) -> StdResult<Vec<SubMsg>> {
+    if luna_amount.is_zero() {
+        UST_BAD_DEBT.update(storage, |bad_debt| -> StdResult<_> { Ok(bad_debt + ust_debt) })?;
+        return Ok(vec![]);
+    }
+
+    let ust_borrowing = config
+        .ust_borrowing
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("ust borrowing is disabled"))?;
+
+    PENDING_UST_REPAYMENT.save(storage, &(luna_amount, ust_debt))?;
+
+    let asset_to_swap = Asset::native("uluna", luna_amount);
+    let route = [AssetInfo::native("uluna"), AssetInfo::native("uusd")];
+    Ok(vec![SubMsg::reply_always(
+        Router(&ust_borrowing.router).swap_msg(
+            &asset_to_swap,
+            &route,
+            Some(ust_borrowing.max_spread),
+        )?,
+        9,
+    )])
+}
+
+/// Add the prepaid debt reimbursed by the settled positions back to the reserve fund. Returns the
+/// amount of prepaid debt that could not be reimbursed, which is a loss to be carried forward.
+fn reimburse_prepayment(
+    storage: &mut dyn Storage,
+    env: &Env,
+    settlement: &Settlement,
+) -> StdResult<Uint128> {
+    add_to_reserve(storage, env, ReserveChangeKind::PrepaymentReimbursed, settlement.reimbursed)?;
+    Ok(settlement.prepaid - settlement.reimbursed)
+}
+
+/// Find the amount of debt that can be prepaid, i.e. debt attributable to open positions and the
+/// inventory, excluding the specified amount already being repaid. Bad debt is not backed by any
+/// position, so it is not prepaid.
+fn prepayable_debt(
+    deps: Deps,
+    env: &Env,
+    config: &Config<Addr>,
+    amount_being_repaid: Uint128,
+) -> StdResult<Uint128> {
+    if POSITIONS.range(deps.storage, None, None, Order::Ascending).next().is_none()
+        && INVENTORY.load(deps.storage)?.cost_basis.is_zero()
+    {
+        return Ok(Uint128::zero());
+    }
+
+    let debt = RedBank(&config.red_bank)
+        .query_user_asset_debt(&deps.querier, &env.contract.address, &AssetInfo::native("uluna"))?
+        .amount;
+    Ok(debt.saturating_sub(amount_being_repaid).saturating_sub(BAD_DEBT.load(deps.storage)?))
+}
+
+/// Draw the specified amount from the reserve fund to prepay debt, and record it so that it can be
+/// reimbursed as the open positions are settled
+fn record_prepayment(storage: &mut dyn Storage, env: &Env, amount: Uint128) -> StdResult<()> {
+    draw_from_reserve(storage, env, ReserveChangeKind::DebtPrepaid, amount)?;
+    PREPAID.update(storage, |prepaid| -> StdResult<_> { Ok(prepaid + amount) })?;
+    Ok(())
+}
+
+/// Cover the specified shortfall using the reserve fund first, then first-loss capital; whatever
+/// cannot be covered is recorded as bad debt. Returns the amount covered, and the loss not absorbed
+/// by the reserve fund, i.e. first-loss capital drawn and bad debt, which is to be carried forward.
+fn cover_shortfall(
+    storage: &mut dyn Storage,
+    env: &Env,
+    shortfall: Uint128,
+) -> StdResult<(Uint128, Uint128)> {
+    let (amount_covered, loss_uncovered) = draw_for_shortfall(storage, env, shortfall)?;
+    BAD_DEBT.update(storage, |bad_debt| -> StdResult<_> {
+        Ok(bad_debt + shortfall - amount_covered)
+    })?;
+
+    Ok((amount_covered, loss_uncovered))
+}
+
+/// Draw Luna to cover the specified shortfall from the reserve fund first, then first-loss capital.
+/// Returns the amount drawn, and the loss not absorbed by the reserve fund.
+fn draw_for_shortfall(
+    storage: &mut dyn Storage,
+    env: &Env,
+    shortfall: Uint128,
+) -> StdResult<(Uint128, Uint128)> {
+    let amount_from_reserve = cmp::min(shortfall, RESERVE.load(storage)?);
+    draw_from_reserve(storage, env, ReserveChangeKind::ShortfallCovered, amount_from_reserve)?;
+
+    let first_loss_capital = FIRST_LOSS_CAPITAL.load(storage)?;
+    let amount_from_first_loss = cmp::min(shortfall - amount_from_reserve, first_loss_capital);
+    FIRST_LOSS_CAPITAL.save(storage, &(first_loss_capital - amount_from_first_loss))?;
+    FIRST_LOSS_DRAWN.update(storage, |drawn| -> StdResult<_> {
+        Ok(drawn + amount_from_first_loss)
+    })?;
+
+    Ok((amount_from_reserve + amount_from_first_loss, shortfall - amount_from_reserve))
+}
+
+/// Carry the specified loss forward, and recover as much of the carried loss as possible from the
+/// specified profit. The recovered Luna is first used to repay bad debt, then to replenish the
+/// first-loss capital drawn; the rest is added to the reserve fund. Returns the amount recovered,
+/// and the portion of it used to repay bad debt.
+fn carry_forward_loss(
+    storage: &mut dyn Storage,
+    env: &Env,
+    loss: Uint128,
+    profit: Uint128,
+) -> StdResult<(Uint128, Uint128)> {
+    let carried_loss = CARRIED_LOSS.load(storage)? + loss;
+    let amount_recovered = cmp::min(profit, carried_loss);
+    CARRIED_LOSS.save(storage, &(carried_loss - amount_recovered))?;
+
+    let bad_debt = BAD_DEBT.load(storage)?;
+    let bad_debt_repaid = cmp::min(amount_recovered, bad_debt);
+    BAD_DEBT.save(storage, &(bad_debt - bad_debt_repaid))?;
+
+    let first_loss_drawn = FIRST_LOSS_DRAWN.load(storage)?;
+    let first_loss_replenished = cmp::min(amount_recovered - bad_debt_repaid, first_loss_drawn);
+    FIRST_LOSS_DRAWN.save(storage, &(first_loss_drawn - first_loss_replenished))?;
+    FIRST_LOSS_CAPITAL.update(storage, |capital| -> StdResult<_> {
+        Ok(capital + first_loss_replenished)
+    })?;
+
+    let amount_to_reserve = amount_recovered - bad_debt_repaid - first_loss_replenished;
+    add_to_reserve(storage, env, ReserveChangeKind::LossRecovered, amount_to_reserve)?;
+
+    Ok((amount_recovered, bad_debt_repaid))
+}
+
+/// Charge Mars protocol fee on the specified realised profit. Returns the amount charged, and the
+/// message to send it to Mars treasury if the amount is not zero.
+fn charge_protocol_fee(
+    deps: Deps,
+    config: &Config<Addr>,
+    profit: Uint128,
+) -> StdResult<(Uint128, Option<CosmosMsg>)> {
+    let asset_charged = Asset::native("uluna", profit * config.protocol_fee.rate);
+    if asset_charged.amount.is_zero() {
+        return Ok((asset_charged.amount, None));
+    }
+
+    let treasury = match &config.protocol_fee.treasury {
+        Some(treasury) => treasury.clone(),
+        None => {
+            let address_provider =
+                RedBank(&config.red_bank).query_config(&deps.querier)?.address_provider_address;
+            AddressProvider(&address_provider)
+                .query_address(&deps.querier, MarsContract::Treasury)?
+        },
+    };
+
+    Ok((asset_charged.amount, Some(asset_charged.transfer_msg(&treasury)?)))
+}
+
+/// Find the largest amount of Luna, up to the strategy's maximum size, that can be swapped for
+/// bLuna while making the strategy's minimum profit. As the price impact grows with the amount
+/// swapped, the profit shrinks as the amount grows, so we can use binary search.
+fn size_arb(deps: Deps, config: &Config<Addr>, strategy: &Strategy) -> StdResult<Uint128> {
+    let pair = Pair(&config.bluna_pair);
+    let is_profitable = |amount: Uint128| -> StdResult<bool> {
+        let asset = Asset::native("uluna", amount);
+        let return_amount = pair.query_simulation(&deps.querier, &asset)?.return_amount;
+        Ok(return_amount >= amount.checked_add(amount * strategy.minimum_profit)?)
+    };
+
+    if is_profitable(strategy.max_size)? {
+        return Ok(strategy.max_size);
+    }
+
+    let mut low = Uint128::zero();
+    let mut high = strategy.max_size;
+    for _ in 0..SIZING_ITERATIONS {
+        let mid = low.checked_add(high)?.multiply_ratio(1u128, 2u128);
+        if mid.is_zero() {
+            break;
+        }
+        if is_profitable(mid)? {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    Ok(low)
+}
+
+/// Calculate the keeper bounty on the specified profit, net of protocol fee
+fn keeper_bounty(config: &Config<Addr>, profit: Uint128) -> Uint128 {
+    cmp::min(profit * config.keeper_bounty.rate, config.keeper_bounty.cap)
+}
+
+/// Refuse to borrow the specified amount if that would push the coverage ratio below the floor set
+/// in config. Once swapped and unbonded, the borrowed Luna is backed by at least the specified
+/// minimum amount of bLuna to receive.
+fn check_coverage(
+    deps: Deps,
+    env: &Env,
+    config: &Config<Addr>,
+    amount: Uint128,
+    minimum_receive: Uint128,
+) -> StdResult<()> {
+    let floor = match config.coverage_floor {
+        Some(floor) => floor,
+        None => return Ok(()),
+    };
+
+    let report = credit_report(deps, env, config)?;
+    let exchange_rate = Hub(&config.bluna_hub).query_state(&deps.querier)?.exchange_rate;
+    let debt = (report.total_debt + report.ust_debt_value).checked_add(amount)?;
+    if debt.is_zero() {
+        return Ok(());
+    }
+
+    let covered = report.pending_unbond_value
+        + report.held_value
+        + report.withdrawable
+        + report.reserve
+        + minimum_receive * exchange_rate;
+    let coverage_ratio = Decimal::from_ratio(covered, debt);
+    if coverage_ratio < floor {
+        return Err(StdError::generic_err(
+            format!("coverage ratio {} would fall below floor {}", coverage_ratio, floor)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Retain a portion of the specified profit in the reserve fund, until the reserve reaches its
+/// target. Luna drawn from the reserve to prepay debt still counts towards the target, as it will
+/// be reimbursed. Returns the amount retained.
+fn retain_profit(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &Config<Addr>,
+    profit: Uint128,
+) -> StdResult<Uint128> {
+    let reserve = RESERVE.load(storage)? + PREPAID.load(storage)?;
+    let amount_to_target = config.reserve_target.saturating_sub(reserve);
+    let amount_retained = cmp::min(profit * config.reserve_ratio, amount_to_target);
+    add_to_reserve(storage, env, ReserveChangeKind::ProfitRetained, amount_retained)?;
+    Ok(amount_retained)
+}
+
+fn add_to_reserve(
+    storage: &mut dyn Storage,
+    env: &Env,
+    kind: ReserveChangeKind,
+    amount: Uint128,
+) -> StdResult<()> {
+    let balance = RESERVE.load(storage)?.checked_add(amount)?;
+    record_reserve_change(storage, env, kind, amount, balance)
+}
+
+fn draw_from_reserve(
+    storage: &mut dyn Storage,
+    env: &Env,
+    kind: ReserveChangeKind,
+    amount: Uint128,
+) -> StdResult<()> {
+    let balance = RESERVE.load(storage)?.checked_sub(amount)?;
+    record_reserve_change(storage, env, kind, amount, balance)
+}
+
+/// Update the reserve fund's balance, and record the change in its history. Changes of zero amount
+/// are ignored.
+fn record_reserve_change(
+    storage: &mut dyn Storage,
+    env: &Env,
+    kind: ReserveChangeKind,
+    amount: Uint128,
+    balance: Uint128,
+) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    let id = RESERVE_CHANGE_COUNT.load(storage)? + 1;
+    RESERVE_CHANGE_COUNT.save(storage, &id)?;
+    RESERVE.save(storage, &balance)?;
+    RESERVE_HISTORY.save(
+        storage,
+        U64Key::new(id),
+        &ReserveChange {
+            id,
+            timestamp: env.block.time.seconds(),
+            kind,
+            amount,
+            balance,
+        },
+    )
+}
+
+fn after_payout(
+    deps: DepsMut,
+    result: ContractResult<SubMsgExecutionResponse>,
+) -> StdResult<Response> {
+    // Payouts are executed in the order they were saved
+    let mut pending_payouts = PENDING_PAYOUTS.load(deps.storage)?;
+    if pending_payouts.is_empty() {
+        return Err(StdError::generic_err("no pending payout"));
+    }
+    let (recipient, pair, amount) = pending_payouts.remove(0);
+    if pending_payouts.is_empty() {
+        PENDING_PAYOUTS.remove(deps.storage);
+    } else {
+        PENDING_PAYOUTS.save(deps.storage, &pending_payouts)?;
+    }
+
+    let asset_paid = Asset::native("uluna", amount);
+    let res = Response::new()
+        .add_attribute("action", "bluna_lev_arb/reply/after_payout")
+        .add_attribute("recipient", &recipient)
+        .add_attribute("asset_paid", asset_paid.to_string());
+
+    match (result, pair) {
+        (ContractResult::Ok(response), Some(pair)) => {
+            let asset_converted = Pair(&pair).parse_swap_events(deps.api, &response.events)?;
+            Ok(res.add_attribute("asset_converted", asset_converted.to_string()))
+        },
+        (ContractResult::Ok(_), None) => Ok(res),
+        // The swap or the contract execution failed, e.g. due to slippage or the contract rejecting
+        // the message, and its state changes have been reverted, so the Luna is still with us.
+        // Send it to the recipient as is, so that the rest of the settlement doesn't fail with it.
+        (ContractResult::Err(err), _) => Ok(res
+            .add_message(asset_paid.transfer_msg(&recipient)?)
+            .add_attribute("payout_error", err)),
+    }
+}
+
+fn after_unwind_swap(
+    mut deps: DepsMut,
+    env: Env,
+    response: SubMsgExecutionResponse,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let amount_sold = PENDING_EXIT.load(deps.storage)?;
+    PENDING_EXIT.remove(deps.storage);
+
+    let asset_received = Pair(&config.bluna_pair).parse_swap_events(deps.api, &response.events)?;
+    let (_, held_total, ust) = close_held_positions(
+        deps.branch(),
+        &env,
+        &config,
+        amount_sold,
+        asset_received.amount,
+        None,
+    )?;
+    let (asset_to_repay, _, ust_msgs) = repay_with_proceeds(
+        deps.branch(),
+        &env,
+        &config,
+        asset_received.amount,
+        &held_total,
+        &ust,
+    )?;
+
+    let mut res = Response::new().add_submessages(ust_msgs);
+    if !asset_to_repay.amount.is_zero() {
+        res = res.add_message(RedBank(&config.red_bank).repay_msg(&asset_to_repay)?);
+    }
+
+    Ok(res
+        .add_attribute("action", "bluna_lev_arb/reply/after_unwind_swap")
+        .add_attribute("asset_received", asset_received.to_string())
+        .add_attribute("asset_repaid", asset_to_repay.to_string()))
+}
+
+fn after_exit_swap(
+    mut deps: DepsMut,
+    env: Env,
+    response: SubMsgExecutionResponse,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let amount_sold = PENDING_EXIT.load(deps.storage)?;
+    PENDING_EXIT.remove(deps.storage);
+
+    let asset_received = Pair(&config.bluna_pair).parse_swap_events(deps.api, &response.events)?;
+
+    // Compare the sale with unbonding the bLuna at bLuna Hub's current exchange rate
+    let exchange_rate = Hub(&config.bluna_hub).query_state(&deps.querier)?.exchange_rate;
+    let amount_expected = amount_sold * exchange_rate;
+    let amount_lost = amount_expected.saturating_sub(asset_received.amount);
+
+    let (held_sold, held_total, ust) = close_held_positions(
+        deps.branch(),
+        &env,
+        &config,
+        amount_sold,
+        asset_received.amount,
+        Some(exchange_rate),
+    )?;
+    let (asset_to_repay, held_covered, ust_msgs) = repay_with_proceeds(
+        deps.branch(),
+        &env,
+        &config,
+        asset_received.amount,
+        &held_total,
+        &ust,
+    )?;
+
+    let mut res = Response::new().add_submessages(ust_msgs);
+    if !asset_to_repay.amount.is_zero() {
+        res = res.add_message(RedBank(&config.red_bank).repay_msg(&asset_to_repay)?);
+    }
+    res = res
+        .add_attribute("action", "bluna_lev_arb/reply/after_exit_swap")
+        .add_attribute("asset_received", asset_received.to_string())
+        .add_attribute("asset_repaid", asset_to_repay.to_string())
+        .add_attribute("loss", Asset::native("uluna", amount_lost).to_string());
+
+    // The sale of held positions' and the inventory's bLuna is recorded with the positions. Record
+    // the rest of the exit, if any, in the position ledger as a position of its own.
+    let rest_sold = amount_sold.saturating_sub(held_sold);
+    if !rest_sold.is_zero() {
+        let rest_received = asset_received.amount - held_total.received;
+        let rest_repaid = asset_to_repay.amount.saturating_sub(held_total.repaid + held_covered);
+        let rest_expected = rest_sold * exchange_rate;
+        let now = env.block.time.seconds();
+        let id = POSITION_COUNT.load(deps.storage)? + 1;
+        let position = Position {
+            id,
+            borrowed: Uint128::zero(),
+            ust_borrowed: Uint128::zero(),
+            unbonded: Uint128::zero(),
+            batch_id: 0,
+            held: false,
+            opened_at: now,
+            profit_shares: vec![],
+            settlement: Some(Settlement {
+                settled_at: now,
+                received: rest_received,
+                debt: rest_repaid,
+                repaid: rest_repaid,
+                shortfall: Uint128::zero(),
+                prepaid: Uint128::zero(),
+                reimbursed: Uint128::zero(),
+                profit: Uint128::zero(),
+            }),
+            exit: Some(Exit {
+                sold: rest_sold,
+                expected: rest_expected,
+                loss: rest_expected.saturating_sub(rest_received),
+            }),
+        };
+        POSITION_COUNT.save(deps.storage, &id)?;
+        CLOSED_POSITIONS.save(deps.storage, U64Key::new(id), &position)?;
+        res = res.add_attribute("position_id", id.to_string());
+    }
+
+    Ok(res)
+}
+
+fn after_sale_swap(
+    mut deps: DepsMut,
+    env: Env,
+    response: SubMsgExecutionResponse,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let (amount_sold, cost) = PENDING_SALE.load(deps.storage)?;
+    PENDING_SALE.remove(deps.storage);
+
+    let asset_received = Pair(&config.bluna_pair).parse_swap_events(deps.api, &response.events)?;
+
+    // Record the sale as a position backed by its portion of the inventory's cost basis, and
+    // settle it right away with the proceeds
+    let id = POSITION_COUNT.load(deps.storage)? + 1;
+    let position = Position {
+        id,
+        borrowed: cost,
+        ust_borrowed: Uint128::zero(),
+        unbonded: amount_sold,
+        batch_id: 0,
+        held: false,
+        opened_at: env.block.time.seconds(),
+        profit_shares: config.profit_shares.clone(),
+        settlement: None,
+        exit: None,
+    };
+    POSITION_COUNT.save(deps.storage, &id)?;
+    POSITIONS.save(deps.storage, U64Key::new(id), &position)?;
+
+    let (settlement, positions) =
+        settle(deps.branch(), &env, &config, vec![position], asset_received.amount)?;
+
+    let res = Response::new()
+        .add_attribute("action", "bluna_lev_arb/reply/after_sale_swap")
+        .add_attribute("asset_received", asset_received.to_string())
+        .add_attribute("position_id", id.to_string());
+    realise_settlement(deps, &env, &config, &settlement, &positions, &no_ust_debt(), res)
+}
+
+fn after_ust_repayment_swap(
+    deps: DepsMut,
+    env: Env,
+    result: ContractResult<SubMsgExecutionResponse>,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+    let ust_borrowing = config
+        .ust_borrowing
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("ust borrowing is disabled"))?;
+
+    let (luna_swapped, ust_debt) = PENDING_UST_REPAYMENT.load(deps.storage)?;
+    PENDING_UST_REPAYMENT.remove(deps.storage);
+
+    // UST debt that is not backed by any open position, including that of the positions closed
+    let ust_unbacked = UST_BAD_DEBT.load(deps.storage)? + ust_debt;
+
+    let res =
+        Response::new().add_attribute("action", "bluna_lev_arb/reply/after_ust_repayment_swap");
+
+    match result {
+        // The UST received repays the debt not backed by any open position first, then the debt of
+        // open positions, up to the amount owed; whatever remains unpaid of the former goes bad
+        ContractResult::Ok(response) => {
+            let asset_received =
+                Router(&ust_borrowing.router).parse_swap_events(deps.api, &response.events)?;
+            let red_bank = RedBank(&config.red_bank);
+            let ust_owed = red_bank
+                .query_user_asset_debt(
+                    &deps.querier,
+                    &env.contract.address,
+                    &AssetInfo::native("uusd"),
+                )?
+                .amount;
+            let asset_to_repay = Asset::native("uusd", cmp::min(asset_received.amount, ust_owed));
+            let ust_bad_debt = ust_unbacked.saturating_sub(asset_to_repay.amount);
+            UST_BAD_DEBT.save(deps.storage, &ust_bad_debt)?;
+
+            let mut res = res;
+            if !asset_to_repay.amount.is_zero() {
+                res = res.add_message(red_bank.repay_msg(&asset_to_repay)?);
+            }
+            Ok(res
+                .add_attribute("asset_received", asset_received.to_string())
+                .add_attribute("asset_repaid", asset_to_repay.to_string())
+                .add_attribute("ust_bad_debt", Asset::native("uusd", ust_bad_debt).to_string()))
+        },
+        // The swap failed, e.g. due to slippage, and its state changes have been reverted, so the
+        // Luna is still with us. The UST debt it was meant to repay goes bad, and the Luna is added
+        // to the reserve fund, from which the owner can repay the UST bad debt later.
+        ContractResult::Err(err) => {
+            UST_BAD_DEBT.save(deps.storage, &ust_unbacked)?;
+            let kind = ReserveChangeKind::UstRepaymentFailed;
+            add_to_reserve(deps.storage, &env, kind, luna_swapped)?;
+            Ok(res
+                .add_attribute("asset_reserved", Asset::native("uluna", luna_swapped).to_string())
+                .add_attribute("ust_bad_debt", Asset::native("uusd", ust_unbacked).to_string())
+                .add_attribute("swap_error", err))
+        },
+    }
+}
+
+fn after_leg_swap(
+    deps: DepsMut,
+    env: Env,
+    response: SubMsgExecutionResponse,
+) -> StdResult<Response> {
+    let config = CONFIG.load(deps.storage)?;
+
+    // The first of the pending legs is the one that has just been executed
+    let mut pairs = PENDING_LEGS.load(deps.storage)?;
+    let pair = pairs.remove(0);
+
+    let asset_received = Pair(&pair).parse_swap_events(deps.api, &response.events)?;
+
+    // If there are legs remaining, swap the returned asset through the next pair; handle the reply
+    if let Some(next_pair) = pairs.first().cloned() {
+        PENDING_LEGS.save(deps.storage, &pairs)?;
+        return Ok(Response::new()
+            .add_submessage(SubMsg::reply_on_success(
+                Pair(&next_pair).swap_msg(&asset_received)?,
+                10,
+            ))
+            .add_attribute("action", "bluna_lev_arb/reply/after_leg_swap")
+            .add_attribute("asset_received", asset_received.to_string())
+            .add_attribute("next_pair", next_pair.to_string()));
+    }
+    PENDING_LEGS.remove(deps.storage);
+
+    let minimum_receive = MINIMUM_RECEIVE.load(deps.storage)?;
+    MINIMUM_RECEIVE.remove(deps.storage);
+
+    if asset_received.amount < minimum_receive {
+        return Err(StdError::generic_err(
+            format!("too little received from swap: {}, expecting at least {}", asset_received, minimum_receive)
+        ));
+    }
+
+    let borrowed = PENDING_BORROWED.load(deps.storage)?;
+    PENDING_BORROWED.remove(deps.storage);
+
+    // Record the arb in the position ledger, settled right away: the borrowed Luna is repaid, and
+    // the rest is profit
+    let now = env.block.time.seconds();
+    let id = POSITION_COUNT.load(deps.storage)? + 1;
+    let settlement = Settlement {
+        settled_at: now,
+        received: asset_received.amount,
+        debt: borrowed,
+        repaid: borrowed,
+        shortfall: Uint128::zero(),
+        prepaid: Uint128::zero(),
+        reimbursed: Uint128::zero(),
+        profit: asset_received.amount - borrowed,
+    };
+    let position = Position {
+        id,
+        borrowed,
+        ust_borrowed: Uint128::zero(),
+        unbonded: Uint128::zero(),
+        batch_id: 0,
+        held: false,
+        opened_at: now,
+        profit_shares: config.profit_shares.clone(),
+        settlement: Some(settlement.clone()),
+        exit: None,
+    };
+    POSITION_COUNT.save(deps.storage, &id)?;
+    CLOSED_POSITIONS.save(deps.storage, U64Key::new(id), &position)?;
+
+    let res = Response::new()
+        .add_attribute("action", "bluna_lev_arb/reply/after_leg_swap")
+        .add_attribute("asset_received", asset_received.to_string())
+        .add_attribute("position_id", id.to_string());
+    realise_settlement(deps, &env, &config, &settlement, &[position], &no_ust_debt(), res)
+}
+
+/// Close the positions whose bLuna was held for batch unbonding, and empty the inventory, as all
+/// bLuna held by the contract has been sold. The inventory is recorded as a position backed by its
+/// cost basis. Each position is attributed its share of the sale by the amount of bLuna held, and
+/// its share of the debt by the amount borrowed, the same way as when positions are settled; as the
+/// proceeds are used to repay debt, nothing is distributed. If an exchange rate is provided, each
+/// position also records the sale as an exit, compared to unbonding at that rate.
+///
+/// Positions backed by UST debt are settled the same way as when they finish unbonding. Only the
+/// Luna received, the shortfall and the profit of them are included in the sum of all outcomes.
+///
+/// Returns the amount of bLuna sold for the positions, the sum of their settlement outcomes, and
+/// the sum of the outcomes of the positions backed by UST debt along with the UST debt attributed
+/// to them.
+fn close_held_positions(
+    mut deps: DepsMut,
+    env: &Env,
+    config: &Config<Addr>,
+    amount_sold: Uint128,
+    amount_received: Uint128,
+    exchange_rate: Option<Decimal>,
+) -> StdResult<(Uint128, Settlement, (Settlement, Uint128))> {
+    let positions = POSITIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, position)| position))
+        .collect::<StdResult<Vec<Position>>>()?;
+
+    // Debt that has gone bad is not backed by any position. Positions backed by UST debt don't
+    // share the Luna debt.
+    let debt_attributable = RedBank(&config.red_bank)
+        .query_user_asset_debt(&deps.querier, &env.contract.address, &AssetInfo::native("uluna"))?
+        .amount
+        .saturating_sub(BAD_DEBT.load(deps.storage)?);
+    let inventory = INVENTORY.load(deps.storage)?;
+    let total_borrowed = inventory.cost_basis
+        + positions
+            .iter()
+            .filter(|position| position.ust_borrowed.is_zero())
+            .map(|position| position.borrowed)
+            .sum::<Uint128>();
+
+    let (ust_positions, mut positions_to_close): (Vec<Position>, Vec<Position>) = positions
+        .into_iter()
+        .filter(|position| position.held)
+        .partition(|position| !position.ust_borrowed.is_zero());
+    if !inventory.amount.is_zero() {
+        let id = POSITION_COUNT.load(deps.storage)? + 1;
+        positions_to_close.push(Position {
+            id,
+            borrowed: inventory.cost_basis,
+            ust_borrowed: Uint128::zero(),
+            unbonded: inventory.amount,
+            batch_id: 0,
+            held: false,
+            opened_at: env.block.time.seconds(),
+            profit_shares: config.profit_shares.clone(),
+            settlement: None,
+            exit: None,
+        });
+        POSITION_COUNT.save(deps.storage, &id)?;
+    }
+    INVENTORY.save(deps.storage, &Inventory::default())?;
+
+    let mut total_sold = Uint128::zero();
+    let mut total = Settlement {
+        settled_at: env.block.time.seconds(),
+        ..Settlement::default()
+    };
+    for mut position in positions_to_close {
+        let sold = position.unbonded;
+        let received = amount_received.multiply_ratio(sold, amount_sold);
+        let debt = if total_borrowed.is_zero() {
+            Uint128::zero()
+        } else {
+            debt_attributable.multiply_ratio(position.borrowed, total_borrowed)
+        };
+        let repaid = cmp::min(received, debt);
+        let settlement = Settlement {
+            settled_at: env.block.time.seconds(),
+            received,
+            debt,
+            repaid,
+            shortfall: debt - repaid,
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: received - repaid,
+        };
+
+        total_sold += sold;
+        total.received += settlement.received;
+        total.debt += settlement.debt;
+        total.repaid += settlement.repaid;
+        total.shortfall += settlement.shortfall;
+        total.profit += settlement.profit;
+
+        position.held = false;
+        position.settlement = Some(settlement);
+        position.exit = exchange_rate.map(|rate| {
+            let expected = sold * rate;
+            Exit {
+                sold,
+                expected,
+                loss: expected.saturating_sub(received),
+            }
+        });
+        POSITIONS.remove(deps.storage, U64Key::new(position.id));
+        CLOSED_POSITIONS.save(deps.storage, U64Key::new(position.id), &position)?;
+    }
+
+    if ust_positions.is_empty() {
+        return Ok((total_sold, total, no_ust_debt()));
+    }
+
+    let ust_sold: Uint128 = ust_positions.iter().map(|position| position.unbonded).sum();
+    let ust_received = amount_received.multiply_ratio(ust_sold, amount_sold);
+    let ust_positions = ust_positions
+        .into_iter()
+        .map(|position| Position {
+            held: false,
+            ..position
+        })
+        .collect();
+    let (ust_total, ust_closed_positions, ust_debt) =
+        settle_ust(deps.branch(), env, config, ust_positions, ust_received)?;
+    if let Some(rate) = exchange_rate {
+        for mut position in ust_closed_positions {
+            let sold = position.unbonded;
+            let expected = sold * rate;
+            let received = position
+                .settlement
+                .as_ref()
+                .map(|settlement| settlement.received)
+                .unwrap_or_else(Uint128::zero);
+            position.exit = Some(Exit {
+                sold,
+                expected,
+                loss: expected.saturating_sub(received),
+            });
+            CLOSED_POSITIONS.save(deps.storage, U64Key::new(position.id), &position)?;
+        }
+    }
+
+    total_sold += ust_sold;
+    total.received += ust_total.received;
+    total.shortfall += ust_total.shortfall;
+    total.profit += ust_total.profit;
+
+    Ok((total_sold, total, (ust_total, ust_debt)))
+}
+
+/// The outcome of settling no positions backed by UST debt.
+fn no_ust_debt() -> (Settlement, Uint128) {
+    (Settlement::default(), Uint128::zero())
+}
+
+/// Repay as much debt as possible with the proceeds of selling bLuna, given the sum of the
+/// settlement outcomes of the closed positions. Their shortfall is covered the same way as when
+/// positions are settled, with the loss not absorbed by the reserve fund carried forward. The Luna
+/// repaid by closed positions backed by UST debt is swapped for UST to repay their debt instead.
+/// Only what exceeds the closed positions' debt repays bad debt; whatever exceeds the debt stays in
+/// the contract. Returns the asset to repay, the amount covered for the Luna shortfall, and the
+/// submsg to swap Luna for UST.
+fn repay_with_proceeds(
+    deps: DepsMut,
+    env: &Env,
+    config: &Config<Addr>,
+    proceeds: Uint128,
+    closed: &Settlement,
+    ust: &(Settlement, Uint128),
+) -> StdResult<(Asset, Uint128, Vec<SubMsg>)> {
+    let (luna_covered, luna_loss_uncovered) =
+        cover_shortfall(deps.storage, env, closed.shortfall - ust.0.shortfall)?;
+    let (_, ust_loss_uncovered, ust_msgs) = settle_ust_debt(deps.storage, env, config, ust)?;
+    let loss_uncovered = luna_loss_uncovered + ust_loss_uncovered;
+    carry_forward_loss(deps.storage, env, loss_uncovered, Uint128::zero())?;
+
+    let debt = RedBank(&config.red_bank)
+        .query_user_asset_debt(&deps.querier, &env.contract.address, &AssetInfo::native("uluna"))?
+        .amount;
+    let amount_available = proceeds - ust.0.repaid + luna_covered;
+    let asset_to_repay = Asset::native("uluna", cmp::min(amount_available, debt));
+
+    let amount_in_excess = asset_to_repay.amount.saturating_sub(closed.repaid + luna_covered);
+    let bad_debt = BAD_DEBT.load(deps.storage)?;
+    BAD_DEBT.save(deps.storage, &(bad_debt - cmp::min(amount_in_excess, bad_debt)))?;
+
+    Ok((asset_to_repay, luna_covered, ust_msgs))
+}
+
+/// Distribute the specified amount of Luna among the settled positions, pro-rata by their profits.
+/// Each position's portion is shared among recipients according to the profit shares in force when
+/// the position was opened; the remaining goes to the owner. Depending on the payout mode, the
+/// amounts are either sent, converted to recipients' preferred assets if needed, or credited to be
+/// claimed later. Returns the submessages to send the amounts, if any, and the owner's portion.
+fn distribute_profit(
+    storage: &mut dyn Storage,
+    config: &Config<Addr>,
+    positions: &[Position],
+    amount: Uint128,
+) -> StdResult<(Vec<SubMsg>, Asset)> {
+    let profit_of = |position: &Position| {
+        position
+            .settlement
+            .as_ref()
+            .map(|settlement| settlement.profit)
+            .unwrap_or_else(Uint128::zero)
+    };
+    let total_profit: Uint128 = positions.iter().map(profit_of).sum();
+
+    // First, find out the amount to pay to each recipient. A recipient may have shares in multiple
+    // positions, in which case the amounts are combined, unless the recipient prefers different
+    // assets in different positions.
+    let mut amount_shared = Uint128::zero();
+    let mut amounts: Vec<(Recipient<Addr>, Option<Conversion<Addr>>, Uint128)> = vec![];
+    for position in positions {
+        let amount_for_position = if total_profit.is_zero() {
+            Uint128::zero()
+        } else {
+            amount.multiply_ratio(profit_of(position), total_profit)
+        };
+
+        for profit_share in &position.profit_shares {
+            let amount_for_recipient = amount_for_position * profit_share.share;
+            match amounts.iter_mut().find(|(recipient, conversion, _)| {
+                recipient == &profit_share.recipient && conversion == &profit_share.conversion
+            }) {
+                Some((_, _, combined)) => *combined += amount_for_recipient,
+                None => amounts.push((
+                    profit_share.recipient.clone(),
+                    profit_share.conversion.clone(),
+                    amount_for_recipient,
+                )),
+            }
+            amount_shared += amount_for_recipient;
+        }
+    }
+
+    // The remaining profit goes to owner
+    let profit = Asset::native("uluna", amount - amount_shared);
+    amounts.push((Recipient::Address(config.owner.clone()), None, profit.amount));
+
+    let mut msgs: Vec<SubMsg> = vec![];
+    let mut pending_payouts: Vec<(Addr, Option<Addr>, Uint128)> = vec![];
+    for (recipient, conversion, amount) in amounts {
+        let asset = Asset::native("uluna", amount);
+        match (recipient, conversion, config.payout_mode) {
+            // Contract recipients can't claim profit themselves, so their profit is always pushed.
+            // `Config::check` rejects them in pull payout mode, but positions opened before the
+            // mode was changed may still have them.
+            //
+            // Execute the contract with the Luna attached; handle the reply to send the Luna with
+            // a plain transfer instead if the execution fails. Unlike `BankMsg`, the funds sent
+            // along with a contract execution must not be zero, so skip if there is nothing to pay.
+            (Recipient::Contract { contract, msg }, _, _) => {
+                if !amount.is_zero() {
+                    msgs.push(SubMsg::reply_always(
+                        WasmMsg::Execute {
+                            contract_addr: contract.to_string(),
+                            msg,
+                            funds: vec![Coin::new(amount.u128(), "uluna")],
+                        },
+                        4,
+                    ));
+                    pending_payouts.push((contract, None, amount));
+                }
+            },
+            // NOTE: `Config::check` rejects conversions in pull payout mode, but positions opened
+            // before the mode was changed may still have them; they are ignored, and the recipient
+            // claims Luna
+            (Recipient::Address(recipient), _, PayoutMode::Pull) => {
+                CLAIMABLE.update(storage, &recipient, |claimable| -> StdResult<_> {
+                    Ok(claimable.unwrap_or_else(Uint128::zero) + amount)
+                })?;
+            },
+            // Swap Luna for the preferred asset, and have the pair send it to the recipient; handle
+            // the reply to record the payout, or to send Luna instead if the swap fails
+            (Recipient::Address(recipient), Some(conversion), PayoutMode::Push)
+                if !amount.is_zero() =>
+            {
+                msgs.push(SubMsg::reply_always(
+                    Pair(&conversion.pair).swap_msg_with_options(
+                        &asset,
+                        Some(conversion.max_spread),
+                        Some(&recipient),
+                    )?,
+                    4,
+                ));
+                pending_payouts.push((recipient, Some(conversion.pair), amount));
+            },
+            // NOTE: Unlike CW20 transfer, `BankMsg` allows zero amount, so we don't need to check
+            // whether the amount is zero.
+            (Recipient::Address(recipient), _, PayoutMode::Push) => {
+                msgs.push(SubMsg::new(asset.transfer_msg(&recipient)?))
+            },
+        }
+    }
+    if !pending_payouts.is_empty() {
+        PENDING_PAYOUTS.save(storage, &pending_payouts)?;
+    }
+
+    Ok((msgs, profit))
+}
+
+fn unwrap_reply(reply: Reply) -> StdResult<SubMsgExecutionResponse> {
+    reply.result.into_result().map_err(StdError::generic_err)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Query
+//--------------------------------------------------------------------------------------------------
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Status {} => to_binary(&query_status(deps, env)?),
+        QueryMsg::Position { id } => to_binary(&query_position(deps, id)?),
+        QueryMsg::Reserve {
+            start_after,
+            limit,
+        } => to_binary(&query_reserve(deps, start_after, limit)?),
+        QueryMsg::Claimable { address } => to_binary(&query_claimable(deps, address)?),
+        QueryMsg::Finalizable {} => to_binary(&query_finalizable(deps, env)?),
+        QueryMsg::CreditReport {} => to_binary(&query_credit_report(deps, env)?),
+        QueryMsg::Schedule {} => to_binary(&query_schedule(deps, env)?),
+        QueryMsg::Inventory {} => to_binary(&query_inventory(deps)?),
+        QueryMsg::ArbPlan {} => to_binary(&query_arb_plan(deps)?),
+        QueryMsg::Quote { amount } => to_binary(&query_quote(deps, amount)?),
+    }
+}
+
+fn query_config(deps: Deps) -> StdResult<Config<String>> {
+    Ok(CONFIG.load(deps.storage)?.into())
+}
+
+fn query_status(deps: Deps, env: Env) -> StdResult<StatusResponse> {
+    let config = CONFIG.load(deps.storage)?;
+
+    // Interest accrued on idle Luna deposited in Red Bank is reported separately from the principal
+    let deposited = DEPOSITED.load(deps.storage)?;
+    let deposit_interest = if deposited.is_zero() {
+        Uint128::zero()
+    } else {
+        RedBank(&config.red_bank)
+            .query_user_deposit(&deps.querier, &env.contract.address, &AssetInfo::native("uluna"))?
+            .saturating_sub(deposited)
+    };
+
+    Ok(StatusResponse {
+        debt: RedBank(&config.red_bank).query_user_asset_debt(
+            &deps.querier,
+            &env.contract.address,
+            &cw_asset::AssetInfoBase::native("uluna"),
+        )?,
+        unbond_requests: Hub(&config.bluna_hub)
+            .query_unbond_requests(&deps.querier, &env.contract.address)?,
+        withdrawable_unbonded: Hub(&config.bluna_hub)
+            .query_withdrawable_unbonded(&deps.querier, &env.contract.address)?,
+        first_loss_capital: FIRST_LOSS_CAPITAL.load(deps.storage)?,
+        bad_debt: BAD_DEBT.load(deps.storage)?,
+        carried_loss: CARRIED_LOSS.load(deps.storage)?,
+        prepaid: PREPAID.load(deps.storage)?,
+        deposited,
+        deposit_interest,
+        paused: PAUSED.load(deps.storage)?,
+        owner_last_active: OWNER_LAST_ACTIVE.load(deps.storage)?,
+    })
+}
+
+fn query_position(deps: Deps, id: u64) -> StdResult<Position> {
+    match POSITIONS.may_load(deps.storage, U64Key::new(id))? {
+        Some(position) => Ok(position),
+        None => CLOSED_POSITIONS.load(deps.storage, U64Key::new(id)),
+    }
+}
+
+fn query_reserve(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<ReserveResponse> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let start = start_after.map(Bound::exclusive_int);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let history = RESERVE_HISTORY
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, change)| change))
+        .collect::<StdResult<Vec<ReserveChange>>>()?;
+
+    Ok(ReserveResponse {
+        balance: RESERVE.load(deps.storage)?,
+        target: config.reserve_target,
+        history,
+    })
+}
+
+fn query_claimable(deps: Deps, address: String) -> StdResult<ClaimableResponse> {
+    let addr = deps.api.addr_validate(&address)?;
+    Ok(ClaimableResponse {
+        address,
+        amount: CLAIMABLE.may_load(deps.storage, &addr)?.unwrap_or_else(Uint128::zero),
+    })
+}
+
+fn query_finalizable(deps: Deps, env: Env) -> StdResult<FinalizableResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let hub = Hub(&config.bluna_hub);
+
+    let withdrawable =
+        hub.query_withdrawable_unbonded(&deps.querier, &env.contract.address)?.withdrawable;
+    if withdrawable.is_zero() {
+        return Ok(FinalizableResponse {
+            finalizable: false,
+            withdrawable,
+            estimated_bounty: Uint128::zero(),
+        });
+    }
+
+    // The positions to be settled are those whose batches have been released by bLuna Hub, but not
+    // yet withdrawn
+    let batch_ids: Vec<u64> = hub
+        .query_unbond_requests(&deps.querier, &env.contract.address)?
+        .requests
+        .iter()
+        .map(|(batch_id, _)| *batch_id)
+        .collect();
+    let first_batch_id = batch_ids.iter().min().cloned().unwrap_or_default();
+    let last_batch_id = batch_ids.iter().max().cloned().unwrap_or_default();
+    let released_batch_ids: Vec<u64> = hub
+        .query_unbond_history_range(&deps.querier, first_batch_id, last_batch_id)?
+        .iter()
+        .filter(|history| history.released && batch_ids.contains(&history.batch_id))
+        .map(|history| history.batch_id)
+        .collect();
+
+    // Debt is attributed among positions backed by Luna debt and the inventory, as in `settle`
+    let positions = POSITIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, position)| position))
+        .filter(|item| item.as_ref().map_or(true, |position| position.ust_borrowed.is_zero()))
+        .collect::<StdResult<Vec<Position>>>()?;
+    let total_borrowed = INVENTORY.load(deps.storage)?.cost_basis
+        + positions.iter().map(|position| position.borrowed).sum::<Uint128>();
+    let borrowed: Uint128 = positions
+        .iter()
+        .filter(|position| released_batch_ids.contains(&position.batch_id))
+        .map(|position| position.borrowed)
+        .sum();
+
+    // Estimate the realised profit the same way as when the positions are settled: debt, including
+    // the prepaid debt, is attributed pro-rata by the amount borrowed, and losses carried forward
+    // are recovered first
+    let debt_info = RedBank(&config.red_bank).query_user_asset_debt(
+        &deps.querier,
+        &env.contract.address,
+        &AssetInfo::native("uluna"),
+    )?;
+    let debt_attributable = debt_info.amount.saturating_sub(BAD_DEBT.load(deps.storage)?)
+        + PREPAID.load(deps.storage)?;
+    let debt = if total_borrowed.is_zero() {
+        Uint128::zero()
+    } else {
+        debt_attributable.multiply_ratio(borrowed, total_borrowed)
+    };
+    let profit = withdrawable.saturating_sub(debt);
+    let amount_realised = profit - cmp::min(profit, CARRIED_LOSS.load(deps.storage)?);
+    let (fee_charged, _) = charge_protocol_fee(deps, &config, amount_realised)?;
+    let estimated_bounty = keeper_bounty(&config, amount_realised - fee_charged);
+
+    // Keepers are only interested if they would be paid
+    Ok(FinalizableResponse {
+        finalizable: !estimated_bounty.is_zero(),
+        withdrawable,
+        estimated_bounty,
+    })
+}
+
+fn query_credit_report(deps: Deps, env: Env) -> StdResult<CreditReportResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    credit_report(deps, &env, &config)
+}
+
+/// Assess the contract's solvency. Pending unbonds, and bLuna held for batch unbonding or retained
+/// in the inventory, are valued at bLuna Hub's current exchange rate, and UST debt at Astroport
+/// router's current price.
+fn credit_report(
+    deps: Deps,
+    env: &Env,
+    config: &Config<Addr>,
+) -> StdResult<CreditReportResponse> {
+    let red_bank = RedBank(&config.red_bank);
+    let hub = Hub(&config.bluna_hub);
+    let luna = AssetInfo::native("uluna");
+
+    let total_debt =
+        red_bank.query_user_asset_debt(&deps.querier, &env.contract.address, &luna)?.amount;
+    let withdrawable =
+        hub.query_withdrawable_unbonded(&deps.querier, &env.contract.address)?.withdrawable;
+    let reserve = RESERVE.load(deps.storage)?;
+
+    let ust = AssetInfo::native("uusd");
+    let ust_debt = if config.ust_borrowing.is_some() {
+        red_bank.query_user_asset_debt(&deps.querier, &env.contract.address, &ust)?.amount
+    } else {
+        Uint128::zero()
+    };
+    let ust_debt_value = match &config.ust_borrowing {
+        Some(ust_borrowing) if !ust_debt.is_zero() => Router(&ust_borrowing.router)
+            .query_simulation(&deps.querier, ust_debt, &[ust.clone(), luna.clone()])?,
+        _ => Uint128::zero(),
+    };
+
+    // Batches already released by bLuna Hub are counted as withdrawable Luna instead
+    let tranches: Vec<Tranche> = pending_tranches(deps, env, config)?
+        .into_iter()
+        .filter(|tranche| !tranche.released)
+        .collect();
+    let borrow_rate = if tranches.is_empty() {
+        Decimal::zero()
+    } else {
+        red_bank.query_market(&deps.querier, &luna)?.borrow_rate.to_std_decimal()
+    };
+    let ust_borrow_rate = if tranches.is_empty() || ust_debt.is_zero() {
+        Decimal::zero()
+    } else {
+        red_bank.query_market(&deps.querier, &ust)?.borrow_rate.to_std_decimal()
+    };
+
+    let now = env.block.time.seconds();
+    let maturities: Vec<Maturity> = tranches
+        .iter()
+        .map(|tranche| {
+            let time_left = tranche.expected_release.saturating_sub(now);
+            let interest = total_debt.multiply_ratio(time_left, SECONDS_PER_YEAR) * borrow_rate
+                + ust_debt_value.multiply_ratio(time_left, SECONDS_PER_YEAR) * ust_borrow_rate;
+            Maturity {
+                batch_id: tranche.batch_id,
+                matures_at: tranche.expected_release,
+                luna_value: tranche.expected_luna,
+                projected_debt: total_debt + ust_debt_value + interest,
+            }
+        })
+        .collect();
+
+    let pending_unbond_value: Uint128 = maturities.iter().map(|maturity| maturity.luna_value).sum();
+
+    // bLuna not yet unbonding backs debt too
+    let mut amount_held = INVENTORY.load(deps.storage)?.amount;
+    for item in POSITIONS.range(deps.storage, None, None, Order::Ascending) {
+        let (_, position) = item?;
+        if position.held {
+            amount_held += position.unbonded;
+        }
+    }
+    let held_value = if amount_held.is_zero() {
+        Uint128::zero()
+    } else {
+        amount_held * hub.query_state(&deps.querier)?.exchange_rate
+    };
+
+    let debt = total_debt + ust_debt_value;
+    let coverage_ratio = if debt.is_zero() {
+        None
+    } else {
+        let covered = pending_unbond_value + held_value + withdrawable + reserve;
+        Some(Decimal::from_ratio(covered, debt))
+    };
+
+    Ok(CreditReportResponse {
+        total_debt,
+        ust_debt,
+        ust_debt_value,
+        maturities,
+        pending_unbond_value,
+        held_value,
+        withdrawable,
+        reserve,
+        coverage_ratio,
+    })
+}
+
+fn query_schedule(deps: Deps, env: Env) -> StdResult<ScheduleResponse> {
+    let config = CONFIG.load(deps.storage)?;
+
+    let withdrawable = Hub(&config.bluna_hub)
+        .query_withdrawable_unbonded(&deps.querier, &env.contract.address)?
+        .withdrawable;
+
+    Ok(ScheduleResponse {
+        tranches: pending_tranches(deps, &env, &config)?,
+        withdrawable,
+        ready_now: !withdrawable.is_zero(),
+    })
+}
+
+/// Find the contract's unbonding requests at bLuna Hub that have not been withdrawn, in ascending
+/// order of batch ids, along with when each is expected to be released and how much Luna it is
+/// expected to return. Batches not yet submitted for unbonding are valued at bLuna Hub's current
+/// exchange rate, and are expected to be submitted at the start of the next epoch.
+fn pending_tranches(deps: Deps, env: &Env, config: &Config<Addr>) -> StdResult<Vec<Tranche>> {
+    let hub = Hub(&config.bluna_hub);
+
+    let mut requests = hub.query_unbond_requests(&deps.querier, &env.contract.address)?.requests;
+    if requests.is_empty() {
+        return Ok(vec![]);
+    }
+    requests.sort_by_key(|(batch_id, _)| *batch_id);
+
+    let first_batch_id = requests[0].0;
+    let last_batch_id = requests[requests.len() - 1].0;
+    let state = hub.query_state(&deps.querier)?;
+    let parameters = hub.query_parameters(&deps.querier)?;
+    let history = hub.query_unbond_history_range(&deps.querier, first_batch_id, last_batch_id)?;
+
+    let positions = POSITIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, position)| position))
+        .collect::<StdResult<Vec<Position>>>()?;
+
+    let now = env.block.time.seconds();
+    let next_submission = cmp::max(state.last_unbonded_time + parameters.epoch_period, now);
+
+    Ok(requests
+        .into_iter()
+        .map(|(batch_id, amount)| {
+            let batch_history = history.iter().find(|history| history.batch_id == batch_id);
+            let released = batch_history.map_or(false, |history| history.released);
+            let submitted_at = batch_history.map_or(next_submission, |history| history.time);
+            let rate = match batch_history {
+                Some(history) if released => history.withdraw_rate,
+                _ => state.exchange_rate,
+            };
+            Tranche {
+                batch_id,
+                bluna_amount: amount,
+                expected_release: submitted_at + parameters.unbonding_period,
+                expected_luna: amount * rate,
+                released,
+                position_ids: positions
+                    .iter()
+                    .filter(|position| position.batch_id == batch_id)
+                    .map(|position| position.id)
+                    .collect(),
+                ust_borrowed: positions
+                    .iter()
+                    .filter(|position| position.batch_id == batch_id)
+                    .map(|position| position.ust_borrowed)
+                    .sum(),
+            }
+        })
+        .collect())
+}
+
+fn query_inventory(deps: Deps) -> StdResult<Inventory> {
+    INVENTORY.load(deps.storage)
+}
+
+fn query_arb_plan(deps: Deps) -> StdResult<Option<ArbPlan>> {
+    ARB_PLAN.may_load(deps.storage)
+}
+
+fn query_quote(deps: Deps, amount: Uint128) -> StdResult<QuoteResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    quote(deps, &config, amount)
+}
+
+/// Price the specified amount of bLuna at the liquidity desk: its Luna value at bLuna Hub's current
+/// exchange rate, less the peg recovery fee charged on unbonding, if any, and the desk's discount.
+/// Fails if the desk is disabled, if the Luna to pay would exceed the desk's maximum size, or if it
+/// would take the desk's outstanding debt above the maximum.
+fn quote(deps: Deps, config: &Config<Addr>, amount: Uint128) -> StdResult<QuoteResponse> {
+    let desk = config
+        .desk
+        .as_ref()
+        .ok_or_else(|| StdError::generic_err("liquidity desk is disabled"))?;
+
+    // bLuna Hub charges the peg recovery fee while the exchange rate is below the threshold
+    let hub = Hub(&config.bluna_hub);
+    let exchange_rate = hub.query_state(&deps.querier)?.exchange_rate;
+    let parameters = hub.query_parameters(&deps.querier)?;
+    let peg_fee = if exchange_rate < parameters.er_threshold {
+        parameters.peg_recovery_fee
+    } else {
+        Decimal::zero()
+    };
+
+    let luna_amount =
+        amount * exchange_rate * (Decimal::one() - peg_fee) * (Decimal::one() - desk.discount);
+    if luna_amount > desk.max_size {
+        return Err(StdError::generic_err(
+            format!("luna amount {} is greater than max size {}", luna_amount, desk.max_size)
+        ));
+    }
+
+    let desk_debt = open_desk_positions(deps.storage)?
+        .iter()
+        .map(|position| position.borrowed)
+        .sum::<Uint128>();
+    if desk_debt + luna_amount > desk.max_debt {
+        return Err(StdError::generic_err(format!(
+            "luna amount {} would take desk debt {} above max debt {}",
+            luna_amount, desk_debt, desk.max_debt
+        )));
+    }
+
+    Ok(QuoteResponse {
+        bluna_amount: amount,
+        exchange_rate,
+        peg_fee,
+        discount: desk.discount,
+        luna_amount,
+    })
+}
+
+/// The positions opened by the liquidity desk that are still open
+fn open_desk_positions(storage: &dyn Storage) -> StdResult<Vec<Position>> {
+    DESK_POSITION_IDS
+        .load(storage)?
+        .into_iter()
+        .map(|id| POSITIONS.may_load(storage, U64Key::new(id)))
+        .filter_map(|result| result.transpose())
+        .collect()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Migrate
+//--------------------------------------------------------------------------------------------------
+
+#[entry_point]
+pub fn migrate(mut deps: DepsMut, env: Env, msg: MigrateMsg) -> StdResult<Response> {
+    let config = match msg.config {
+        Some(config) => {
+            let config = config.check(deps.api, &deps.querier)?;
+            CONFIG.save(deps.storage, &config)?;
+            config
+        },
+        None => CONFIG.load(deps.storage).map_err(|_| {
+            StdError::generic_err("stored config cannot be loaded; a new config must be provided")
+        })?,
+    };
+
+    // Versions that did not track positions may have unbonds and debt in flight
+    if POSITION_COUNT.may_load(deps.storage)?.is_none() {
+        seed_legacy_positions(deps.branch(), &env, &config)?;
+    }
+
+    // State added by later versions is initialised as it would have been at instantiation
+    init_if_missing(deps.storage, &POSITION_COUNT, 0)?;
+    init_if_missing(deps.storage, &FIRST_LOSS_CAPITAL, Uint128::zero())?;
+    init_if_missing(deps.storage, &FIRST_LOSS_DRAWN, Uint128::zero())?;
+    init_if_missing(deps.storage, &BAD_DEBT, Uint128::zero())?;
+    init_if_missing(deps.storage, &CARRIED_LOSS, Uint128::zero())?;
+    init_if_missing(deps.storage, &RESERVE, Uint128::zero())?;
+    init_if_missing(deps.storage, &RESERVE_CHANGE_COUNT, 0)?;
+    init_if_missing(deps.storage, &PREPAID, Uint128::zero())?;
+    init_if_missing(deps.storage, &DEPOSITED, Uint128::zero())?;
+    init_if_missing(deps.storage, &INVENTORY, Inventory::default())?;
+    init_if_missing(deps.storage, &DESK_POSITION_IDS, vec![])?;
+    init_if_missing(deps.storage, &UST_BAD_DEBT, Uint128::zero())?;
+    init_if_missing(deps.storage, &PAUSED, false)?;
+    init_if_missing(deps.storage, &OWNER_LAST_ACTIVE, env.block.time.seconds())?;
+
+    Ok(Response::new())
+}
+
+/// Record each of the contract's pending unbonding requests at bLuna Hub as a position, attributed
+/// its share of the Luna debt by the amount of bLuna unbonded, so that it can be settled the same
+/// way as positions opened after the migration. If nothing is unbonding, the debt is not backed by
+/// any position and is recorded as bad debt.
+fn seed_legacy_positions(deps: DepsMut, env: &Env, config: &Config<Addr>) -> StdResult<()> {
+    let debt = RedBank(&config.red_bank)
+        .query_user_asset_debt(&deps.querier, &env.contract.address, &AssetInfo::native("uluna"))?
+        .amount;
+    let requests = Hub(&config.bluna_hub)
+        .query_unbond_requests(&deps.querier, &env.contract.address)?
+        .requests;
+
+    let total_unbonding: Uint128 = requests.iter().map(|(_, amount)| *amount).sum();
+    if total_unbonding.is_zero() {
+        BAD_DEBT.save(deps.storage, &debt)?;
+        POSITION_COUNT.save(deps.storage, &0)?;
+        return Ok(());
+    }
+
+    let mut total_borrowed = Uint128::zero();
+    let last_index = requests.len() - 1;
+    for (index, (batch_id, amount)) in requests.into_iter().enumerate() {
+        let borrowed = if index == last_index {
+            debt - total_borrowed
+        } else {
+            debt.multiply_ratio(amount, total_unbonding)
+        };
+        total_borrowed += borrowed;
+
+        let id = index as u64 + 1;
+        let position = Position {
+            id,
+            borrowed,
+            ust_borrowed: Uint128::zero(),
+            unbonded: amount,
+            batch_id,
+            held: false,
+            opened_at: env.block.time.seconds(),
+            profit_shares: config.profit_shares.clone(),
+            settlement: None,
+            exit: None,
+        };
+        POSITIONS.save(deps.storage, U64Key::new(id), &position)?;
+        POSITION_COUNT.save(deps.storage, &id)?;
+    }
+
+    Ok(())
+}
+
+fn init_if_missing<T: Serialize + DeserializeOwned>(
+    storage: &mut dyn Storage,
+    item: &Item<T>,
+    value: T,
+) -> StdResult<()> {
+    if item.may_load(storage)?.is_none() {
+        item.save(storage, &value)?;
+    }
+    Ok(())
 }
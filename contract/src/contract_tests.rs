@@ -2,18 +2,39 @@ use std::str::FromStr;
 
 use cosmwasm_std::testing::{mock_env, mock_info, MockApi, MockStorage, MOCK_CONTRACT_ADDR};
 use cosmwasm_std::{
-    from_binary, to_binary, Addr, BankMsg, Coin, ContractResult, CosmosMsg, Decimal, Deps, Event,
-    OwnedDeps, Reply, ReplyOn, StdError, SubMsg, SubMsgExecutionResponse, Uint128, WasmMsg,
+    attr, from_binary, to_binary, Addr, BankMsg, Binary, Coin, ContractResult, CosmosMsg, Decimal,
+    Deps, Event, OwnedDeps, Reply, ReplyOn, StdError, StdResult, Storage, SubMsg,
+    SubMsgExecutionResponse, Uint128, WasmMsg,
 };
-use cw20::Cw20ExecuteMsg;
+use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw_asset::{AssetInfoBase, AssetUnchecked};
+use cw_storage_plus::U64Key;
+use mars_core::address_provider::MarsContract;
 use mars_core::asset::{Asset as LegacyAsset, AssetType as LegacyAssetType};
-use mars_core::red_bank::UserAssetDebtResponse;
+use mars_core::math::decimal::Decimal as MarsDecimal;
+use mars_core::red_bank::{ConfigResponse as RedBankConfigResponse, Market, UserAssetDebtResponse};
 use serde::de::DeserializeOwned;
 
-use crate::config::Config;
-use crate::contract::{execute, instantiate, query, reply};
-use crate::msg::{ExecuteMsg, QueryMsg};
-use crate::state::MINIMUM_RECEIVE;
+use crate::config::{
+    BatchUnbonding, Config, Conversion, CrossLsd, Desk, KeeperBounty, PayoutMode, ProfitShare,
+    ProtocolFee, Recipient, Recovery, Strategy, UstBorrowing,
+};
+use crate::contract::{execute, instantiate, migrate, query, reply};
+use crate::msg::{
+    ClaimableResponse, CreditReportResponse, ExecuteMsg, FinalizableResponse, Maturity, MigrateMsg,
+    QueryMsg, QuoteResponse, ReceiveMsg, ReserveResponse, ScheduleResponse, StatusResponse,
+    Tranche, UstRoute,
+};
+use crate::plan::{ArbPlan, PlanStatus};
+use crate::position::{Exit, Inventory, Position, Settlement};
+use crate::reserve::{ReserveChange, ReserveChangeKind};
+use crate::state::{
+    BAD_DEBT, CARRIED_LOSS, CLAIMABLE, CONFIG, DEPOSITED, DESK_POSITION_IDS, FIRST_LOSS_CAPITAL,
+    FIRST_LOSS_DRAWN, INVENTORY, KEEPER, MINIMUM_RECEIVE, PAUSED, PENDING_BORROWED,
+    PENDING_HOLD_RATIO, PENDING_LEGS, PENDING_PAYOUTS, PENDING_SALE, PENDING_UST_BORROWED,
+    PENDING_UST_REPAYMENT, POSITIONS, POSITION_COUNT, PREPAID, RESERVE, ROLLOVER_PARAMS,
+    UST_BAD_DEBT,
+};
 use crate::test_utils::CustomQuerier;
 
 fn mock_dependencies() -> OwnedDeps<MockStorage, MockApi, CustomQuerier> {
@@ -31,14 +52,45 @@ fn query_helper<T: DeserializeOwned>(deps: Deps, msg: QueryMsg) -> T {
 fn create_config() -> Config<Addr> {
     Config {
         owner: Addr::unchecked("owner"),
+        recovery: None,
         bluna_token: Addr::unchecked("bluna_token"),
         bluna_pair: Addr::unchecked("bluna_pair"),
         bluna_hub: Addr::unchecked("bluna_hub"),
+        bluna_reward: None,
         red_bank: Addr::unchecked("red_bank"),
+        council: None,
+        guardian: None,
+        protocol_fee: ProtocolFee {
+            rate: Decimal::zero(),
+            treasury: Some(Addr::unchecked("treasury")),
+        },
+        keeper_bounty: KeeperBounty {
+            rate: Decimal::zero(),
+            cap: Uint128::zero(),
+        },
+        strategy: None,
         profit_shares: vec![
-            (Addr::unchecked("alice"), Decimal::from_str("0.2").unwrap()),
-            (Addr::unchecked("bob"), Decimal::from_str("0.1").unwrap()),
+            ProfitShare {
+                recipient: Recipient::Address(Addr::unchecked("alice")),
+                share: Decimal::from_str("0.2").unwrap(),
+                conversion: None,
+            },
+            ProfitShare {
+                recipient: Recipient::Address(Addr::unchecked("bob")),
+                share: Decimal::from_str("0.1").unwrap(),
+                conversion: None,
+            },
         ],
+        reserve_ratio: Decimal::zero(),
+        reserve_target: Uint128::zero(),
+        payout_mode: PayoutMode::Push,
+        auto_prepay: false,
+        park_idle_luna: false,
+        coverage_floor: None,
+        batch_unbonding: None,
+        desk: None,
+        ust_borrowing: None,
+        cross_lsd: None,
     }
 }
 
@@ -69,19 +121,98 @@ fn setup_test() -> OwnedDeps<MockStorage, MockApi, CustomQuerier> {
     deps.querier.set_red_bank_address("red_bank");
     deps.querier.set_red_bank_user_debt(MOCK_CONTRACT_ADDR, asset, debt);
 
+    // Set up bLuna Hub
+    deps.querier.set_hub_address("bluna_hub");
+    deps.querier.set_hub_current_batch(2);
+
     deps
 }
 
+fn set_user_debt(deps: &mut OwnedDeps<MockStorage, MockApi, CustomQuerier>, amount: u128) {
+    let asset = LegacyAsset::Native {
+        denom: String::from("uluna"),
+    };
+    let debt = UserAssetDebtResponse {
+        denom: String::from("uluna"),
+        asset_label: String::from("uluna"),
+        asset_reference: String::from("uluna").as_bytes().to_vec(),
+        asset_type: LegacyAssetType::Native,
+        amount_scaled: Uint128::new(amount),
+        amount: Uint128::new(amount),
+    };
+    deps.querier.set_red_bank_user_debt(MOCK_CONTRACT_ADDR, asset, debt);
+}
+
+fn set_ust_debt(deps: &mut OwnedDeps<MockStorage, MockApi, CustomQuerier>, amount: u128) {
+    let asset = LegacyAsset::Native {
+        denom: String::from("uusd"),
+    };
+    let debt = UserAssetDebtResponse {
+        denom: String::from("uusd"),
+        asset_label: String::from("uusd"),
+        asset_reference: String::from("uusd").as_bytes().to_vec(),
+        asset_type: LegacyAssetType::Native,
+        amount_scaled: Uint128::new(amount),
+        amount: Uint128::new(amount),
+    };
+    deps.querier.set_red_bank_user_debt(MOCK_CONTRACT_ADDR, asset, debt);
+}
+
+fn save_ust_position(storage: &mut dyn Storage, id: u64, ust_borrowed: u128, unbonded: u128) {
+    POSITIONS
+        .save(
+            storage,
+            U64Key::new(id),
+            &Position {
+                id,
+                borrowed: Uint128::zero(),
+                ust_borrowed: Uint128::new(ust_borrowed),
+                unbonded: Uint128::new(unbonded),
+                batch_id: 1,
+                held: false,
+                opened_at: 0,
+                profit_shares: create_config().profit_shares,
+                settlement: None,
+                exit: None,
+            },
+        )
+        .unwrap();
+    POSITION_COUNT.save(storage, &id).unwrap();
+}
+
+fn save_position(storage: &mut dyn Storage, id: u64, borrowed: u128, unbonded: u128, batch_id: u64) {
+    POSITIONS
+        .save(
+            storage,
+            U64Key::new(id),
+            &Position {
+                id,
+                borrowed: Uint128::new(borrowed),
+                ust_borrowed: Uint128::zero(),
+                unbonded: Uint128::new(unbonded),
+                batch_id,
+                held: false,
+                opened_at: 0,
+                profit_shares: create_config().profit_shares,
+                settlement: None,
+                exit: None,
+            },
+        )
+        .unwrap();
+    POSITION_COUNT.save(storage, &id).unwrap();
+}
+
 #[test]
 fn proper_instantiation() {
     let mut deps = setup_test();
 
     // Invalid config: The sum of shares cannot be greater than one
     let mut invalid_config = create_config();
-    invalid_config.profit_shares.push((
-        Addr::unchecked("charlie"),
-        Decimal::from_str("0.8").unwrap(),
-    ));
+    invalid_config.profit_shares.push(ProfitShare {
+        recipient: Recipient::Address(Addr::unchecked("charlie")),
+        share: Decimal::from_str("0.8").unwrap(),
+        conversion: None,
+    });
 
     let err = instantiate(
         deps.as_mut(),
@@ -95,6 +226,54 @@ fn proper_instantiation() {
         StdError::generic_err("total shares 1.1 is greater than one")
     );
 
+    // Invalid config: The reserve ratio cannot be greater than one
+    let mut invalid_config = create_config();
+    invalid_config.reserve_ratio = Decimal::from_str("1.5").unwrap();
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("reserve ratio 1.5 is greater than one")
+    );
+
+    // Invalid config: The protocol fee rate cannot be greater than one
+    let mut invalid_config = create_config();
+    invalid_config.protocol_fee.rate = Decimal::from_str("1.2").unwrap();
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("protocol fee rate 1.2 is greater than one")
+    );
+
+    // Invalid config: The keeper bounty rate cannot be greater than one
+    let mut invalid_config = create_config();
+    invalid_config.keeper_bounty.rate = Decimal::from_str("1.1").unwrap();
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("keeper bounty rate 1.1 is greater than one")
+    );
+
     // Valid config: The config should have been saved in storage and can be queried
     let res: Config<String> = query_helper(deps.as_ref(), QueryMsg::Config {});
     assert_eq!(res, create_config().into());
@@ -107,6 +286,7 @@ fn executing_arb() {
     let msg = ExecuteMsg::ExecuteArb {
         amount: Uint128::new(100_000_000_000),
         minimum_profit: Decimal::from_str("0.05").unwrap(),
+        hold_ratio: None,
     };
 
     // Non-owner cannot call
@@ -167,9 +347,114 @@ fn executing_arb() {
         }
     );
 
-    // Minimum receive amount should have been saved
+    // Minimum receive amount and borrowed amount should have been saved
     let minimum_receive = MINIMUM_RECEIVE.load(deps.as_ref().storage).unwrap();
     assert_eq!(minimum_receive, Uint128::new(105_000_000_000));
+    let borrowed = PENDING_BORROWED.load(deps.as_ref().storage).unwrap();
+    assert_eq!(borrowed, Uint128::new(100_000_000_000));
+}
+
+#[test]
+fn triggering_arb() {
+    let mut deps = setup_test();
+
+    // Strategy is not set
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("keeper", &[]),
+        ExecuteMsg::TriggerArb {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("strategy is not set"));
+
+    let mut config = create_config();
+    config.strategy = Some(Strategy {
+        minimum_profit: Decimal::from_str("0.05").unwrap(),
+        max_size: Uint128::new(100_000_000_000),
+        cooldown: 3600,
+        bounty: Uint128::new(1_000_000),
+    });
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+    RESERVE.save(deps.as_mut().storage, &Uint128::new(600_000)).unwrap();
+
+    // bLuna trades at a discount. Swapping 100 Luna would not make 5% profit due to price impact,
+    // so the arb is sized down to ~47.56 Luna
+    deps.querier.set_pair_address("bluna_pair");
+    deps.querier.set_pair_pools(Uint128::new(1_000_000_000_000), Uint128::new(1_100_000_000_000));
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("keeper", &[]),
+        ExecuteMsg::TriggerArb {},
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 3);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::Borrow {
+                    asset: mars_core::asset::Asset::Native {
+                        denom: String::from("uluna")
+                    },
+                    amount: Uint128::new(47_558_593_750),
+                    recipient: None,
+                })
+                .unwrap(),
+                funds: vec![]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never,
+        }
+    );
+    // The bounty is paid as far as the reserve allows
+    assert_eq!(
+        res.messages[2],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("keeper"),
+                amount: vec![Coin::new(600_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    let minimum_receive = MINIMUM_RECEIVE.load(deps.as_ref().storage).unwrap();
+    assert_eq!(minimum_receive, Uint128::new(49_936_523_437));
+    let reserve = RESERVE.load(deps.as_ref().storage).unwrap();
+    assert_eq!(reserve, Uint128::zero());
+
+    // Cannot trigger again before cooldown ends
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("keeper", &[]),
+        ExecuteMsg::TriggerArb {},
+    )
+    .unwrap_err();
+    let cooldown_end = mock_env().block.time.seconds() + 3600;
+    assert_eq!(err, StdError::generic_err(format!("cooldown ends at {}", cooldown_end)));
+
+    // After cooldown, the discount has closed
+    deps.querier.set_pair_pools(Uint128::new(1_000_000_000_000), Uint128::new(1_000_000_000_000));
+
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(3600);
+
+    let err = execute(
+        deps.as_mut(),
+        env,
+        mock_info("keeper", &[]),
+        ExecuteMsg::TriggerArb {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("no arb makes minimum profit 0.05"));
 }
 
 #[test]
@@ -215,6 +500,9 @@ fn replying_after_swap() {
     MINIMUM_RECEIVE
         .save(deps.as_mut().storage, &Uint128::new(105_000_000_000))
         .unwrap();
+    PENDING_BORROWED
+        .save(deps.as_mut().storage, &Uint128::new(100_000_000_000))
+        .unwrap();
 
     let mut valid_response = invalid_response.clone();
     valid_response.events[0].attributes[4].value = String::from("108000000000");
@@ -247,6 +535,25 @@ fn replying_after_swap() {
             reply_on: ReplyOn::Never
         }
     );
+
+    // A position should have been recorded, with the bLuna unbonded in the current batch
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(
+        position,
+        Position {
+            id: 1,
+            borrowed: Uint128::new(100_000_000_000),
+            ust_borrowed: Uint128::zero(),
+            unbonded: Uint128::new(108_000_000_000),
+            batch_id: 2,
+            held: false,
+            opened_at: mock_env().block.time.seconds(),
+            profit_shares: create_config().profit_shares,
+            settlement: None,
+            exit: None,
+        }
+    );
+    assert!(PENDING_BORROWED.may_load(deps.as_ref().storage).unwrap().is_none());
 }
 
 #[test]
@@ -274,12 +581,23 @@ fn finalizing_arb() {
             reply_on: ReplyOn::Success
         }
     );
+
+    let keeper = KEEPER.load(deps.as_ref().storage).unwrap();
+    assert_eq!(keeper, Addr::unchecked("anyone"));
 }
 
 #[test]
 fn replying_after_withdrawal() {
     let mut deps = setup_test();
 
+    // Position 1 has finished unbonding; position 2 is still unbonding
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    save_position(deps.as_mut().storage, 2, 0, 1_000_000_000, 2);
+    deps.querier.set_hub_unbond_requests(
+        MOCK_CONTRACT_ADDR,
+        vec![(2, Uint128::new(1_000_000_000))],
+    );
+
     let response = SubMsgExecutionResponse {
         events: vec![Event::new("from_contract")
             .add_attribute("action", "finish_burn")
@@ -351,4 +669,4468 @@ fn replying_after_withdrawal() {
             reply_on: ReplyOn::Never
         }
     );
+
+    // Position 1 should have been settled; position 2 should still be open
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(
+        position.settlement,
+        Some(Settlement {
+            settled_at: mock_env().block.time.seconds(),
+            received: Uint128::new(105_000_000_000),
+            debt: Uint128::new(101_000_000_000),
+            repaid: Uint128::new(101_000_000_000),
+            shortfall: Uint128::zero(),
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: Uint128::new(4_000_000_000),
+        })
+    );
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 2 });
+    assert_eq!(position.settlement, None);
+}
+
+#[test]
+fn covering_shortfall() {
+    let mut deps = setup_test();
+
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    RESERVE.save(deps.as_mut().storage, &Uint128::new(300_000_000)).unwrap();
+    FIRST_LOSS_CAPITAL
+        .save(deps.as_mut().storage, &Uint128::new(600_000_000))
+        .unwrap();
+
+    // Unbonding returns 100 Luna, less than the 101 Luna debt
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "100000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // The 1 Luna shortfall is partially covered by the 0.3 Luna reserve and the 0.6 Luna first-loss
+    // capital
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(100_900_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert!(res
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "shortfall" && attr.value == "native:uluna:1000000000"));
+
+    // The remaining 0.1 Luna should have been recorded as bad debt
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(position.settlement.unwrap().shortfall, Uint128::new(1_000_000_000));
+    let reserve = RESERVE.load(deps.as_ref().storage).unwrap();
+    assert_eq!(reserve, Uint128::zero());
+    let first_loss_capital = FIRST_LOSS_CAPITAL.load(deps.as_ref().storage).unwrap();
+    assert_eq!(first_loss_capital, Uint128::zero());
+    let bad_debt = BAD_DEBT.load(deps.as_ref().storage).unwrap();
+    assert_eq!(bad_debt, Uint128::new(100_000_000));
+
+    // The part of the shortfall not absorbed by the reserve, i.e. the first-loss capital drawn and
+    // the bad debt, should have been carried forward as loss
+    let carried_loss = CARRIED_LOSS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(carried_loss, Uint128::new(700_000_000));
+    let first_loss_drawn = FIRST_LOSS_DRAWN.load(deps.as_ref().storage).unwrap();
+    assert_eq!(first_loss_drawn, Uint128::new(600_000_000));
+}
+
+#[test]
+fn depositing_first_loss() {
+    let mut deps = setup_test();
+
+    BAD_DEBT.save(deps.as_mut().storage, &Uint128::new(400_000_000)).unwrap();
+
+    // Non-owner cannot call
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("non_owner", &[Coin::new(1_000_000_000, "uluna")]),
+        ExecuteMsg::DepositFirstLoss {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    // Owner must send Luna
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::DepositFirstLoss {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("no luna deposited"));
+
+    // The deposit should be first used to repay bad debt
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[Coin::new(1_000_000_000, "uluna")]),
+        ExecuteMsg::DepositFirstLoss {},
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(400_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    // The rest should be kept as first-loss capital
+    let first_loss_capital = FIRST_LOSS_CAPITAL.load(deps.as_ref().storage).unwrap();
+    assert_eq!(first_loss_capital, Uint128::new(600_000_000));
+    let bad_debt = BAD_DEBT.load(deps.as_ref().storage).unwrap();
+    assert_eq!(bad_debt, Uint128::zero());
+
+    // The part used to repay bad debt is to be replenished once the loss is recovered
+    let first_loss_drawn = FIRST_LOSS_DRAWN.load(deps.as_ref().storage).unwrap();
+    assert_eq!(first_loss_drawn, Uint128::new(400_000_000));
+}
+
+#[test]
+fn rolling_over() {
+    let mut deps = setup_test();
+
+    let msg = ExecuteMsg::Rollover {
+        minimum_profit: Decimal::from_str("0.05").unwrap(),
+        reinvest_ratio: Decimal::from_str("0.5").unwrap(),
+    };
+
+    // Non-owner cannot call
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("non_owner", &[]),
+        msg.clone(),
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    // Reinvest ratio cannot be greater than one
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Rollover {
+            minimum_profit: Decimal::from_str("0.05").unwrap(),
+            reinvest_ratio: Decimal::from_str("1.5").unwrap(),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("reinvest ratio 1.5 is greater than one")
+    );
+
+    // Owner can call
+    let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 3,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("bluna_hub"),
+                msg: to_binary(&basset::hub::ExecuteMsg::WithdrawUnbonded {}).unwrap(),
+                funds: vec![]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Success
+        }
+    );
+
+    // Rollover parameters should have been saved
+    let params = ROLLOVER_PARAMS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(
+        params,
+        (Decimal::from_str("0.05").unwrap(), Decimal::from_str("0.5").unwrap())
+    );
+}
+
+#[test]
+fn replying_after_rollover_withdrawal() {
+    let mut deps = setup_test();
+
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+
+    ROLLOVER_PARAMS
+        .save(
+            deps.as_mut().storage,
+            &(Decimal::from_str("0.05").unwrap(), Decimal::from_str("0.5").unwrap()),
+        )
+        .unwrap();
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 3,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 1,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("bluna_pair"),
+                msg: to_binary(&astroport::pair::ExecuteMsg::Swap {
+                    offer_asset: astroport::asset::Asset {
+                        info: astroport::asset::AssetInfo::NativeToken {
+                            denom: String::from("uluna")
+                        },
+                        amount: Uint128::new(103_000_000_000) // 101_000_000_000 + 4_000_000_000 * 0.5
+                    },
+                    belief_price: None,
+                    max_spread: None,
+                    to: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(103_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Success
+        }
+    );
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(400_000_000, "uluna")] // 2_000_000_000 * 0.2
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[2],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("bob"),
+                amount: vec![Coin::new(200_000_000, "uluna")] // 2_000_000_000 * 0.1
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[3],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("owner"),
+                amount: vec![Coin::new(1_400_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    // Rollover parameters should have been removed; minimum receive amount should have been saved
+    assert!(ROLLOVER_PARAMS.may_load(deps.as_ref().storage).unwrap().is_none());
+    let minimum_receive = MINIMUM_RECEIVE.load(deps.as_ref().storage).unwrap();
+    assert_eq!(minimum_receive, Uint128::new(108_150_000_000));
+
+    // The debt rolled over, excluding the reinvested profit, should back the new position
+    let borrowed = PENDING_BORROWED.load(deps.as_ref().storage).unwrap();
+    assert_eq!(borrowed, Uint128::new(101_000_000_000));
+}
+
+#[test]
+fn retaining_profit_in_reserve() {
+    let mut deps = setup_test();
+
+    let mut config = create_config();
+    config.reserve_ratio = Decimal::from_str("0.25").unwrap();
+    config.reserve_target = Uint128::new(1_500_000_000);
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    RESERVE.save(deps.as_mut().storage, &Uint128::new(1_000_000_000)).unwrap();
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // 25% of the 4 Luna profit is 1 Luna, but only 0.5 Luna is needed to reach the target. The
+    // remaining 3.5 Luna is distributed.
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(700_000_000, "uluna")] // 3_500_000_000 * 0.2
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[2],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("bob"),
+                amount: vec![Coin::new(350_000_000, "uluna")] // 3_500_000_000 * 0.1
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[3],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("owner"),
+                amount: vec![Coin::new(2_450_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    let res: ReserveResponse = query_helper(
+        deps.as_ref(),
+        QueryMsg::Reserve {
+            start_after: None,
+            limit: None,
+        },
+    );
+    assert_eq!(
+        res,
+        ReserveResponse {
+            balance: Uint128::new(1_500_000_000),
+            target: Uint128::new(1_500_000_000),
+            history: vec![ReserveChange {
+                id: 1,
+                timestamp: mock_env().block.time.seconds(),
+                kind: ReserveChangeKind::ProfitRetained,
+                amount: Uint128::new(500_000_000),
+                balance: Uint128::new(1_500_000_000),
+            }],
+        }
+    );
+}
+
+#[test]
+fn recovering_carried_loss() {
+    let mut deps = setup_test();
+
+    CARRIED_LOSS.save(deps.as_mut().storage, &Uint128::new(1_000_000_000)).unwrap();
+    BAD_DEBT.save(deps.as_mut().storage, &Uint128::new(400_000_000)).unwrap();
+    FIRST_LOSS_DRAWN.save(deps.as_mut().storage, &Uint128::new(400_000_000)).unwrap();
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+
+    // The position's debt is 101.4 Luna, minus 0.4 Luna bad debt
+    set_user_debt(&mut deps, 101_400_000_000);
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // 1 Luna of the 4 Luna profit is used to recover the carried loss, of which 0.4 Luna repays the
+    // bad debt. The remaining 3 Luna is distributed.
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(101_400_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(600_000_000, "uluna")] // 3_000_000_000 * 0.2
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[2],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("bob"),
+                amount: vec![Coin::new(300_000_000, "uluna")] // 3_000_000_000 * 0.1
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[3],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("owner"),
+                amount: vec![Coin::new(2_100_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    // The carried loss and bad debt should have been cleared, and the first-loss capital drawn
+    // replenished; the rest of the recovered Luna goes to the reserve
+    let carried_loss = CARRIED_LOSS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(carried_loss, Uint128::zero());
+    let bad_debt = BAD_DEBT.load(deps.as_ref().storage).unwrap();
+    assert_eq!(bad_debt, Uint128::zero());
+    let first_loss_capital = FIRST_LOSS_CAPITAL.load(deps.as_ref().storage).unwrap();
+    assert_eq!(first_loss_capital, Uint128::new(400_000_000));
+    let reserve = RESERVE.load(deps.as_ref().storage).unwrap();
+    assert_eq!(reserve, Uint128::new(200_000_000));
+}
+
+#[test]
+fn updating_config() {
+    let mut deps = setup_test();
+
+    let mut new_config = create_config();
+    new_config.profit_shares = vec![ProfitShare {
+        recipient: Recipient::Address(Addr::unchecked("charlie")),
+        share: Decimal::from_str("0.5").unwrap(),
+        conversion: None,
+    }];
+
+    // Non-owner cannot call
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("non_owner", &[]),
+        ExecuteMsg::UpdateConfig {
+            config: new_config.clone().into(),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    // Owner cannot change Red Bank, council or the protocol fee rate
+    let mut configs = vec![new_config.clone(), new_config.clone(), new_config.clone()];
+    configs[0].red_bank = Addr::unchecked("another_red_bank");
+    configs[1].council = Some(Addr::unchecked("council"));
+    configs[2].protocol_fee.rate = Decimal::from_str("0.1").unwrap();
+    for config in configs {
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::UpdateConfig {
+                config: config.into(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            StdError::generic_err("red bank, council and protocol fee rate cannot be changed")
+        );
+    }
+
+    // Owner can call
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::UpdateConfig {
+            config: new_config.clone().into(),
+        },
+    )
+    .unwrap();
+
+    let res: Config<String> = query_helper(deps.as_ref(), QueryMsg::Config {});
+    assert_eq!(res, new_config.into());
+}
+
+#[test]
+fn distributing_profit_by_snapshot() {
+    let mut deps = setup_test();
+
+    // Profit shares are changed after position 1 was opened
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    let mut config = create_config();
+    config.profit_shares = vec![ProfitShare {
+        recipient: Recipient::Address(Addr::unchecked("charlie")),
+        share: Decimal::from_str("0.5").unwrap(),
+        conversion: None,
+    }];
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // The profit should be distributed according to the shares in force when position 1 was opened
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(800_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[2],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("bob"),
+                amount: vec![Coin::new(400_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[3],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("owner"),
+                amount: vec![Coin::new(2_800_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+}
+
+#[test]
+fn claiming_profit() {
+    let mut deps = setup_test();
+
+    let mut config = create_config();
+    config.payout_mode = PayoutMode::Pull;
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // Only the repayment should be sent; the profit should be credited instead
+    assert_eq!(res.messages.len(), 1);
+
+    let res: ClaimableResponse = query_helper(
+        deps.as_ref(),
+        QueryMsg::Claimable {
+            address: String::from("alice"),
+        },
+    );
+    assert_eq!(res.amount, Uint128::new(800_000_000));
+
+    let res: ClaimableResponse = query_helper(
+        deps.as_ref(),
+        QueryMsg::Claimable {
+            address: String::from("owner"),
+        },
+    );
+    assert_eq!(res.amount, Uint128::new(2_800_000_000));
+
+    // Alice claims her profit
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("alice", &[]),
+        ExecuteMsg::ClaimProfit {},
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(800_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    // Alice cannot claim again
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("alice", &[]),
+        ExecuteMsg::ClaimProfit {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("no profit to claim"));
+}
+
+#[test]
+fn converting_payout() {
+    let mut deps = setup_test();
+    deps.querier.set_pair_address("bluna_pair");
+    deps.querier.set_other_pair_pools(
+        "lsd_pair",
+        &[("bluna_token", 1_000_000_000_000), ("stluna_token", 1_000_000_000_000)],
+    );
+
+    // Invalid config: The conversion pair must trade Luna
+    let mut invalid_config = create_config();
+    invalid_config.profit_shares[0].conversion = Some(Conversion {
+        pair: Addr::unchecked("lsd_pair"),
+        max_spread: Decimal::from_str("0.01").unwrap(),
+    });
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("conversion pair lsd_pair does not trade uluna"));
+
+    // Invalid config: Conversions are not supported in pull payout mode
+    let mut invalid_config = create_config();
+    invalid_config.profit_shares[0].conversion = Some(Conversion {
+        pair: Addr::unchecked("bluna_pair"),
+        max_spread: Decimal::from_str("0.01").unwrap(),
+    });
+    invalid_config.payout_mode = PayoutMode::Pull;
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("conversion for recipient alice is not supported in pull payout mode")
+    );
+
+    // Alice prefers to be paid in bLuna
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    POSITIONS
+        .update(deps.as_mut().storage, U64Key::new(1), |position| -> StdResult<_> {
+            let mut position = position.unwrap();
+            position.profit_shares[0].conversion = Some(Conversion {
+                pair: Addr::unchecked("bluna_pair"),
+                max_spread: Decimal::from_str("0.01").unwrap(),
+            });
+            Ok(position)
+        })
+        .unwrap();
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // Alice's Luna should be swapped for bLuna and sent to her by the pair
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 4,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("bluna_pair"),
+                msg: to_binary(&astroport::pair::ExecuteMsg::Swap {
+                    offer_asset: astroport::asset::Asset {
+                        info: astroport::asset::AssetInfo::NativeToken {
+                            denom: String::from("uluna")
+                        },
+                        amount: Uint128::new(800_000_000)
+                    },
+                    belief_price: None,
+                    max_spread: Some(Decimal::from_str("0.01").unwrap()),
+                    to: Some(String::from("alice"))
+                })
+                .unwrap(),
+                funds: vec![Coin::new(800_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Always
+        }
+    );
+
+    let pending_payouts = PENDING_PAYOUTS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(
+        pending_payouts,
+        vec![(
+            Addr::unchecked("alice"),
+            Some(Addr::unchecked("bluna_pair")),
+            Uint128::new(800_000_000)
+        )]
+    );
+
+    // The payout should be recorded once the swap is completed
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "uluna")
+            .add_attribute("ask_asset", "bluna_token")
+            .add_attribute("offer_amount", "800000000")
+            .add_attribute("return_amount", "840000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 4,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.attributes[1..],
+        [
+            attr("recipient", "alice"),
+            attr("asset_paid", "native:uluna:800000000"),
+            attr("asset_converted", "cw20:bluna_token:840000000"),
+        ]
+    );
+    assert!(PENDING_PAYOUTS.may_load(deps.as_ref().storage).unwrap().is_none());
+
+    // If the swap fails, e.g. due to slippage, Alice should be sent Luna instead
+    PENDING_PAYOUTS
+        .save(
+            deps.as_mut().storage,
+            &vec![(
+                Addr::unchecked("alice"),
+                Some(Addr::unchecked("bluna_pair")),
+                Uint128::new(800_000_000),
+            )],
+        )
+        .unwrap();
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 4,
+            result: ContractResult::Err(String::from("Operation exceeds max spread limit")),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(BankMsg::Send {
+            to_address: String::from("alice"),
+            amount: vec![Coin::new(800_000_000, "uluna")]
+        })]
+    );
+    assert_eq!(
+        res.attributes[1..],
+        [
+            attr("recipient", "alice"),
+            attr("asset_paid", "native:uluna:800000000"),
+            attr("payout_error", "Operation exceeds max spread limit"),
+        ]
+    );
+    assert!(PENDING_PAYOUTS.may_load(deps.as_ref().storage).unwrap().is_none());
+}
+
+#[test]
+fn paying_contract_recipient() {
+    let mut deps = setup_test();
+
+    let recipient = Recipient::Contract {
+        contract: Addr::unchecked("staking"),
+        msg: Binary::from(br#"{"bond":{}}"#.to_vec()),
+    };
+
+    // Invalid config: The message must be valid JSON
+    let mut invalid_config = create_config();
+    invalid_config.profit_shares[0].recipient = Recipient::Contract {
+        contract: Addr::unchecked("staking"),
+        msg: Binary::from(b"not json".to_vec()),
+    };
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("message for contract staking is not valid JSON")
+    );
+
+    // Invalid config: Contract recipients cannot have conversions
+    let mut invalid_config = create_config();
+    invalid_config.profit_shares[0].recipient = recipient.clone();
+    invalid_config.profit_shares[0].conversion = Some(Conversion {
+        pair: Addr::unchecked("bluna_pair"),
+        max_spread: Decimal::from_str("0.01").unwrap(),
+    });
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("conversion is not supported for contract recipient staking")
+    );
+
+    // Invalid config: Contract recipients are not supported in pull payout mode
+    let mut invalid_config = create_config();
+    invalid_config.profit_shares[0].recipient = recipient.clone();
+    invalid_config.payout_mode = PayoutMode::Pull;
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("contract recipient staking is not supported in pull payout mode")
+    );
+
+    // Alice's share goes to a staking contract instead
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    POSITIONS
+        .update(deps.as_mut().storage, U64Key::new(1), |position| -> StdResult<_> {
+            let mut position = position.unwrap();
+            position.profit_shares[0].recipient = recipient.clone();
+            Ok(position)
+        })
+        .unwrap();
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response.clone()),
+        },
+    )
+    .unwrap();
+
+    // The staking contract should be executed with the message, and the Luna attached
+    let staking_msg = SubMsg {
+        id: 4,
+        msg: CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: String::from("staking"),
+            msg: Binary::from(br#"{"bond":{}}"#.to_vec()),
+            funds: vec![Coin::new(800_000_000, "uluna")],
+        }),
+        gas_limit: None,
+        reply_on: ReplyOn::Always,
+    };
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(res.messages[1], staking_msg);
+
+    let pending_payouts = PENDING_PAYOUTS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(
+        pending_payouts,
+        vec![(Addr::unchecked("staking"), None, Uint128::new(800_000_000))]
+    );
+
+    // If the execution fails, the Luna should be sent to the contract with a plain transfer
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 4,
+            result: ContractResult::Err(String::from("unknown variant `bond`")),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(BankMsg::Send {
+            to_address: String::from("staking"),
+            amount: vec![Coin::new(800_000_000, "uluna")]
+        })]
+    );
+    assert!(res.attributes.contains(&attr("payout_error", "unknown variant `bond`")));
+    assert!(PENDING_PAYOUTS.may_load(deps.as_ref().storage).unwrap().is_none());
+
+    // Payout mode is changed to pull after position 2 was opened. The contract can't claim, so its
+    // profit should still be pushed, while Bob's is credited.
+    save_position(deps.as_mut().storage, 2, 100_000_000_000, 108_000_000_000, 1);
+    POSITIONS
+        .update(deps.as_mut().storage, U64Key::new(2), |position| -> StdResult<_> {
+            let mut position = position.unwrap();
+            position.profit_shares[0].recipient = recipient.clone();
+            Ok(position)
+        })
+        .unwrap();
+    let mut config = create_config();
+    config.payout_mode = PayoutMode::Pull;
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert!(res.messages.contains(&staking_msg));
+    let claimable = CLAIMABLE.may_load(deps.as_ref().storage, &Addr::unchecked("staking")).unwrap();
+    assert!(claimable.is_none());
+    let claimable = CLAIMABLE.may_load(deps.as_ref().storage, &Addr::unchecked("bob")).unwrap();
+    assert!(claimable.is_some());
+}
+
+#[test]
+fn charging_protocol_fee() {
+    let mut deps = setup_test();
+
+    // The fee rate and treasury are specified in config
+    let mut config = create_config();
+    config.protocol_fee.rate = Decimal::from_str("0.05").unwrap();
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response.clone()),
+        },
+    )
+    .unwrap();
+
+    // The fee is charged on the 4 Luna profit before profit shares are applied
+    assert_eq!(res.messages.len(), 5);
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("treasury"),
+                amount: vec![Coin::new(200_000_000, "uluna")] // 4_000_000_000 * 0.05
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[2],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(760_000_000, "uluna")] // 3_800_000_000 * 0.2
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert!(res.attributes.contains(&attr("protocol_fee", "native:uluna:200000000")));
+
+    // The treasury is not specified, and is queried from Mars instead
+    let mut config = create_config();
+    config.protocol_fee = ProtocolFee {
+        rate: Decimal::from_str("0.1").unwrap(),
+        treasury: None,
+    };
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    deps.querier.set_red_bank_config(RedBankConfigResponse {
+        owner: Addr::unchecked("council"),
+        address_provider_address: Addr::unchecked("address_provider"),
+        ma_token_code_id: 1,
+        market_count: 1,
+        close_factor: MarsDecimal::from_str("0.5").unwrap(),
+    });
+    deps.querier.set_address_provider_address("address_provider");
+    deps.querier.set_mars_contract_address(MarsContract::Treasury, "mars_treasury");
+
+    save_position(deps.as_mut().storage, 2, 100_000_000_000, 108_000_000_000, 1);
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    assert_eq!(res.messages.len(), 5);
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("mars_treasury"),
+                amount: vec![Coin::new(400_000_000, "uluna")] // 4_000_000_000 * 0.1
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert!(res.attributes.contains(&attr("protocol_fee", "native:uluna:400000000")));
+}
+
+#[test]
+fn paying_keeper_bounty() {
+    let mut deps = setup_test();
+
+    let mut config = create_config();
+    config.keeper_bounty = KeeperBounty {
+        rate: Decimal::from_str("0.1").unwrap(),
+        cap: Uint128::new(300_000_000),
+    };
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    // Nothing can be withdrawn yet, so finalizing would not pay
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    deps.querier.set_hub_unbond_requests(
+        MOCK_CONTRACT_ADDR,
+        vec![(1, Uint128::new(108_000_000_000))],
+    );
+
+    let res: FinalizableResponse = query_helper(deps.as_ref(), QueryMsg::Finalizable {});
+    assert_eq!(
+        res,
+        FinalizableResponse {
+            finalizable: false,
+            withdrawable: Uint128::zero(),
+            estimated_bounty: Uint128::zero(),
+        }
+    );
+
+    // Batch 1 is released
+    deps.querier.set_hub_withdrawable_unbonded(MOCK_CONTRACT_ADDR, Uint128::new(105_000_000_000));
+    deps.querier.set_hub_history(vec![basset::hub::UnbondHistory {
+        batch_id: 1,
+        time: 0,
+        amount: Uint128::new(108_000_000_000),
+        applied_exchange_rate: Decimal::from_str("0.972222222222222222").unwrap(),
+        withdraw_rate: Decimal::from_str("0.972222222222222222").unwrap(),
+        released: true,
+    }]);
+
+    // The 4 Luna profit would pay 0.4 Luna, which is capped at 0.3 Luna
+    let res: FinalizableResponse = query_helper(deps.as_ref(), QueryMsg::Finalizable {});
+    assert_eq!(
+        res,
+        FinalizableResponse {
+            finalizable: true,
+            withdrawable: Uint128::new(105_000_000_000),
+            estimated_bounty: Uint128::new(300_000_000),
+        }
+    );
+
+    // Without a bounty to be paid, finalizing is not worth a keeper's while
+    let mut config_without_bounty = config.clone();
+    config_without_bounty.keeper_bounty.rate = Decimal::zero();
+    CONFIG.save(deps.as_mut().storage, &config_without_bounty).unwrap();
+
+    let res: FinalizableResponse = query_helper(deps.as_ref(), QueryMsg::Finalizable {});
+    assert_eq!(
+        res,
+        FinalizableResponse {
+            finalizable: false,
+            withdrawable: Uint128::new(105_000_000_000),
+            estimated_bounty: Uint128::zero(),
+        }
+    );
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    // With only 100 Luna withdrawable, the position makes no profit
+    deps.querier.set_hub_withdrawable_unbonded(MOCK_CONTRACT_ADDR, Uint128::new(100_000_000_000));
+
+    let res: FinalizableResponse = query_helper(deps.as_ref(), QueryMsg::Finalizable {});
+    assert!(!res.finalizable);
+
+    // Debt is also attributed to the inventory, the same way as when the positions are settled.
+    // Half of the debt is attributed to the position, so it makes 49.5 Luna profit.
+    INVENTORY
+        .save(
+            deps.as_mut().storage,
+            &Inventory {
+                amount: Uint128::new(100_000_000_000),
+                cost_basis: Uint128::new(100_000_000_000),
+            },
+        )
+        .unwrap();
+
+    let res: FinalizableResponse = query_helper(deps.as_ref(), QueryMsg::Finalizable {});
+    assert_eq!(
+        res,
+        FinalizableResponse {
+            finalizable: true,
+            withdrawable: Uint128::new(100_000_000_000),
+            estimated_bounty: Uint128::new(300_000_000),
+        }
+    );
+
+    INVENTORY.save(deps.as_mut().storage, &Inventory::default()).unwrap();
+    deps.querier.set_hub_withdrawable_unbonded(MOCK_CONTRACT_ADDR, Uint128::new(105_000_000_000));
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("keeper", &[]),
+        ExecuteMsg::FinializeArb {},
+    )
+    .unwrap();
+
+    // The withdrawn batch is removed from the unbonding requests
+    deps.querier.set_hub_unbond_requests(MOCK_CONTRACT_ADDR, vec![]);
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // The bounty is paid before profit shares are applied
+    assert_eq!(res.messages.len(), 5);
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("keeper"),
+                amount: vec![Coin::new(300_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[2],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(740_000_000, "uluna")] // 3_700_000_000 * 0.2
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert!(res.attributes.contains(&attr("keeper_bounty", "native:uluna:300000000")));
+    assert!(KEEPER.may_load(deps.as_ref().storage).unwrap().is_none());
+}
+
+#[test]
+fn estimating_bounty_across_history_pages() {
+    let mut deps = setup_test();
+
+    let mut config = create_config();
+    config.keeper_bounty = KeeperBounty {
+        rate: Decimal::from_str("0.1").unwrap(),
+        cap: Uint128::new(300_000_000),
+    };
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    // Batches 1 and 150 are pending, and only batch 150 is released. bLuna Hub returns at most 100
+    // batches per history query, so batch 150 is only found on the second page.
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    save_position(deps.as_mut().storage, 2, 100_000_000_000, 108_000_000_000, 150);
+    deps.querier.set_hub_unbond_requests(
+        MOCK_CONTRACT_ADDR,
+        vec![(1, Uint128::new(108_000_000_000)), (150, Uint128::new(108_000_000_000))],
+    );
+    deps.querier.set_hub_withdrawable_unbonded(MOCK_CONTRACT_ADDR, Uint128::new(105_000_000_000));
+    deps.querier.set_hub_history(
+        (1..=150)
+            .map(|batch_id| basset::hub::UnbondHistory {
+                batch_id,
+                time: 0,
+                amount: Uint128::new(108_000_000_000),
+                applied_exchange_rate: Decimal::from_str("0.972222222222222222").unwrap(),
+                withdraw_rate: Decimal::from_str("0.972222222222222222").unwrap(),
+                released: batch_id == 150,
+            })
+            .collect(),
+    );
+
+    let res: FinalizableResponse = query_helper(deps.as_ref(), QueryMsg::Finalizable {});
+    assert_eq!(
+        res,
+        FinalizableResponse {
+            finalizable: true,
+            withdrawable: Uint128::new(105_000_000_000),
+            estimated_bounty: Uint128::new(300_000_000),
+        }
+    );
+}
+
+#[test]
+fn forcing_unwind() {
+    let mut deps = setup_test();
+
+    let mut config = create_config();
+    config.council = Some(Addr::unchecked("council"));
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    // Position 1 has finished unbonding; position 2 is still unbonding. The contract also holds
+    // some bLuna.
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    save_position(deps.as_mut().storage, 2, 50_000_000_000, 54_000_000_000, 2);
+    deps.querier.set_hub_withdrawable_unbonded(MOCK_CONTRACT_ADDR, Uint128::new(105_000_000_000));
+    deps.querier.set_cw20_balance("bluna_token", MOCK_CONTRACT_ADDR, Uint128::new(10_000_000_000));
+
+    // Accounts other than Red Bank and council cannot call
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::ForceUnwind {
+            sell_bluna: true,
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not red bank or council"));
+
+    // Council can call
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("council", &[]),
+        ExecuteMsg::ForceUnwind {
+            sell_bluna: true,
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 2,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("bluna_hub"),
+                msg: to_binary(&basset::hub::ExecuteMsg::WithdrawUnbonded {}).unwrap(),
+                funds: vec![]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Success
+        }
+    );
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 5,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("bluna_token"),
+                msg: to_binary(&Cw20ExecuteMsg::Send {
+                    contract: String::from("bluna_pair"),
+                    amount: Uint128::new(10_000_000_000),
+                    msg: to_binary(&astroport::pair::Cw20HookMsg::Swap {
+                        belief_price: None,
+                        max_spread: None,
+                        to: None
+                    })
+                    .unwrap()
+                })
+                .unwrap(),
+                funds: vec![]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Success
+        }
+    );
+    assert!(PAUSED.load(deps.as_ref().storage).unwrap());
+
+    // New arbs cannot be executed while paused
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::ExecuteArb {
+            amount: Uint128::new(100_000_000_000),
+            minimum_profit: Decimal::from_str("0.05").unwrap(),
+            hold_ratio: None,
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("contract is paused"));
+
+    // Position 1 is attributed 2/3 of the 101 Luna debt. Its profit is used to repay the remaining
+    // 1/3 before anything is distributed.
+    deps.querier.set_hub_unbond_requests(
+        MOCK_CONTRACT_ADDR,
+        vec![(2, Uint128::new(54_000_000_000))],
+    );
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "105000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(101_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(800_000_000, "uluna")] // 4_000_000_000 * 0.2
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert!(res.attributes.contains(&attr("profit_unwound", "native:uluna:33666666667")));
+
+    // Proceeds from selling bLuna are used to repay debt
+    set_user_debt(&mut deps, 54_000_000_000);
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "bluna_token")
+            .add_attribute("ask_asset", "uluna")
+            .add_attribute("offer_amount", "10000000000")
+            .add_attribute("return_amount", "10200000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 5,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(10_200_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    // Council can resume new arbs
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("council", &[]),
+        ExecuteMsg::Resume {},
+    )
+    .unwrap();
+    assert!(!PAUSED.load(deps.as_ref().storage).unwrap());
+}
+
+#[test]
+fn claiming_ownership() {
+    let mut deps = setup_test();
+
+    let mut config = create_config();
+    config.recovery = Some(Recovery {
+        address: Addr::unchecked("recovery"),
+        inactivity_timeout: 86400,
+    });
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+    CLAIMABLE
+        .save(deps.as_mut().storage, &Addr::unchecked("owner"), &Uint128::new(1_000_000))
+        .unwrap();
+
+    let instantiated_at = mock_env().block.time.seconds();
+    let env_at = |seconds: u64| {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(seconds);
+        env
+    };
+
+    // Non-recovery address cannot call
+    let err = execute(
+        deps.as_mut(),
+        env_at(100000),
+        mock_info("non_recovery", &[]),
+        ExecuteMsg::ClaimOwnership {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not recovery address"));
+
+    // Owner is active later on, which extends the timeout
+    execute(
+        deps.as_mut(),
+        env_at(50000),
+        mock_info("owner", &[]),
+        ExecuteMsg::ExecuteArb {
+            amount: Uint128::new(100_000_000_000),
+            minimum_profit: Decimal::from_str("0.05").unwrap(),
+            hold_ratio: None,
+        },
+    )
+    .unwrap();
+
+    let err = execute(
+        deps.as_mut(),
+        env_at(100000),
+        mock_info("recovery", &[]),
+        ExecuteMsg::ClaimOwnership {},
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err(format!(
+            "owner inactivity timeout ends at {}",
+            instantiated_at + 136400
+        ))
+    );
+
+    // Recovery address can claim ownership once the timeout has passed
+    execute(
+        deps.as_mut(),
+        env_at(136400),
+        mock_info("recovery", &[]),
+        ExecuteMsg::ClaimOwnership {},
+    )
+    .unwrap();
+
+    let config = CONFIG.load(deps.as_ref().storage).unwrap();
+    assert_eq!(config.owner, Addr::unchecked("recovery"));
+
+    // Profit credited to the previous owner is redirected
+    let res: ClaimableResponse = query_helper(
+        deps.as_ref(),
+        QueryMsg::Claimable {
+            address: String::from("owner"),
+        },
+    );
+    assert_eq!(res.amount, Uint128::zero());
+    let res: ClaimableResponse = query_helper(
+        deps.as_ref(),
+        QueryMsg::Claimable {
+            address: String::from("recovery"),
+        },
+    );
+    assert_eq!(res.amount, Uint128::new(1_000_000));
+}
+
+#[test]
+fn exiting_in_emergency() {
+    let mut deps = setup_test();
+
+    let mut config = create_config();
+    config.guardian = Some(Addr::unchecked("guardian"));
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    deps.querier.set_cw20_balance("bluna_token", MOCK_CONTRACT_ADDR, Uint128::new(10_000_000_000));
+    deps.querier.set_hub_exchange_rate(Decimal::one());
+
+    // Accounts other than owner and guardian cannot call
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("non_guardian", &[]),
+        ExecuteMsg::EmergencyExit {
+            max_spread: Decimal::from_str("0.02").unwrap(),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner or guardian"));
+
+    // Max spread cannot exceed Astroport's limit
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("guardian", &[]),
+        ExecuteMsg::EmergencyExit {
+            max_spread: Decimal::from_str("0.6").unwrap(),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("max spread 0.6 is greater than 0.5"));
+
+    // Guardian can call
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("guardian", &[]),
+        ExecuteMsg::EmergencyExit {
+            max_spread: Decimal::from_str("0.02").unwrap(),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 6,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("bluna_token"),
+                msg: to_binary(&Cw20ExecuteMsg::Send {
+                    contract: String::from("bluna_pair"),
+                    amount: Uint128::new(10_000_000_000),
+                    msg: to_binary(&astroport::pair::Cw20HookMsg::Swap {
+                        belief_price: None,
+                        max_spread: Some(Decimal::from_str("0.02").unwrap()),
+                        to: None
+                    })
+                    .unwrap()
+                })
+                .unwrap(),
+                funds: vec![]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Success
+        }
+    );
+
+    // The bLuna is sold at a 4% discount; the proceeds are used to repay debt
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "bluna_token")
+            .add_attribute("ask_asset", "uluna")
+            .add_attribute("offer_amount", "10000000000")
+            .add_attribute("return_amount", "9600000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 6,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(9_600_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    // The exit should have been recorded in the position ledger, along with its loss compared to
+    // unbonding the bLuna
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(
+        position.exit,
+        Some(Exit {
+            sold: Uint128::new(10_000_000_000),
+            expected: Uint128::new(10_000_000_000),
+            loss: Uint128::new(400_000_000),
+        })
+    );
+    assert_eq!(position.settlement.unwrap().repaid, Uint128::new(9_600_000_000));
+
+    // The loss compared to unbonding is only recorded in the ledger. The debt was fully repaid, so
+    // no loss is carried forward.
+    let carried_loss = CARRIED_LOSS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(carried_loss, Uint128::zero());
+}
+
+#[test]
+fn exiting_with_held_positions() {
+    let mut deps = setup_test();
+
+    // Position 1 holds 6 bLuna for batch unbonding, and is attributed 60 of the 101 Luna debt. The
+    // contract holds another 4 bLuna not backing any position.
+    save_position(deps.as_mut().storage, 1, 60_000_000_000, 6_000_000_000, 0);
+    POSITIONS
+        .update(deps.as_mut().storage, U64Key::new(1), |position| -> StdResult<_> {
+            let mut position = position.unwrap();
+            position.held = true;
+            Ok(position)
+        })
+        .unwrap();
+    save_position(deps.as_mut().storage, 2, 40_000_000_000, 40_000_000_000, 1);
+    deps.querier.set_cw20_balance("bluna_token", MOCK_CONTRACT_ADDR, Uint128::new(10_000_000_000));
+    deps.querier.set_hub_exchange_rate(Decimal::one());
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::EmergencyExit {
+            max_spread: Decimal::from_str("0.02").unwrap(),
+        },
+    )
+    .unwrap();
+
+    // The 10 bLuna is sold for 9.6 Luna
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "bluna_token")
+            .add_attribute("ask_asset", "uluna")
+            .add_attribute("offer_amount", "10000000000")
+            .add_attribute("return_amount", "9600000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 6,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // Position 1 is closed with its share of the sale and the debt, and records its part of the
+    // exit
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert!(!position.held);
+    assert_eq!(
+        position.settlement,
+        Some(Settlement {
+            settled_at: mock_env().block.time.seconds(),
+            received: Uint128::new(5_760_000_000),
+            debt: Uint128::new(60_600_000_000),
+            repaid: Uint128::new(5_760_000_000),
+            shortfall: Uint128::new(54_840_000_000),
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: Uint128::zero(),
+        })
+    );
+    assert_eq!(
+        position.exit,
+        Some(Exit {
+            sold: Uint128::new(6_000_000_000),
+            expected: Uint128::new(6_000_000_000),
+            loss: Uint128::new(240_000_000),
+        })
+    );
+
+    // Only the rest of the sale is recorded as a position of its own
+    assert!(res.attributes.contains(&attr("position_id", "3")));
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 3 });
+    assert_eq!(
+        position.exit,
+        Some(Exit {
+            sold: Uint128::new(4_000_000_000),
+            expected: Uint128::new(4_000_000_000),
+            loss: Uint128::new(160_000_000),
+        })
+    );
+    let settlement = position.settlement.unwrap();
+    assert_eq!(settlement.received, Uint128::new(3_840_000_000));
+    assert_eq!(settlement.repaid, Uint128::new(3_840_000_000));
+}
+
+#[test]
+fn exiting_with_bad_debt() {
+    let mut deps = setup_test();
+
+    // Of the 250 Luna debt, 50 has gone bad. Position 1 holds 100 bLuna for batch unbonding, and
+    // position 2 is still unbonding; each is attributed 100 of the debt.
+    set_user_debt(&mut deps, 250_000_000_000);
+    BAD_DEBT.save(deps.as_mut().storage, &Uint128::new(50_000_000_000)).unwrap();
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 100_000_000_000, 0);
+    POSITIONS
+        .update(deps.as_mut().storage, U64Key::new(1), |position| -> StdResult<_> {
+            let mut position = position.unwrap();
+            position.held = true;
+            Ok(position)
+        })
+        .unwrap();
+    save_position(deps.as_mut().storage, 2, 100_000_000_000, 110_000_000_000, 1);
+    deps.querier.set_cw20_balance("bluna_token", MOCK_CONTRACT_ADDR, Uint128::new(100_000_000_000));
+    deps.querier.set_hub_exchange_rate(Decimal::one());
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::EmergencyExit {
+            max_spread: Decimal::from_str("0.02").unwrap(),
+        },
+    )
+    .unwrap();
+
+    //------------------------------------------------------------
+    // Test 1. The proceeds repay position 1's debt only, so the bad debt is still owed
+    //
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "bluna_token")
+            .add_attribute("ask_asset", "uluna")
+            .add_attribute("offer_amount", "100000000000")
+            .add_attribute("return_amount", "100000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 6,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(100_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }]
+    );
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    let settlement = position.settlement.unwrap();
+    assert_eq!(settlement.debt, Uint128::new(100_000_000_000));
+    assert_eq!(settlement.repaid, Uint128::new(100_000_000_000));
+    assert_eq!(BAD_DEBT.load(deps.as_ref().storage).unwrap(), Uint128::new(50_000_000_000));
+
+    //------------------------------------------------------------
+    // Test 2. Position 2 is attributed only its own debt when it finishes unbonding
+    //
+    set_user_debt(&mut deps, 150_000_000_000);
+    deps.querier.set_hub_unbond_requests(MOCK_CONTRACT_ADDR, vec![]);
+
+    reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(SubMsgExecutionResponse {
+                events: vec![Event::new("from_contract")
+                    .add_attribute("action", "finish_burn")
+                    .add_attribute("from", "bluna_hub")
+                    .add_attribute("amount", "110000000000")],
+                data: None,
+            }),
+        },
+    )
+    .unwrap();
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 2 });
+    let settlement = position.settlement.unwrap();
+    assert_eq!(settlement.debt, Uint128::new(100_000_000_000));
+    assert_eq!(settlement.shortfall, Uint128::zero());
+    assert_eq!(settlement.profit, Uint128::new(10_000_000_000));
+}
+
+#[test]
+fn exiting_with_held_ust_positions() {
+    let mut deps = setup_test();
+
+    CONFIG.save(deps.as_mut().storage, &create_ust_config()).unwrap();
+    deps.querier.set_router_address("router");
+    deps.querier.set_router_price("uluna", "uusd", Decimal::from_str("25").unwrap());
+    set_ust_debt(&mut deps, 100_000_000_000);
+
+    // Position 1 is backed by UST debt, and holds 6 bLuna for batch unbonding
+    save_ust_position(deps.as_mut().storage, 1, 100_000_000_000, 6_000_000_000);
+    POSITIONS
+        .update(deps.as_mut().storage, U64Key::new(1), |position| -> StdResult<_> {
+            let mut position = position.unwrap();
+            position.held = true;
+            Ok(position)
+        })
+        .unwrap();
+    deps.querier.set_cw20_balance("bluna_token", MOCK_CONTRACT_ADDR, Uint128::new(6_000_000_000));
+    deps.querier.set_hub_exchange_rate(Decimal::one());
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::EmergencyExit {
+            max_spread: Decimal::from_str("0.02").unwrap(),
+        },
+    )
+    .unwrap();
+
+    // The 6 bLuna is sold for 5.76 Luna
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "bluna_token")
+            .add_attribute("ask_asset", "uluna")
+            .add_attribute("offer_amount", "6000000000")
+            .add_attribute("return_amount", "5760000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 6,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // The UST debt is worth 4 Luna, which is swapped for UST to repay it; the rest of the proceeds
+    // repays the Luna debt
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 9,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("router"),
+                msg: router_swap_msg("uluna", &["uusd"]),
+                funds: vec![Coin::new(4_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Always
+        }
+    );
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(1_760_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert!(!position.held);
+    assert_eq!(
+        position.settlement,
+        Some(Settlement {
+            settled_at: mock_env().block.time.seconds(),
+            received: Uint128::new(5_760_000_000),
+            debt: Uint128::new(4_000_000_000),
+            repaid: Uint128::new(4_000_000_000),
+            shortfall: Uint128::zero(),
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: Uint128::new(1_760_000_000),
+        })
+    );
+    assert_eq!(
+        position.exit,
+        Some(Exit {
+            sold: Uint128::new(6_000_000_000),
+            expected: Uint128::new(6_000_000_000),
+            loss: Uint128::new(240_000_000),
+        })
+    );
+    assert_eq!(
+        PENDING_UST_REPAYMENT.load(deps.as_ref().storage).unwrap(),
+        (Uint128::new(4_000_000_000), Uint128::new(100_000_000_000))
+    );
+}
+
+#[test]
+fn exiting_with_inventory() {
+    let mut deps = setup_test();
+
+    // The contract retains 4 bLuna in its inventory at a cost basis of 10 Luna, and is attributed
+    // 20.2 of the 101 Luna debt
+    save_position(deps.as_mut().storage, 2, 40_000_000_000, 40_000_000_000, 1);
+    INVENTORY
+        .save(
+            deps.as_mut().storage,
+            &Inventory {
+                amount: Uint128::new(4_000_000_000),
+                cost_basis: Uint128::new(10_000_000_000),
+            },
+        )
+        .unwrap();
+    deps.querier.set_cw20_balance("bluna_token", MOCK_CONTRACT_ADDR, Uint128::new(4_000_000_000));
+    deps.querier.set_hub_exchange_rate(Decimal::one());
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::EmergencyExit {
+            max_spread: Decimal::from_str("0.02").unwrap(),
+        },
+    )
+    .unwrap();
+
+    // The 4 bLuna is sold for 3.84 Luna
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "bluna_token")
+            .add_attribute("ask_asset", "uluna")
+            .add_attribute("offer_amount", "4000000000")
+            .add_attribute("return_amount", "3840000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 6,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // The inventory is emptied, and recorded as a closed position backed by its cost basis, with
+    // the part of its debt the sale does not cover as shortfall
+    assert_eq!(INVENTORY.load(deps.as_ref().storage).unwrap(), Inventory::default());
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 3 });
+    assert_eq!(position.borrowed, Uint128::new(10_000_000_000));
+    assert_eq!(position.unbonded, Uint128::new(4_000_000_000));
+    assert_eq!(
+        position.settlement,
+        Some(Settlement {
+            settled_at: mock_env().block.time.seconds(),
+            received: Uint128::new(3_840_000_000),
+            debt: Uint128::new(20_200_000_000),
+            repaid: Uint128::new(3_840_000_000),
+            shortfall: Uint128::new(16_360_000_000),
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: Uint128::zero(),
+        })
+    );
+    assert_eq!(
+        position.exit,
+        Some(Exit {
+            sold: Uint128::new(4_000_000_000),
+            expected: Uint128::new(4_000_000_000),
+            loss: Uint128::new(160_000_000),
+        })
+    );
+
+    // No bLuna is left over to be recorded as a position of its own
+    assert!(!res.attributes.iter().any(|attr| attr.key == "position_id"));
+}
+
+#[test]
+fn sweeping_tokens() {
+    let mut deps = setup_test();
+
+    // The contract holds 105 Luna while owing 101 Luna to Red Bank
+    deps.querier.set_native_balance(MOCK_CONTRACT_ADDR, &[Coin::new(105_000_000_000, "uluna")]);
+
+    let stray_tokens = AssetUnchecked {
+        info: AssetInfoBase::Cw20(String::from("stray_token")),
+        amount: Uint128::new(12345),
+    };
+
+    // Non-owner cannot sweep
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("non_owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: stray_tokens.clone(),
+            recipient: String::from("owner"),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    // Owner can sweep tokens sent to the contract by mistake
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: stray_tokens,
+            recipient: String::from("alice"),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("stray_token"),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: String::from("alice"),
+                amount: Uint128::new(12345)
+            })
+            .unwrap(),
+            funds: vec![]
+        })]
+    );
+    assert_eq!(
+        res.attributes,
+        vec![
+            attr("action", "bluna_lev_arb/execute/sweep"),
+            attr("asset_swept", "cw20:stray_token:12345"),
+            attr("recipient", "alice")
+        ]
+    );
+
+    // Luna needed to repay the debt cannot be swept
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: AssetUnchecked {
+                info: AssetInfoBase::Native(String::from("uluna")),
+                amount: Uint128::new(5_000_000_000),
+            },
+            recipient: String::from("owner"),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("amount 5000000000 is greater than sweepable amount 4000000000")
+    );
+
+    // Luna in excess of the debt can be swept
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: AssetUnchecked {
+                info: AssetInfoBase::Native(String::from("uluna")),
+                amount: Uint128::new(4_000_000_000),
+            },
+            recipient: String::from("owner"),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(BankMsg::Send {
+            to_address: String::from("owner"),
+            amount: vec![Coin::new(4_000_000_000, "uluna")]
+        })]
+    );
+
+    // Luna set aside in the reserve fund, first-loss capital or credited to recipients cannot be
+    // swept either
+    RESERVE.save(deps.as_mut().storage, &Uint128::new(1_000_000_000)).unwrap();
+    FIRST_LOSS_CAPITAL.save(deps.as_mut().storage, &Uint128::new(1_000_000_000)).unwrap();
+    CLAIMABLE
+        .save(deps.as_mut().storage, &Addr::unchecked("alice"), &Uint128::new(1_000_000_000))
+        .unwrap();
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: AssetUnchecked {
+                info: AssetInfoBase::Native(String::from("uluna")),
+                amount: Uint128::new(2_000_000_000),
+            },
+            recipient: String::from("owner"),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("amount 2000000000 is greater than sweepable amount 1000000000")
+    );
+
+    // bLuna held for batch unbonding or retained in the inventory cannot be swept
+    deps.querier.set_cw20_balance("bluna_token", MOCK_CONTRACT_ADDR, Uint128::new(10_000_000_000));
+    save_position(deps.as_mut().storage, 1, 6_000_000_000, 6_000_000_000, 0);
+    POSITIONS
+        .update(deps.as_mut().storage, U64Key::new(1), |position| -> StdResult<_> {
+            let mut position = position.unwrap();
+            position.held = true;
+            Ok(position)
+        })
+        .unwrap();
+    INVENTORY
+        .save(
+            deps.as_mut().storage,
+            &Inventory {
+                amount: Uint128::new(3_000_000_000),
+                cost_basis: Uint128::new(3_000_000_000),
+            },
+        )
+        .unwrap();
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: AssetUnchecked {
+                info: AssetInfoBase::Cw20(String::from("bluna_token")),
+                amount: Uint128::new(2_000_000_000),
+            },
+            recipient: String::from("owner"),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("amount 2000000000 is greater than sweepable amount 1000000000")
+    );
+
+    // UST needed to repay the UST debt cannot be swept
+    set_ust_debt(&mut deps, 5_000_000_000);
+    deps.querier.set_native_balance(
+        MOCK_CONTRACT_ADDR,
+        &[Coin::new(105_000_000_000, "uluna"), Coin::new(5_000_000_000, "uusd")],
+    );
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: AssetUnchecked {
+                info: AssetInfoBase::Native(String::from("uusd")),
+                amount: Uint128::new(1),
+            },
+            recipient: String::from("owner"),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("amount 1 is greater than sweepable amount 0"));
+}
+
+#[test]
+fn prepaying_debt() {
+    let mut deps = setup_test();
+
+    RESERVE.save(deps.as_mut().storage, &Uint128::new(3_000_000_000)).unwrap();
+    save_position(deps.as_mut().storage, 1, 50_000_000_000, 54_000_000_000, 1);
+    save_position(deps.as_mut().storage, 2, 50_000_000_000, 54_000_000_000, 2);
+
+    // Non-owner cannot prepay
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("non_owner", &[]),
+        ExecuteMsg::Prepay {
+            amount: Uint128::new(2_000_000_000),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    // Cannot prepay more than the reserve holds
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Prepay {
+            amount: Uint128::new(4_000_000_000),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("amount 4000000000 is greater than reserve 3000000000"));
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Prepay {
+            amount: Uint128::new(2_000_000_000),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("red_bank"),
+            msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                denom: String::from("uluna"),
+                on_behalf_of: None
+            })
+            .unwrap(),
+            funds: vec![Coin::new(2_000_000_000, "uluna")]
+        })]
+    );
+    assert_eq!(RESERVE.load(deps.as_ref().storage).unwrap(), Uint128::new(1_000_000_000));
+    assert_eq!(PREPAID.load(deps.as_ref().storage).unwrap(), Uint128::new(2_000_000_000));
+
+    // Position 1 finishes unbonding after the prepayment. Automatic prepayment is enabled.
+    set_user_debt(&mut deps, 99_000_000_000);
+    deps.querier.set_hub_unbond_requests(
+        MOCK_CONTRACT_ADDR,
+        vec![(2, Uint128::new(54_000_000_000))],
+    );
+
+    let mut config = create_config();
+    config.auto_prepay = true;
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", "52500000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+
+    // Position 1 repays its 49.5 Luna share of the remaining debt, and reimburses its 1 Luna share
+    // of the prepaid debt to the reserve. The reserve, now 2 Luna, is then used to prepay the debt
+    // of position 2.
+    assert_eq!(
+        res.messages[0],
+        SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("red_bank"),
+            msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                denom: String::from("uluna"),
+                on_behalf_of: None
+            })
+            .unwrap(),
+            funds: vec![Coin::new(51_500_000_000, "uluna")]
+        })
+    );
+    assert!(res.attributes.contains(&attr("debt_prepaid", "native:uluna:2000000000")));
+    assert!(res.attributes.contains(&attr("profit", "native:uluna:2000000000")));
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(
+        position.settlement,
+        Some(Settlement {
+            settled_at: mock_env().block.time.seconds(),
+            received: Uint128::new(52_500_000_000),
+            debt: Uint128::new(50_500_000_000),
+            repaid: Uint128::new(49_500_000_000),
+            shortfall: Uint128::zero(),
+            prepaid: Uint128::new(1_000_000_000),
+            reimbursed: Uint128::new(1_000_000_000),
+            profit: Uint128::new(2_000_000_000),
+        })
+    );
+    assert_eq!(RESERVE.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+    assert_eq!(PREPAID.load(deps.as_ref().storage).unwrap(), Uint128::new(3_000_000_000));
+}
+
+#[test]
+fn parking_idle_luna() {
+    let mut deps = setup_test();
+
+    deps.querier.set_native_balance(MOCK_CONTRACT_ADDR, &[Coin::new(5_000_000_000, "uluna")]);
+
+    // Cannot park unless enabled in config
+    let err = execute(deps.as_mut(), mock_env(), mock_info("keeper", &[]), ExecuteMsg::Park {})
+        .unwrap_err();
+    assert_eq!(err, StdError::generic_err("parking idle luna is disabled"));
+
+    let mut config = create_config();
+    config.park_idle_luna = true;
+    config.reserve_target = Uint128::new(60_000_000);
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    // First-loss capital and profit yet to be claimed are not parked
+    FIRST_LOSS_CAPITAL.save(deps.as_mut().storage, &Uint128::new(1_000_000_000)).unwrap();
+    CLAIMABLE
+        .save(deps.as_mut().storage, &Addr::unchecked("alice"), &Uint128::new(1_000_000_000))
+        .unwrap();
+
+    let res =
+        execute(deps.as_mut(), mock_env(), mock_info("keeper", &[]), ExecuteMsg::Park {}).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("red_bank"),
+            msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::DepositNative {
+                denom: String::from("uluna"),
+                on_behalf_of: None
+            })
+            .unwrap(),
+            funds: vec![Coin::new(3_000_000_000, "uluna")]
+        })]
+    );
+    assert_eq!(DEPOSITED.load(deps.as_ref().storage).unwrap(), Uint128::new(3_000_000_000));
+
+    // The deposit accrues 0.1 Luna interest, which is reported separately from the principal
+    deps.querier.set_native_balance(MOCK_CONTRACT_ADDR, &[Coin::new(2_000_000_000, "uluna")]);
+    deps.querier.set_red_bank_market(
+        LegacyAsset::Native {
+            denom: String::from("uluna"),
+        },
+        Market {
+            ma_token_address: Addr::unchecked("ma_luna"),
+            ..Default::default()
+        },
+    );
+    deps.querier.set_ma_token_underlying_balance(
+        "ma_luna",
+        MOCK_CONTRACT_ADDR,
+        Uint128::new(3_100_000_000),
+    );
+
+    let res: StatusResponse = query_helper(deps.as_ref(), QueryMsg::Status {});
+    assert_eq!(res.deposited, Uint128::new(3_000_000_000));
+    assert_eq!(res.deposit_interest, Uint128::new(100_000_000));
+
+    // A claim withdraws the deposit first; the interest is added to the reserve up to its target
+    let res =
+        execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), ExecuteMsg::ClaimProfit {})
+            .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::Withdraw {
+                    asset: LegacyAsset::Native {
+                        denom: String::from("uluna")
+                    },
+                    amount: None,
+                    recipient: None
+                })
+                .unwrap(),
+                funds: vec![]
+            }),
+            SubMsg::new(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(1_000_000_000, "uluna")]
+            })
+        ]
+    );
+    assert_eq!(DEPOSITED.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+    assert_eq!(RESERVE.load(deps.as_ref().storage).unwrap(), Uint128::new(60_000_000));
+
+    // A sweep also withdraws the deposit first, which counts towards the sweepable amount. The 2
+    // Luna held plus 3.1 Luna deposited, less the reserve and first-loss capital, is 4.04 Luna.
+    set_user_debt(&mut deps, 0);
+    DEPOSITED.save(deps.as_mut().storage, &Uint128::new(3_000_000_000)).unwrap();
+
+    // The maLuna representing the deposit cannot be swept while Luna is parked
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: AssetUnchecked {
+                info: AssetInfoBase::Cw20(String::from("ma_luna")),
+                amount: Uint128::new(3_000_000_000),
+            },
+            recipient: String::from("owner"),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("cannot sweep maluna while luna is parked"));
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::Sweep {
+            asset: AssetUnchecked {
+                info: AssetInfoBase::Native(String::from("uluna")),
+                amount: Uint128::new(4_000_000_000),
+            },
+            recipient: String::from("owner"),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::Withdraw {
+                    asset: LegacyAsset::Native {
+                        denom: String::from("uluna")
+                    },
+                    amount: None,
+                    recipient: None
+                })
+                .unwrap(),
+                funds: vec![]
+            }),
+            SubMsg::new(BankMsg::Send {
+                to_address: String::from("owner"),
+                amount: vec![Coin::new(4_000_000_000, "uluna")]
+            })
+        ]
+    );
+    assert_eq!(DEPOSITED.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+}
+
+#[test]
+fn reporting_credit() {
+    let mut deps = setup_test();
+
+    let now = mock_env().block.time.seconds();
+    let day = 86400;
+
+    set_user_debt(&mut deps, 73_000_000_000);
+    RESERVE.save(deps.as_mut().storage, &Uint128::new(1_000_000_000)).unwrap();
+    deps.querier.set_red_bank_market(
+        LegacyAsset::Native {
+            denom: String::from("uluna"),
+        },
+        Market {
+            borrow_rate: MarsDecimal::from_str("0.05").unwrap(),
+            ..Default::default()
+        },
+    );
+
+    // Batch 1 has been released and is withdrawable; batch 2 was submitted 10 days ago; batch 3 is
+    // yet to be submitted, at the start of the next epoch in 2 days
+    deps.querier.set_hub_exchange_rate(Decimal::from_str("1.01").unwrap());
+    deps.querier.set_hub_epochs(now - day, 3 * day, 21 * day);
+    deps.querier.set_hub_unbond_requests(
+        MOCK_CONTRACT_ADDR,
+        vec![
+            (1, Uint128::new(2_000_000_000)),
+            (2, Uint128::new(50_000_000_000)),
+            (3, Uint128::new(54_000_000_000)),
+        ],
+    );
+    deps.querier.set_hub_withdrawable_unbonded(MOCK_CONTRACT_ADDR, Uint128::new(2_000_000_000));
+    deps.querier.set_hub_history(vec![
+        basset::hub::UnbondHistory {
+            batch_id: 1,
+            time: now - 30 * day,
+            amount: Uint128::new(2_000_000_000),
+            applied_exchange_rate: Decimal::one(),
+            withdraw_rate: Decimal::one(),
+            released: true,
+        },
+        basset::hub::UnbondHistory {
+            batch_id: 2,
+            time: now - 10 * day,
+            amount: Uint128::new(50_000_000_000),
+            applied_exchange_rate: Decimal::one(),
+            withdraw_rate: Decimal::one(),
+            released: false,
+        },
+    ]);
+
+    let res: CreditReportResponse = query_helper(deps.as_ref(), QueryMsg::CreditReport {});
+    assert_eq!(
+        res,
+        CreditReportResponse {
+            total_debt: Uint128::new(73_000_000_000),
+            ust_debt: Uint128::zero(),
+            ust_debt_value: Uint128::zero(),
+            maturities: vec![
+                Maturity {
+                    batch_id: 2,
+                    matures_at: now + 11 * day,
+                    luna_value: Uint128::new(50_500_000_000),
+                    projected_debt: Uint128::new(73_110_000_000), // 73 * 0.05 * 11 / 365 = 0.11
+                },
+                Maturity {
+                    batch_id: 3,
+                    matures_at: now + 23 * day,
+                    luna_value: Uint128::new(54_540_000_000),
+                    projected_debt: Uint128::new(73_230_000_000), // 73 * 0.05 * 23 / 365 = 0.23
+                },
+            ],
+            pending_unbond_value: Uint128::new(105_040_000_000),
+            held_value: Uint128::zero(),
+            withdrawable: Uint128::new(2_000_000_000),
+            reserve: Uint128::new(1_000_000_000),
+            coverage_ratio: Some(Decimal::from_str("1.48").unwrap()), // 108.04 / 73
+        }
+    );
+
+    // bLuna held for batch unbonding and retained in the inventory counts towards coverage
+    save_position(deps.as_mut().storage, 4, 5_000_000_000, 6_000_000_000, 0);
+    POSITIONS
+        .update(deps.as_mut().storage, U64Key::new(4), |position| -> StdResult<_> {
+            let mut position = position.unwrap();
+            position.held = true;
+            Ok(position)
+        })
+        .unwrap();
+    INVENTORY
+        .save(
+            deps.as_mut().storage,
+            &Inventory {
+                amount: Uint128::new(4_000_000_000),
+                cost_basis: Uint128::new(4_000_000_000),
+            },
+        )
+        .unwrap();
+
+    let res: CreditReportResponse = query_helper(deps.as_ref(), QueryMsg::CreditReport {});
+    assert_eq!(res.held_value, Uint128::new(10_100_000_000));
+    assert_eq!(
+        res.coverage_ratio,
+        Some(Decimal::from_ratio(118_140_000_000u128, 73_000_000_000u128)) // 118.14 / 73
+    );
+
+    POSITIONS.remove(deps.as_mut().storage, U64Key::new(4));
+    INVENTORY.save(deps.as_mut().storage, &Inventory::default()).unwrap();
+
+    // New borrows are refused if they would push coverage below the floor
+    let mut config = create_config();
+    config.coverage_floor = Some(Decimal::from_str("1.4").unwrap());
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::ExecuteArb {
+            amount: Uint128::new(100_000_000_000),
+            minimum_profit: Decimal::zero(),
+            hold_ratio: None,
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("coverage ratio 1.208323699421965317 would fall below floor 1.4")
+    );
+
+    // (108.04 + 10.1) / 83 = 1.4233...
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::ExecuteArb {
+            amount: Uint128::new(10_000_000_000),
+            minimum_profit: Decimal::zero(),
+            hold_ratio: None,
+        },
+    )
+    .unwrap();
+}
+
+#[test]
+fn querying_schedule() {
+    let mut deps = setup_test();
+
+    let now = mock_env().block.time.seconds();
+    let day = 86400;
+
+    save_position(deps.as_mut().storage, 1, 2_000_000_000, 2_000_000_000, 1);
+    save_position(deps.as_mut().storage, 2, 20_000_000_000, 20_000_000_000, 2);
+    save_position(deps.as_mut().storage, 3, 30_000_000_000, 30_000_000_000, 2);
+    save_position(deps.as_mut().storage, 4, 54_000_000_000, 54_000_000_000, 3);
+
+    // Batch 1 has been released; batch 2 was submitted 10 days ago; batch 3 is yet to be
+    // submitted, at the start of the next epoch in 2 days
+    deps.querier.set_hub_exchange_rate(Decimal::from_str("1.01").unwrap());
+    deps.querier.set_hub_epochs(now - day, 3 * day, 21 * day);
+    deps.querier.set_hub_unbond_requests(
+        MOCK_CONTRACT_ADDR,
+        vec![
+            (3, Uint128::new(54_000_000_000)),
+            (1, Uint128::new(2_000_000_000)),
+            (2, Uint128::new(50_000_000_000)),
+        ],
+    );
+    deps.querier.set_hub_withdrawable_unbonded(MOCK_CONTRACT_ADDR, Uint128::new(1_980_000_000));
+    deps.querier.set_hub_history(vec![
+        basset::hub::UnbondHistory {
+            batch_id: 1,
+            time: now - 30 * day,
+            amount: Uint128::new(2_000_000_000),
+            applied_exchange_rate: Decimal::one(),
+            withdraw_rate: Decimal::from_str("0.99").unwrap(),
+            released: true,
+        },
+        basset::hub::UnbondHistory {
+            batch_id: 2,
+            time: now - 10 * day,
+            amount: Uint128::new(50_000_000_000),
+            applied_exchange_rate: Decimal::one(),
+            withdraw_rate: Decimal::one(),
+            released: false,
+        },
+    ]);
+
+    let res: ScheduleResponse = query_helper(deps.as_ref(), QueryMsg::Schedule {});
+    assert_eq!(
+        res,
+        ScheduleResponse {
+            tranches: vec![
+                Tranche {
+                    batch_id: 1,
+                    bluna_amount: Uint128::new(2_000_000_000),
+                    expected_release: now - 9 * day,
+                    expected_luna: Uint128::new(1_980_000_000),
+                    released: true,
+                    position_ids: vec![1],
+                    ust_borrowed: Uint128::zero(),
+                },
+                Tranche {
+                    batch_id: 2,
+                    bluna_amount: Uint128::new(50_000_000_000),
+                    expected_release: now + 11 * day,
+                    expected_luna: Uint128::new(50_500_000_000),
+                    released: false,
+                    position_ids: vec![2, 3],
+                    ust_borrowed: Uint128::zero(),
+                },
+                Tranche {
+                    batch_id: 3,
+                    bluna_amount: Uint128::new(54_000_000_000),
+                    expected_release: now + 23 * day,
+                    expected_luna: Uint128::new(54_540_000_000),
+                    released: false,
+                    position_ids: vec![4],
+                    ust_borrowed: Uint128::zero(),
+                },
+            ],
+            withdrawable: Uint128::new(1_980_000_000),
+            ready_now: true,
+        }
+    );
+}
+
+#[test]
+fn submitting_unbonds() {
+    let mut deps = setup_test();
+
+    let now = mock_env().block.time.seconds();
+    let day = 86400;
+
+    // Invalid config: Batch unbonding requires the reward contract, so that rewards accrued on the
+    // held bLuna can be claimed
+    let mut invalid_config = create_config();
+    invalid_config.batch_unbonding = Some(BatchUnbonding {
+        submit_window: 3600,
+    });
+
+    let err = instantiate(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("deployer", &[]),
+        invalid_config.into(),
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("bluna reward is required for batch unbonding"));
+
+    let mut config = create_config();
+    config.bluna_reward = Some(Addr::unchecked("bluna_reward"));
+    config.batch_unbonding = Some(BatchUnbonding {
+        submit_window: 3600,
+    });
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    // The next batch is submitted in 2 hours, so unbonds can be submitted in an hour
+    deps.querier.set_hub_epochs(now + 2 * 3600 - 3 * day, 3 * day, 21 * day);
+
+    //------------------------------------------------------------
+    // Test 1. Swapped bLuna is held rather than unbonded
+    //
+    MINIMUM_RECEIVE
+        .save(deps.as_mut().storage, &Uint128::new(105_000_000_000))
+        .unwrap();
+    PENDING_BORROWED
+        .save(deps.as_mut().storage, &Uint128::new(100_000_000_000))
+        .unwrap();
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 1,
+            result: ContractResult::Ok(SubMsgExecutionResponse {
+                events: vec![Event::new("from_contract")
+                    .add_attribute("action", "swap")
+                    .add_attribute("offer_asset", "uluna")
+                    .add_attribute("ask_asset", "bluna_token")
+                    .add_attribute("offer_amount", "100000000000")
+                    .add_attribute("return_amount", "108000000000")],
+                data: None,
+            }),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 0);
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert!(position.held);
+    assert_eq!(position.batch_id, 0);
+
+    //------------------------------------------------------------
+    // Test 2. Too early to submit
+    //
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("keeper", &[]),
+        ExecuteMsg::SubmitUnbonds {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err(format!("unbonds can be submitted from {}", now + 3600)));
+
+    //------------------------------------------------------------
+    // Test 3. Within the submit window
+    //
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(3600);
+
+    let res = execute(deps.as_mut(), env, mock_info("keeper", &[]), ExecuteMsg::SubmitUnbonds {})
+        .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: String::from("bluna_reward"),
+                msg: to_binary(&basset::reward::ExecuteMsg::ClaimRewards {
+                    recipient: Some(String::from("owner")),
+                })
+                .unwrap(),
+                funds: vec![],
+            }),
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: String::from("bluna_token"),
+                msg: to_binary(&Cw20ExecuteMsg::Send {
+                    contract: String::from("bluna_hub"),
+                    amount: Uint128::new(108_000_000_000),
+                    msg: to_binary(&basset::hub::Cw20HookMsg::Unbond {}).unwrap(),
+                })
+                .unwrap(),
+                funds: vec![],
+            }),
+        ]
+    );
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert!(!position.held);
+    assert_eq!(position.batch_id, 2);
+
+    //------------------------------------------------------------
+    // Test 4. Nothing held anymore
+    //
+    let mut env = mock_env();
+    env.block.time = env.block.time.plus_seconds(3600);
+
+    let err = execute(deps.as_mut(), env, mock_info("keeper", &[]), ExecuteMsg::SubmitUnbonds {})
+        .unwrap_err();
+    assert_eq!(err, StdError::generic_err("no bluna held for unbonding"));
+}
+
+#[test]
+fn holding_inventory() {
+    let mut deps = setup_test();
+
+    //------------------------------------------------------------
+    // Test 1. Retain a portion of the swapped bLuna as inventory
+    //
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::ExecuteArb {
+            amount: Uint128::new(100_000_000_000),
+            minimum_profit: Decimal::from_str("0.05").unwrap(),
+            hold_ratio: Some(Decimal::one()),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("hold ratio 1 is not less than one"));
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::ExecuteArb {
+            amount: Uint128::new(100_000_000_000),
+            minimum_profit: Decimal::from_str("0.05").unwrap(),
+            hold_ratio: Some(Decimal::from_str("0.25").unwrap()),
+        },
+    )
+    .unwrap();
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 1,
+            result: ContractResult::Ok(SubMsgExecutionResponse {
+                events: vec![Event::new("from_contract")
+                    .add_attribute("action", "swap")
+                    .add_attribute("offer_asset", "uluna")
+                    .add_attribute("ask_asset", "bluna_token")
+                    .add_attribute("offer_amount", "100000000000")
+                    .add_attribute("return_amount", "108000000000")],
+                data: None,
+            }),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("bluna_token"),
+            msg: to_binary(&Cw20ExecuteMsg::Send {
+                contract: String::from("bluna_hub"),
+                amount: Uint128::new(81_000_000_000),
+                msg: to_binary(&basset::hub::Cw20HookMsg::Unbond {}).unwrap(),
+            })
+            .unwrap(),
+            funds: vec![],
+        })]
+    );
+
+    // A quarter of the bLuna and of the debt go to the inventory; the rest forms the position
+    let inventory: Inventory = query_helper(deps.as_ref(), QueryMsg::Inventory {});
+    assert_eq!(
+        inventory,
+        Inventory {
+            amount: Uint128::new(27_000_000_000),
+            cost_basis: Uint128::new(25_000_000_000),
+        }
+    );
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(position.borrowed, Uint128::new(75_000_000_000));
+    assert_eq!(position.unbonded, Uint128::new(81_000_000_000));
+    assert!(PENDING_HOLD_RATIO.may_load(deps.as_ref().storage).unwrap().is_none());
+
+    //------------------------------------------------------------
+    // Test 2. Claim rewards on the inventory
+    //
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("anyone", &[]),
+        ExecuteMsg::ClaimRewards {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("bluna reward contract is not set"));
+
+    let mut config = create_config();
+    config.bluna_reward = Some(Addr::unchecked("bluna_reward"));
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("anyone", &[]),
+        ExecuteMsg::ClaimRewards {},
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("bluna_reward"),
+            msg: to_binary(&basset::reward::ExecuteMsg::ClaimRewards {
+                recipient: Some(String::from("owner")),
+            })
+            .unwrap(),
+            funds: vec![],
+        })]
+    );
+
+    //------------------------------------------------------------
+    // Test 3. Unbond part of the inventory
+    //
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("anyone", &[]),
+        ExecuteMsg::UnbondInventory {
+            amount: Uint128::new(9_000_000_000),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::UnbondInventory {
+            amount: Uint128::new(28_000_000_000),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("amount 28000000000 is not between zero and inventory 27000000000")
+    );
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::UnbondInventory {
+            amount: Uint128::new(9_000_000_000),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("bluna_token"),
+            msg: to_binary(&Cw20ExecuteMsg::Send {
+                contract: String::from("bluna_hub"),
+                amount: Uint128::new(9_000_000_000),
+                msg: to_binary(&basset::hub::Cw20HookMsg::Unbond {}).unwrap(),
+            })
+            .unwrap(),
+            funds: vec![],
+        })]
+    );
+
+    // The new position is backed by a third of the inventory's cost basis
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 2 });
+    assert_eq!(position.borrowed, Uint128::new(8_333_333_333));
+    assert_eq!(position.unbonded, Uint128::new(9_000_000_000));
+    assert_eq!(position.batch_id, 2);
+
+    //------------------------------------------------------------
+    // Test 4. Sell the rest of the inventory, withdrawing the idle Luna parked in Red Bank first
+    //
+    DEPOSITED.save(deps.as_mut().storage, &Uint128::new(1_000_000_000)).unwrap();
+    deps.querier.set_red_bank_market(
+        LegacyAsset::Native {
+            denom: String::from("uluna"),
+        },
+        Market {
+            ma_token_address: Addr::unchecked("ma_luna"),
+            ..Default::default()
+        },
+    );
+    deps.querier.set_ma_token_underlying_balance(
+        "ma_luna",
+        MOCK_CONTRACT_ADDR,
+        Uint128::new(1_000_000_000),
+    );
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        ExecuteMsg::SellInventory {
+            amount: Uint128::new(18_000_000_000),
+            max_spread: Decimal::from_str("0.01").unwrap(),
+        },
+    )
+    .unwrap();
+    assert_eq!(DEPOSITED.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+    assert_eq!(
+        res.messages,
+        vec![
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::Withdraw {
+                    asset: LegacyAsset::Native {
+                        denom: String::from("uluna")
+                    },
+                    amount: None,
+                    recipient: None
+                })
+                .unwrap(),
+                funds: vec![]
+            }),
+            SubMsg::reply_on_success(
+                WasmMsg::Execute {
+                    contract_addr: String::from("bluna_token"),
+                    msg: to_binary(&Cw20ExecuteMsg::Send {
+                        contract: String::from("bluna_pair"),
+                        amount: Uint128::new(18_000_000_000),
+                        msg: to_binary(&astroport::pair::Cw20HookMsg::Swap {
+                            belief_price: None,
+                            max_spread: Some(Decimal::from_str("0.01").unwrap()),
+                            to: None,
+                        })
+                        .unwrap(),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                },
+                7
+            )
+        ]
+    );
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 7,
+            result: ContractResult::Ok(SubMsgExecutionResponse {
+                events: vec![Event::new("from_contract")
+                    .add_attribute("action", "swap")
+                    .add_attribute("offer_asset", "bluna_token")
+                    .add_attribute("ask_asset", "uluna")
+                    .add_attribute("offer_amount", "18000000000")
+                    .add_attribute("return_amount", "19000000000")],
+                data: None,
+            }),
+        },
+    )
+    .unwrap();
+
+    // The sale is settled right away. The 101 Luna debt is attributed by amount borrowed; the sale
+    // is backed by 16_666_666_667 of the 100_000_000_000 borrowed in total
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        res.messages[0],
+        SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("red_bank"),
+            msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                denom: String::from("uluna"),
+                on_behalf_of: None,
+            })
+            .unwrap(),
+            funds: vec![Coin::new(16_833_333_333, "uluna")],
+        })
+    );
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 3 });
+    assert_eq!(position.borrowed, Uint128::new(16_666_666_667));
+    assert_eq!(
+        position.settlement,
+        Some(Settlement {
+            settled_at: mock_env().block.time.seconds(),
+            received: Uint128::new(19_000_000_000),
+            debt: Uint128::new(16_833_333_333),
+            repaid: Uint128::new(16_833_333_333),
+            shortfall: Uint128::zero(),
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: Uint128::new(2_166_666_667),
+        })
+    );
+
+    let inventory: Inventory = query_helper(deps.as_ref(), QueryMsg::Inventory {});
+    assert_eq!(inventory, Inventory::default());
+    assert!(PENDING_SALE.may_load(deps.as_ref().storage).unwrap().is_none());
+}
+
+#[test]
+fn selling_to_desk() {
+    let mut deps = setup_test();
+
+    deps.querier.set_hub_exchange_rate(Decimal::from_str("1.01").unwrap());
+
+    let sell_msg = |amount: u128, minimum_receive: Option<u128>| {
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: String::from("seller"),
+            amount: Uint128::new(amount),
+            msg: to_binary(&ReceiveMsg::Sell {
+                minimum_receive: minimum_receive.map(Uint128::new),
+            })
+            .unwrap(),
+        })
+    };
+
+    //------------------------------------------------------------
+    // Test 1. Desk is disabled
+    //
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("bluna_token", &[]),
+        sell_msg(10_000_000_000, None),
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("liquidity desk is disabled"));
+
+    let mut config = create_config();
+    config.desk = Some(Desk {
+        discount: Decimal::from_str("0.02").unwrap(),
+        max_size: Uint128::new(50_000_000_000),
+        max_debt: Uint128::new(15_000_000_000),
+    });
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    //------------------------------------------------------------
+    // Test 2. Quote
+    //
+    let res: QuoteResponse = query_helper(
+        deps.as_ref(),
+        QueryMsg::Quote {
+            amount: Uint128::new(10_000_000_000),
+        },
+    );
+    assert_eq!(
+        res,
+        QuoteResponse {
+            bluna_amount: Uint128::new(10_000_000_000),
+            exchange_rate: Decimal::from_str("1.01").unwrap(),
+            peg_fee: Decimal::zero(),
+            discount: Decimal::from_str("0.02").unwrap(),
+            luna_amount: Uint128::new(9_898_000_000), // 10_000_000_000 * 1.01 * 0.98
+        }
+    );
+
+    // The peg recovery fee is deducted while the exchange rate is below the threshold
+    deps.querier.set_hub_peg_recovery(
+        Decimal::from_str("0.005").unwrap(),
+        Decimal::from_str("1.02").unwrap(),
+    );
+    let res: QuoteResponse = query_helper(
+        deps.as_ref(),
+        QueryMsg::Quote {
+            amount: Uint128::new(10_000_000_000),
+        },
+    );
+    assert_eq!(res.peg_fee, Decimal::from_str("0.005").unwrap());
+    // 10_000_000_000 * 1.01 * 0.995 * 0.98
+    assert_eq!(res.luna_amount, Uint128::new(9_848_510_000));
+
+    deps.querier.set_hub_peg_recovery(Decimal::from_str("0.005").unwrap(), Decimal::one());
+    let res: QuoteResponse = query_helper(
+        deps.as_ref(),
+        QueryMsg::Quote {
+            amount: Uint128::new(10_000_000_000),
+        },
+    );
+    assert_eq!(res.peg_fee, Decimal::zero());
+    assert_eq!(res.luna_amount, Uint128::new(9_898_000_000));
+
+    let err = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::Quote {
+            amount: Uint128::new(60_000_000_000),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("luna amount 59388000000 is greater than max size 50000000000")
+    );
+
+    //------------------------------------------------------------
+    // Test 3. Invalid sales
+    //
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("other_token", &[]),
+        sell_msg(10_000_000_000, None),
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("only bluna can be sold"));
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("bluna_token", &[]),
+        sell_msg(10_000_000_000, Some(9_900_000_000)),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err(
+            "too little to be paid: native:uluna:9898000000, expecting at least 9900000000"
+        )
+    );
+
+    //------------------------------------------------------------
+    // Test 4. Valid sale
+    //
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("bluna_token", &[]),
+        sell_msg(10_000_000_000, Some(9_800_000_000)),
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::Borrow {
+                    asset: mars_core::asset::Asset::Native {
+                        denom: String::from("uluna"),
+                    },
+                    amount: Uint128::new(9_898_000_000),
+                    recipient: None,
+                })
+                .unwrap(),
+                funds: vec![],
+            }),
+            SubMsg::new(BankMsg::Send {
+                to_address: String::from("seller"),
+                amount: vec![Coin::new(9_898_000_000, "uluna")],
+            }),
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: String::from("bluna_token"),
+                msg: to_binary(&Cw20ExecuteMsg::Send {
+                    contract: String::from("bluna_hub"),
+                    amount: Uint128::new(10_000_000_000),
+                    msg: to_binary(&basset::hub::Cw20HookMsg::Unbond {}).unwrap(),
+                })
+                .unwrap(),
+                funds: vec![],
+            }),
+        ]
+    );
+
+    // The fill is recorded as a position backed by the Luna paid
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(position.borrowed, Uint128::new(9_898_000_000));
+    assert_eq!(position.unbonded, Uint128::new(10_000_000_000));
+    assert_eq!(position.batch_id, 2);
+
+    //------------------------------------------------------------
+    // Test 5. Outstanding desk debt
+    //
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("bluna_token", &[]),
+        sell_msg(10_000_000_000, None),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err(
+            "luna amount 9898000000 would take desk debt 9898000000 above max debt 15000000000"
+        )
+    );
+
+    // Once the first fill's position is closed, its debt no longer counts
+    POSITIONS.remove(deps.as_mut().storage, U64Key::new(1));
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("bluna_token", &[]),
+        sell_msg(10_000_000_000, None),
+    )
+    .unwrap();
+    assert_eq!(DESK_POSITION_IDS.load(deps.as_ref().storage).unwrap(), vec![2]);
+}
+
+#[test]
+fn executing_arb_in_tranches() {
+    let mut deps = setup_test();
+
+    deps.querier.set_pair_address("bluna_pair");
+    deps.querier.set_pair_pools(Uint128::new(1_000_000_000_000), Uint128::new(1_100_000_000_000));
+
+    let schedule_msg = |total_amount: u128, tranche_size: u128, min_profit: &str| {
+        ExecuteMsg::ScheduleArb {
+            total_amount: Uint128::new(total_amount),
+            tranche_size: Uint128::new(tranche_size),
+            min_profit: Decimal::from_str(min_profit).unwrap(),
+            interval_blocks: 10,
+        }
+    };
+    let env_at = |blocks_later: u64| {
+        let mut env = mock_env();
+        env.block.height += blocks_later;
+        env
+    };
+    let height = mock_env().block.height;
+
+    //------------------------------------------------------------
+    // Test 1. Schedule a plan
+    //
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("non_owner", &[]),
+        schedule_msg(50_000_000_000, 20_000_000_000, "0.05"),
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        schedule_msg(50_000_000_000, 0, "0.05"),
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("total amount and tranche size must be non-zero"));
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        schedule_msg(50_000_000_000, 20_000_000_000, "0"),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("min profit 0 must be greater than zero and less than one")
+    );
+
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        schedule_msg(50_000_000_000, 20_000_000_000, "1"),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("min profit 1 must be greater than zero and less than one")
+    );
+
+    let res: Option<ArbPlan> = query_helper(deps.as_ref(), QueryMsg::ArbPlan {});
+    assert_eq!(res, None);
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("owner", &[]),
+        schedule_msg(50_000_000_000, 20_000_000_000, "0.05"),
+    )
+    .unwrap();
+
+    //------------------------------------------------------------
+    // Test 2. Execute the first tranche
+    //
+    let res = execute(
+        deps.as_mut(),
+        env_at(0),
+        mock_info("keeper", &[]),
+        ExecuteMsg::ExecuteTranche {},
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(
+        res.messages[0],
+        SubMsg::new(WasmMsg::Execute {
+            contract_addr: String::from("red_bank"),
+            msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::Borrow {
+                asset: mars_core::asset::Asset::Native {
+                    denom: String::from("uluna"),
+                },
+                amount: Uint128::new(20_000_000_000),
+                recipient: None,
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    );
+    assert_eq!(res.messages[1].id, 1);
+    assert_eq!(
+        MINIMUM_RECEIVE.load(deps.as_ref().storage).unwrap(),
+        Uint128::new(21_000_000_000)
+    );
+
+    // The next tranche has to wait for the interval
+    let err = execute(
+        deps.as_mut(),
+        env_at(9),
+        mock_info("keeper", &[]),
+        ExecuteMsg::ExecuteTranche {},
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err(format!("next tranche can be executed at height {}", height + 10))
+    );
+
+    //------------------------------------------------------------
+    // Test 3. Execute the second tranche
+    //
+    execute(
+        deps.as_mut(),
+        env_at(10),
+        mock_info("keeper", &[]),
+        ExecuteMsg::ExecuteTranche {},
+    )
+    .unwrap();
+
+    let res: Option<ArbPlan> = query_helper(deps.as_ref(), QueryMsg::ArbPlan {});
+    assert_eq!(
+        res,
+        Some(ArbPlan {
+            total_amount: Uint128::new(50_000_000_000),
+            tranche_size: Uint128::new(20_000_000_000),
+            min_profit: Decimal::from_str("0.05").unwrap(),
+            interval_blocks: 10,
+            executed: Uint128::new(40_000_000_000),
+            tranches_executed: 2,
+            scheduled_at: height,
+            last_executed_at: Some(height + 10),
+            status: PlanStatus::Active,
+        })
+    );
+
+    //------------------------------------------------------------
+    // Test 4. The discount has closed, so the plan ends
+    //
+    deps.querier.set_pair_pools(Uint128::new(1_000_000_000_000), Uint128::new(1_000_000_000_000));
+
+    let res = execute(
+        deps.as_mut(),
+        env_at(20),
+        mock_info("keeper", &[]),
+        ExecuteMsg::ExecuteTranche {},
+    )
+    .unwrap();
+    assert!(res.messages.is_empty());
+    assert!(res.attributes.contains(&attr("status", "discount_closed")));
+
+    let plan: Option<ArbPlan> = query_helper(deps.as_ref(), QueryMsg::ArbPlan {});
+    let plan = plan.unwrap();
+    assert_eq!(plan.status, PlanStatus::DiscountClosed);
+    assert_eq!(plan.executed, Uint128::new(40_000_000_000));
+
+    let err = execute(
+        deps.as_mut(),
+        env_at(30),
+        mock_info("keeper", &[]),
+        ExecuteMsg::ExecuteTranche {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("arb plan is not active"));
+
+    //------------------------------------------------------------
+    // Test 5. Only the owner can cancel an active plan
+    //
+    deps.querier.set_pair_pools(Uint128::new(1_000_000_000_000), Uint128::new(1_100_000_000_000));
+    execute(
+        deps.as_mut(),
+        env_at(20),
+        mock_info("owner", &[]),
+        schedule_msg(50_000_000_000, 20_000_000_000, "0.05"),
+    )
+    .unwrap();
+
+    let err = execute(
+        deps.as_mut(),
+        env_at(20),
+        mock_info("keeper", &[]),
+        ExecuteMsg::CancelArbPlan {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    execute(deps.as_mut(), env_at(20), mock_info("owner", &[]), ExecuteMsg::CancelArbPlan {})
+        .unwrap();
+
+    let plan: Option<ArbPlan> = query_helper(deps.as_ref(), QueryMsg::ArbPlan {});
+    assert_eq!(plan.unwrap().status, PlanStatus::Cancelled);
+
+    // A cancelled plan can't be executed or cancelled again
+    let err = execute(
+        deps.as_mut(),
+        env_at(30),
+        mock_info("keeper", &[]),
+        ExecuteMsg::ExecuteTranche {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("arb plan is not active"));
+
+    let err = execute(
+        deps.as_mut(),
+        env_at(30),
+        mock_info("owner", &[]),
+        ExecuteMsg::CancelArbPlan {},
+    )
+    .unwrap_err();
+    assert_eq!(err, StdError::generic_err("arb plan is not active"));
+
+    //------------------------------------------------------------
+    // Test 6. A new plan replaces the old one, and finishes once its total amount is executed
+    //
+    execute(
+        deps.as_mut(),
+        env_at(30),
+        mock_info("owner", &[]),
+        schedule_msg(15_000_000_000, 20_000_000_000, "0.05"),
+    )
+    .unwrap();
+    let res = execute(
+        deps.as_mut(),
+        env_at(30),
+        mock_info("keeper", &[]),
+        ExecuteMsg::ExecuteTranche {},
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 2);
+
+    let plan: Option<ArbPlan> = query_helper(deps.as_ref(), QueryMsg::ArbPlan {});
+    let plan = plan.unwrap();
+    assert_eq!(plan.status, PlanStatus::Finished);
+    assert_eq!(plan.executed, Uint128::new(15_000_000_000));
+    assert_eq!(plan.tranches_executed, 1);
+}
+
+fn create_ust_config() -> Config<Addr> {
+    let mut config = create_config();
+    config.ust_borrowing = Some(UstBorrowing {
+        router: Addr::unchecked("router"),
+        max_spread: Decimal::from_str("0.01").unwrap(),
+        max_debt: Uint128::new(10_000_000_000),
+    });
+    config
+}
+
+fn router_swap_msg(offer: &str, route: &[&str]) -> Binary {
+    let info = |denom: &str| {
+        if denom.starts_with('u') {
+            astroport::asset::AssetInfo::NativeToken {
+                denom: String::from(denom),
+            }
+        } else {
+            astroport::asset::AssetInfo::Token {
+                contract_addr: Addr::unchecked(denom),
+            }
+        }
+    };
+    let operations = [&[offer], route]
+        .concat()
+        .windows(2)
+        .map(|hop| astroport::router::SwapOperation::AstroSwap {
+            offer_asset_info: info(hop[0]),
+            ask_asset_info: info(hop[1]),
+        })
+        .collect();
+    to_binary(&astroport::router::ExecuteMsg::ExecuteSwapOperations {
+        operations,
+        minimum_receive: None,
+        to: None,
+        max_spread: Some(Decimal::from_str("0.01").unwrap()),
+    })
+    .unwrap()
+}
+
+#[test]
+fn executing_ust_arb() {
+    let mut deps = setup_test();
+
+    deps.querier.set_router_address("router");
+    deps.querier.set_router_price("uusd", "uluna", Decimal::from_str("0.02").unwrap());
+    set_ust_debt(&mut deps, 5_000_000_000);
+
+    let arb_msg = |amount: u128| ExecuteMsg::ExecuteUstArb {
+        amount: Uint128::new(amount),
+        minimum_profit: Decimal::from_str("0.05").unwrap(),
+        route: UstRoute::ViaLuna,
+    };
+
+    //------------------------------------------------------------
+    // Test 1. UST borrowing is disabled
+    //
+    let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), arb_msg(1_000_000_000))
+        .unwrap_err();
+    assert_eq!(err, StdError::generic_err("ust borrowing is disabled"));
+
+    CONFIG.save(deps.as_mut().storage, &create_ust_config()).unwrap();
+
+    //------------------------------------------------------------
+    // Test 2. Borrowing would exceed max debt
+    //
+    let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), arb_msg(6_000_000_000))
+        .unwrap_err();
+    assert_eq!(err, StdError::generic_err("ust debt 11000000000 would exceed max debt 10000000000"));
+
+    //------------------------------------------------------------
+    // Test 3. Borrow UST and swap it for bLuna via Luna
+    //
+    let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), arb_msg(5_000_000_000))
+        .unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::Borrow {
+                    asset: LegacyAsset::Native {
+                        denom: String::from("uusd")
+                    },
+                    amount: Uint128::new(5_000_000_000),
+                    recipient: None
+                })
+                .unwrap(),
+                funds: vec![]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 8,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("router"),
+                msg: router_swap_msg("uusd", &["uluna", "bluna_token"]),
+                funds: vec![Coin::new(5_000_000_000, "uusd")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Success
+        }
+    );
+
+    // 5_000_000_000 uusd buys 100_000_000 uluna; the swap should return 5% more bLuna than that
+    let minimum_receive = MINIMUM_RECEIVE.load(deps.as_ref().storage).unwrap();
+    assert_eq!(minimum_receive, Uint128::new(105_000_000));
+    let ust_borrowed = PENDING_UST_BORROWED.load(deps.as_ref().storage).unwrap();
+    assert_eq!(ust_borrowed, Uint128::new(5_000_000_000));
+
+    //------------------------------------------------------------
+    // Test 4. Record a position backed by the UST debt once the swap is done
+    //
+    let response = SubMsgExecutionResponse {
+        events: vec![
+            Event::new("from_contract")
+                .add_attribute("action", "swap")
+                .add_attribute("offer_asset", "uusd")
+                .add_attribute("ask_asset", "uluna")
+                .add_attribute("offer_amount", "5000000000")
+                .add_attribute("return_amount", "100000000"),
+            Event::new("from_contract")
+                .add_attribute("action", "swap")
+                .add_attribute("offer_asset", "uluna")
+                .add_attribute("ask_asset", "bluna_token")
+                .add_attribute("offer_amount", "100000000")
+                .add_attribute("return_amount", "108000000"),
+        ],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 8,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(
+        position,
+        Position {
+            id: 1,
+            borrowed: Uint128::zero(),
+            ust_borrowed: Uint128::new(5_000_000_000),
+            unbonded: Uint128::new(108_000_000),
+            batch_id: 2,
+            held: false,
+            opened_at: mock_env().block.time.seconds(),
+            profit_shares: create_config().profit_shares,
+            settlement: None,
+            exit: None,
+        }
+    );
+    assert!(PENDING_UST_BORROWED.may_load(deps.as_ref().storage).unwrap().is_none());
+}
+
+#[test]
+fn settling_ust_arb() {
+    let mut deps = setup_test();
+
+    CONFIG.save(deps.as_mut().storage, &create_ust_config()).unwrap();
+    deps.querier.set_router_address("router");
+    deps.querier.set_router_price("uluna", "uusd", Decimal::from_str("25").unwrap());
+    set_ust_debt(&mut deps, 450_000_000_000);
+
+    // Position 1 is backed by Luna debt; position 2 by UST debt. Both have finished unbonding.
+    save_position(deps.as_mut().storage, 1, 100_000_000_000, 108_000_000_000, 1);
+    save_ust_position(deps.as_mut().storage, 2, 400_000_000_000, 20_000_000_000);
+    deps.querier.set_hub_unbond_requests(MOCK_CONTRACT_ADDR, vec![]);
+
+    let withdraw_response = |amount: &str| SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "finish_burn")
+            .add_attribute("from", "bluna_hub")
+            .add_attribute("amount", amount)],
+        data: None,
+    };
+
+    //------------------------------------------------------------
+    // Test 1. Luna is swapped for UST to repay the UST debt
+    //
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(withdraw_response("128000000000")),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 5);
+
+    // 20_000_000_000 uluna is worth 500_000_000_000 uusd, so the UST debt is worth 18_000_000_000
+    // uluna
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 9,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("router"),
+                msg: router_swap_msg("uluna", &["uusd"]),
+                funds: vec![Coin::new(18_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Always
+        }
+    );
+    // The Luna debt is repaid in Luna as usual
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(101_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    // Profit of both positions is distributed together
+    assert_eq!(
+        res.messages[2],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(1_800_000_000, "uluna")] // 9_000_000_000 * 0.2
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 2 });
+    assert_eq!(
+        position.settlement,
+        Some(Settlement {
+            settled_at: mock_env().block.time.seconds(),
+            received: Uint128::new(20_000_000_000),
+            debt: Uint128::new(18_000_000_000),
+            repaid: Uint128::new(18_000_000_000),
+            shortfall: Uint128::zero(),
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: Uint128::new(2_000_000_000),
+        })
+    );
+    assert_eq!(UST_BAD_DEBT.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+
+    //------------------------------------------------------------
+    // Test 2. UST received from the swap is repaid to Red Bank
+    //
+    let response = SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "uluna")
+            .add_attribute("ask_asset", "uusd")
+            .add_attribute("offer_amount", "18000000000")
+            .add_attribute("return_amount", "450000000000")],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 9,
+            result: ContractResult::Ok(response),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uusd"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(450_000_000_000, "uusd")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }]
+    );
+
+    //------------------------------------------------------------
+    // Test 3. If Luna's price has dropped, the UST debt that cannot be repaid goes bad
+    //
+    deps.querier.set_router_price("uluna", "uusd", Decimal::from_str("20").unwrap());
+    save_ust_position(deps.as_mut().storage, 3, 400_000_000_000, 20_000_000_000);
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 2,
+            result: ContractResult::Ok(withdraw_response("20000000000")),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 4); // swap, then zero payouts to alice, bob and owner
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 9,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("router"),
+                msg: router_swap_msg("uluna", &["uusd"]),
+                funds: vec![Coin::new(20_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Always
+        }
+    );
+
+    // The UST debt is worth 22_500_000_000 uluna, of which 20_000_000_000 uluna is repaid. There is
+    // no reserve fund or first-loss capital to cover the shortfall, so it is carried forward.
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 3 });
+    let settlement = position.settlement.unwrap();
+    assert_eq!(settlement.debt, Uint128::new(22_500_000_000));
+    assert_eq!(settlement.shortfall, Uint128::new(2_500_000_000));
+    assert_eq!(settlement.profit, Uint128::zero());
+    assert_eq!(CARRIED_LOSS.load(deps.as_ref().storage).unwrap(), Uint128::new(2_500_000_000));
+    assert_eq!(UST_BAD_DEBT.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+
+    // The swap returns 400_000_000_000 uusd, which is repaid; the rest of the UST debt goes bad
+    let ust_swap_response = |offer_amount: &str, return_amount: &str| SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", "uluna")
+            .add_attribute("ask_asset", "uusd")
+            .add_attribute("offer_amount", offer_amount)
+            .add_attribute("return_amount", return_amount)],
+        data: None,
+    };
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 9,
+            result: ContractResult::Ok(ust_swap_response("20000000000", "400000000000")),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 1);
+    assert_eq!(UST_BAD_DEBT.load(deps.as_ref().storage).unwrap(), Uint128::new(50_000_000_000));
+    assert!(PENDING_UST_REPAYMENT.may_load(deps.as_ref().storage).unwrap().is_none());
+
+    //------------------------------------------------------------
+    // Test 4. Owner repays UST bad debt from the reserve fund
+    //
+    let repay_msg = ExecuteMsg::RepayUstBadDebt {
+        amount: Uint128::new(10_000_000_000),
+    };
+
+    let err = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), repay_msg.clone())
+        .unwrap_err();
+    assert_eq!(err, StdError::generic_err("sender is not owner"));
+
+    let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), repay_msg.clone())
+        .unwrap_err();
+    assert_eq!(err, StdError::generic_err("amount 10000000000 is greater than reserve 0"));
+
+    RESERVE.save(deps.as_mut().storage, &Uint128::new(10_000_000_000)).unwrap();
+    let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), repay_msg).unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg {
+            id: 9,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("router"),
+                msg: router_swap_msg("uluna", &["uusd"]),
+                funds: vec![Coin::new(10_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Always
+        }]
+    );
+    assert_eq!(RESERVE.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+
+    //------------------------------------------------------------
+    // Test 5. If the swap fails, the Luna goes back to the reserve fund
+    //
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 9,
+            result: ContractResult::Err(String::from("Operation exceeds max spread limit")),
+        },
+    )
+    .unwrap();
+    assert!(res.messages.is_empty());
+    assert_eq!(RESERVE.load(deps.as_ref().storage).unwrap(), Uint128::new(10_000_000_000));
+    assert_eq!(UST_BAD_DEBT.load(deps.as_ref().storage).unwrap(), Uint128::new(50_000_000_000));
+
+    //------------------------------------------------------------
+    // Test 6. The UST received repays the UST bad debt
+    //
+    let repay_msg = ExecuteMsg::RepayUstBadDebt {
+        amount: Uint128::new(1_000_000_000),
+    };
+    execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), repay_msg).unwrap();
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 9,
+            result: ContractResult::Ok(ust_swap_response("1000000000", "20000000000")),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        res.messages,
+        vec![SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uusd"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(20_000_000_000, "uusd")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }]
+    );
+    assert_eq!(UST_BAD_DEBT.load(deps.as_ref().storage).unwrap(), Uint128::new(30_000_000_000));
+}
+
+#[test]
+fn executing_cross_lsd_arb() {
+    let mut deps = setup_test();
+
+    // bLuna is cheap at the bLuna/Luna pair, while stLuna is dear at the stLuna/Luna pair
+    deps.querier.set_other_pair_pools(
+        "bluna_pair",
+        &[("uluna", 1_000_000_000_000), ("bluna_token", 1_100_000_000_000)],
+    );
+    deps.querier.set_other_pair_pools(
+        "lsd_pair",
+        &[("bluna_token", 1_000_000_000_000), ("stluna_token", 1_000_000_000_000)],
+    );
+    deps.querier.set_other_pair_pools(
+        "stluna_pair",
+        &[("stluna_token", 1_000_000_000_000), ("uluna", 1_100_000_000_000)],
+    );
+
+    let arb_msg = ExecuteMsg::CrossLsdArb {
+        amount: Uint128::new(10_000_000_000),
+        minimum_profit: Decimal::from_str("0.05").unwrap(),
+    };
+    let swap_response = |offer: &str, ask: &str, return_amount: &str| SubMsgExecutionResponse {
+        events: vec![Event::new("from_contract")
+            .add_attribute("action", "swap")
+            .add_attribute("offer_asset", offer)
+            .add_attribute("ask_asset", ask)
+            .add_attribute("offer_amount", "0")
+            .add_attribute("return_amount", return_amount)],
+        data: None,
+    };
+    let cw20_swap_msg = |token: &str, pair: &str, amount: u128| SubMsg {
+        id: 10,
+        msg: CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: String::from(token),
+            msg: to_binary(&Cw20ExecuteMsg::Send {
+                contract: String::from(pair),
+                amount: Uint128::new(amount),
+                msg: to_binary(&astroport::pair::Cw20HookMsg::Swap {
+                    belief_price: None,
+                    max_spread: None,
+                    to: None,
+                })
+                .unwrap(),
+            })
+            .unwrap(),
+            funds: vec![],
+        }),
+        gas_limit: None,
+        reply_on: ReplyOn::Success,
+    };
+
+    //------------------------------------------------------------
+    // Test 1. Cross-LSD arb is disabled
+    //
+    let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), arb_msg.clone())
+        .unwrap_err();
+    assert_eq!(err, StdError::generic_err("cross-lsd arb is disabled"));
+
+    let mut config = create_config();
+    config.cross_lsd = Some(CrossLsd {
+        stluna_token: Addr::unchecked("stluna_token"),
+        stluna_pair: Addr::unchecked("stluna_pair"),
+        lsd_pair: Addr::unchecked("lsd_pair"),
+    });
+    CONFIG.save(deps.as_mut().storage, &config).unwrap();
+
+    //------------------------------------------------------------
+    // Test 2. Borrow Luna and buy bLuna, the cheaper LSD
+    //
+    let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), arb_msg).unwrap();
+    assert_eq!(res.messages.len(), 2);
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 10,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("bluna_pair"),
+                msg: to_binary(&astroport::pair::ExecuteMsg::Swap {
+                    offer_asset: astroport::asset::Asset {
+                        info: astroport::asset::AssetInfo::NativeToken {
+                            denom: String::from("uluna"),
+                        },
+                        amount: Uint128::new(10_000_000_000),
+                    },
+                    belief_price: None,
+                    max_spread: None,
+                    to: None,
+                })
+                .unwrap(),
+                funds: vec![Coin::new(10_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Success
+        }
+    );
+
+    let legs = PENDING_LEGS.load(deps.as_ref().storage).unwrap();
+    assert_eq!(
+        legs,
+        vec![
+            Addr::unchecked("bluna_pair"),
+            Addr::unchecked("lsd_pair"),
+            Addr::unchecked("stluna_pair")
+        ]
+    );
+
+    //------------------------------------------------------------
+    // Test 3. Swap bLuna for stLuna, then stLuna for Luna
+    //
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 10,
+            result: ContractResult::Ok(swap_response("uluna", "bluna_token", "10891089108")),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages, vec![cw20_swap_msg("bluna_token", "lsd_pair", 10_891_089_108)]);
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 10,
+            result: ContractResult::Ok(swap_response("bluna_token", "stluna_token", "10773751223")),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages, vec![cw20_swap_msg("stluna_token", "stluna_pair", 10_773_751_223)]);
+
+    //------------------------------------------------------------
+    // Test 4. Too little Luna is returned
+    //
+    let err = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 10,
+            result: ContractResult::Ok(swap_response("stluna_token", "uluna", "10400000000")),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("too little received from swap: native:uluna:10400000000, expecting at least 10500000000")
+    );
+
+    //------------------------------------------------------------
+    // Test 5. Repay the debt and distribute the profit
+    //
+    PENDING_LEGS
+        .save(deps.as_mut().storage, &vec![Addr::unchecked("stluna_pair")])
+        .unwrap();
+    MINIMUM_RECEIVE
+        .save(deps.as_mut().storage, &Uint128::new(10_500_000_000))
+        .unwrap();
+
+    let res = reply(
+        deps.as_mut(),
+        mock_env(),
+        Reply {
+            id: 10,
+            result: ContractResult::Ok(swap_response("stluna_token", "uluna", "11724806200")),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.messages.len(), 4);
+    assert_eq!(
+        res.messages[0],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("red_bank"),
+                msg: to_binary(&mars_core::red_bank::msg::ExecuteMsg::RepayNative {
+                    denom: String::from("uluna"),
+                    on_behalf_of: None
+                })
+                .unwrap(),
+                funds: vec![Coin::new(10_000_000_000, "uluna")]
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+    assert_eq!(
+        res.messages[1],
+        SubMsg {
+            id: 0,
+            msg: CosmosMsg::Bank(BankMsg::Send {
+                to_address: String::from("alice"),
+                amount: vec![Coin::new(344_961_240, "uluna")] // 1_724_806_200 * 0.2
+            }),
+            gas_limit: None,
+            reply_on: ReplyOn::Never
+        }
+    );
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(
+        position.settlement,
+        Some(Settlement {
+            settled_at: mock_env().block.time.seconds(),
+            received: Uint128::new(11_724_806_200),
+            debt: Uint128::new(10_000_000_000),
+            repaid: Uint128::new(10_000_000_000),
+            shortfall: Uint128::zero(),
+            prepaid: Uint128::zero(),
+            reimbursed: Uint128::zero(),
+            profit: Uint128::new(1_724_806_200),
+        })
+    );
+    assert!(PENDING_LEGS.may_load(deps.as_ref().storage).unwrap().is_none());
+    assert!(PENDING_BORROWED.may_load(deps.as_ref().storage).unwrap().is_none());
+}
+
+#[test]
+fn migrating() {
+    let mut deps = mock_dependencies();
+    deps.querier.set_red_bank_address("red_bank");
+    deps.querier.set_hub_address("bluna_hub");
+    set_user_debt(&mut deps, 101_000_000_000);
+
+    //------------------------------------------------------------
+    // Test 1. A config in an older format must be replaced
+    //
+    deps.as_mut().storage.set(b"config", br#"{"owner":"owner"}"#);
+
+    let err = migrate(deps.as_mut(), mock_env(), MigrateMsg { config: None }).unwrap_err();
+    assert_eq!(
+        err,
+        StdError::generic_err("stored config cannot be loaded; a new config must be provided")
+    );
+
+    //------------------------------------------------------------
+    // Test 2. Unbonds in flight are recorded as positions, attributed the debt by bLuna unbonded
+    //
+    CONFIG.save(deps.as_mut().storage, &create_config()).unwrap();
+    deps.querier.set_hub_unbond_requests(
+        MOCK_CONTRACT_ADDR,
+        vec![(1, Uint128::new(60_000_000_000)), (2, Uint128::new(40_000_000_000))],
+    );
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg { config: None }).unwrap();
+    assert_eq!(POSITION_COUNT.load(deps.as_ref().storage).unwrap(), 2);
+    assert_eq!(RESERVE.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+    assert_eq!(BAD_DEBT.load(deps.as_ref().storage).unwrap(), Uint128::zero());
+    assert!(!PAUSED.load(deps.as_ref().storage).unwrap());
+
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 1 });
+    assert_eq!(
+        position,
+        Position {
+            id: 1,
+            borrowed: Uint128::new(60_600_000_000),
+            ust_borrowed: Uint128::zero(),
+            unbonded: Uint128::new(60_000_000_000),
+            batch_id: 1,
+            held: false,
+            opened_at: mock_env().block.time.seconds(),
+            profit_shares: create_config().profit_shares,
+            settlement: None,
+            exit: None,
+        }
+    );
+    let position: Position = query_helper(deps.as_ref(), QueryMsg::Position { id: 2 });
+    assert_eq!(position.borrowed, Uint128::new(40_400_000_000));
+    assert_eq!(position.batch_id, 2);
+
+    //------------------------------------------------------------
+    // Test 3. State that already exists is left as is, and the config can be replaced
+    //
+    POSITION_COUNT.save(deps.as_mut().storage, &5).unwrap();
+    let mut config = create_config();
+    config.reserve_ratio = Decimal::from_str("0.1").unwrap();
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg { config: Some(config.clone().into()) }).unwrap();
+    assert_eq!(POSITION_COUNT.load(deps.as_ref().storage).unwrap(), 5);
+    assert_eq!(CONFIG.load(deps.as_ref().storage).unwrap(), config);
+
+    //------------------------------------------------------------
+    // Test 4. With nothing unbonding, the debt is not backed by any position and goes bad
+    //
+    let mut deps = mock_dependencies();
+    deps.querier.set_red_bank_address("red_bank");
+    deps.querier.set_hub_address("bluna_hub");
+    set_user_debt(&mut deps, 101_000_000_000);
+    CONFIG.save(deps.as_mut().storage, &create_config()).unwrap();
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg { config: None }).unwrap();
+    assert_eq!(POSITION_COUNT.load(deps.as_ref().storage).unwrap(), 0);
+    assert_eq!(BAD_DEBT.load(deps.as_ref().storage).unwrap(), Uint128::new(101_000_000_000));
 }
@@ -4,6 +4,9 @@ pub mod contract;
 pub mod adapters;
 pub mod config;
 pub mod msg;
+pub mod plan;
+pub mod position;
+pub mod reserve;
 pub mod state;
 
 #[cfg(test)]
@@ -1,4 +1,6 @@
-use cosmwasm_std::{Decimal, Empty, Uint128};
+use cosmwasm_std::{Decimal, Uint128};
+use cw20::Cw20ReceiveMsg;
+use cw_asset::AssetUnchecked;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -14,16 +16,167 @@ pub enum ExecuteMsg {
     /// 2. swap Luna for bLuna at Astroport pool
     /// 3. assert that profit (bLuna amount / Luna amount - 1) is greater than minimum profit
     /// 4. unbond bLuna at bLuna Hub
+    /// If `hold_ratio` is provided, that portion of the bLuna is retained as inventory instead of
+    /// being unbonded, along with the same portion of the debt as its cost basis. Must be less than
+    /// one.
     ExecuteArb {
         amount: Uint128,
         minimum_profit: Decimal,
+        hold_ratio: Option<Decimal>,
     },
+    /// Execute an arbitrage operation with UST borrowed from Red Bank, if enabled in config:
+    /// 1. borrow UST of specified amount from Red Bank
+    /// 2. swap UST for bLuna through Astroport router, along the specified route
+    /// 3. assert that profit (bLuna amount / Luna amount - 1) is greater than minimum profit, where
+    ///    the Luna amount is what the UST would buy at Astroport
+    /// 4. unbond bLuna at bLuna Hub
+    /// Once unbonded, enough Luna is swapped back to UST to repay the debt. Only callable by owner.
+    ExecuteUstArb {
+        amount: Uint128,
+        minimum_profit: Decimal,
+        route: UstRoute,
+    },
+    /// Execute an arbitrage operation between bLuna and stLuna, if enabled in config:
+    /// 1. borrow Luna of specified amount from Red Bank
+    /// 2. swap Luna for whichever of bLuna and stLuna is cheaper
+    /// 3. swap it for the other one at Astroport bLuna/stLuna pair
+    /// 4. swap that back for Luna
+    /// 5. assert that profit (Luna amount received / Luna amount borrowed - 1) is greater than
+    ///    minimum profit, and repay the debt
+    /// Only callable by owner.
+    CrossLsdArb {
+        amount: Uint128,
+        minimum_profit: Decimal,
+    },
+    /// Execute an arbitrage operation within the parameters of the strategy set by owner. The
+    /// contract sizes the trade as the largest amount that makes the strategy's minimum profit, up
+    /// to its maximum size. The caller is paid a bounty on success. Callable by anyone, once the
+    /// strategy's cooldown has passed since the last triggered arb.
+    TriggerArb {},
+    /// Schedule an arbitrage operation of the specified total amount, to be executed in tranches of
+    /// at most `tranche_size`, at least `interval_blocks` apart, so that the pool's discount can
+    /// refill between tranches. `min_profit` must be greater than zero and less than one. Replaces
+    /// any plan already scheduled. Only callable by owner.
+    ScheduleArb {
+        total_amount: Uint128,
+        tranche_size: Uint128,
+        min_profit: Decimal,
+        interval_blocks: u64,
+    },
+    /// Execute the next tranche of the scheduled arb plan, the same way as `ExecuteArb`. If the
+    /// tranche would not make the plan's minimum profit, the plan is ended instead. Callable by
+    /// anyone, once the plan's interval has passed since the last tranche.
+    ExecuteTranche {},
+    /// Cancel the rest of the scheduled arb plan. Only callable by owner.
+    CancelArbPlan {},
     /// Once bLuna unbonding is finished,
     /// 1. claim unbonded Luna
     /// 2. repay Luna debt to Red Bank
-    /// 3. pay fee to Mars treasury
-    /// 4. distribute the remaining reward to owner
+    /// 3. pay fee on the realised profit to Mars treasury
+    /// 4. pay keeper bounty to the caller, if any unbonded Luna was claimed
+    /// 5. distribute the remaining reward to recipients and owner
     FinializeArb {},
+    /// Once bLuna unbonding is finished, start a new arbitrage operation with the unbonded Luna
+    /// instead of repaying the debt and borrowing it again:
+    /// 1. claim unbonded Luna
+    /// 2. distribute the portion of the profit not being reinvested to recipients and owner
+    /// 3. swap the rest of the claimed Luna for bLuna, without repaying the debt
+    /// 4. assert that profit is greater than minimum profit
+    /// 5. unbond bLuna at bLuna Hub
+    Rollover {
+        minimum_profit: Decimal,
+        reinvest_ratio: Decimal,
+    },
+    /// Deposit Luna as first-loss capital, which is used to cover shortfalls, i.e. when the
+    /// unbonded Luna of a position is not enough to repay its debt. If there is any bad debt, the
+    /// deposit is first used to repay it. Only callable by owner.
+    DepositFirstLoss {},
+    /// Update the contract's config. Changes to profit shares only apply to positions opened after
+    /// the update. Red Bank, council and the protocol fee rate cannot be changed. Only callable by
+    /// owner.
+    UpdateConfig { config: Config<String> },
+    /// Claim the profit credited to the sender, if payout mode is `Pull`
+    ClaimProfit {},
+    /// Force an unwind of the contract's positions. Only callable by Red Bank or council:
+    /// 1. pause new arbs
+    /// 2. claim unbonded Luna of matured positions, if any, and use all of it to repay debt
+    /// 3. if `sell_bluna` is true, sell bLuna held by the contract for Luna at Astroport, and use
+    ///    the proceeds to repay debt
+    /// Until resumed, profit from positions settled later is also used to repay debt first.
+    /// NOTE: bLuna already submitted for unbonding cannot be sold.
+    ForceUnwind { sell_bluna: bool },
+    /// Resume new arbs after a forced unwind. Only callable by Red Bank or council.
+    Resume {},
+    /// Sell all bLuna held by the contract, i.e. bLuna not submitted for unbonding, for Luna at
+    /// Astroport with the specified maximum spread, and use the proceeds to repay debt immediately.
+    /// The sale is recorded as a position, along with its loss compared to unbonding the bLuna.
+    /// Only callable by owner or guardian.
+    EmergencyExit { max_spread: Decimal },
+    /// Take over ownership once the owner has been inactive for longer than the recovery timeout.
+    /// Profit credited to the previous owner is credited to the new owner instead. Only callable by
+    /// the recovery address.
+    ClaimOwnership {},
+    /// Use Luna in the reserve fund to repay the debt of open positions early, saving interest. The
+    /// prepaid amount is reimbursed to the reserve as the positions are settled. Only callable by
+    /// owner.
+    Prepay { amount: Uint128 },
+    /// Swap the specified amount of Luna in the reserve fund for UST through Astroport router, and
+    /// use it to repay UST bad debt, i.e. UST debt that unbonded Luna was insufficient to repay.
+    /// Only callable by owner.
+    RepayUstBadDebt { amount: Uint128 },
+    /// Deposit Luna held by the contract in Red Bank to earn interest, if enabled in config.
+    /// First-loss capital and profit yet to be claimed are not deposited. The deposit, including
+    /// interest, is withdrawn before the next operation that needs Luna; the interest is added to
+    /// the reserve fund until the reserve reaches its target. Callable by anyone.
+    Park {},
+    /// Submit bLuna held for batch unbonding to bLuna Hub, claiming the rewards accrued on it to
+    /// the owner first. Only possible within the submit window before bLuna Hub's next batch time.
+    /// Callable by anyone.
+    SubmitUnbonds {},
+    /// Claim the UST rewards accrued on bLuna held by the contract, i.e. inventory and bLuna held
+    /// for batch unbonding, from bLuna reward contract for the owner. Callable by anyone.
+    ClaimRewards {},
+    /// Unbond the specified amount of bLuna inventory at bLuna Hub. The bLuna is recorded as a new
+    /// position, backed by its portion of the inventory's cost basis. Only callable by owner.
+    UnbondInventory { amount: Uint128 },
+    /// Sell the specified amount of bLuna inventory for Luna at Astroport with the specified
+    /// maximum spread. The sale is recorded as a position backed by its portion of the inventory's
+    /// cost basis, and settled right away; the proceeds repay its debt, and the profit is
+    /// distributed as with any other position. Only callable by owner.
+    SellInventory {
+        amount: Uint128,
+        max_spread: Decimal,
+    },
+    /// Sell bLuna to the liquidity desk, if enabled in config, by sending it along with a
+    /// `ReceiveMsg`. The seller is paid Luna borrowed from Red Bank right away, at the desk's
+    /// discount to bLuna Hub's exchange rate; the bLuna is unbonded by the contract, and the fill
+    /// is recorded as a position.
+    Receive(Cw20ReceiveMsg),
+    /// Transfer tokens held by the contract, e.g. tokens sent to it by mistake, to the specified
+    /// recipient. Luna needed to repay the debt owed to Red Bank cannot be swept, nor can maLuna
+    /// while idle Luna is parked in Red Bank. Only callable by owner.
+    Sweep {
+        asset: AssetUnchecked,
+        recipient: String,
+    },
+}
+
+/// Route along which borrowed UST is swapped for bLuna
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UstRoute {
+    /// UST to Luna, then Luna to bLuna
+    ViaLuna,
+    /// UST to bLuna directly
+    Direct,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    /// Sell the bLuna sent to the liquidity desk. Reverts if the Luna paid would be less than
+    /// `minimum_receive`, if provided.
+    Sell { minimum_receive: Option<Uint128> },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -35,8 +188,46 @@ pub enum QueryMsg {
     /// - debt owed to Red Bank
     /// - ongoing unbonding requests at bLuna Hub
     /// - withdrawable unbonded amount at bLuna Hub
+    /// - first-loss capital available for covering shortfalls
+    /// - total unresolved bad debt
+    /// - total loss carried forward, to be recovered from profit before paying profit shares
+    /// - debt of open positions prepaid from the reserve fund
+    /// - idle Luna deposited in Red Bank, and the interest accrued on it
+    /// - whether new arbs are paused due to a forced unwind
+    /// - timestamp of the owner's last execute
     /// Respons: `StatusResponse`
     Status {},
+    /// A position, either still unbonding or already settled. Response: `crate::position::Position`
+    Position { id: u64 },
+    /// Balance and target of the reserve fund, and the history of changes in its balance, in
+    /// ascending order of ids. Response: `ReserveResponse`
+    Reserve {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Amount of Luna profit credited to an account and yet to be claimed.
+    /// Response: `ClaimableResponse`
+    Claimable { address: String },
+    /// Whether calling `FinalizeArb` now would withdraw any unbonded Luna and pay a keeper bounty,
+    /// and an estimate of the bounty. Response: `FinalizableResponse`
+    Finalizable {},
+    /// The contract's solvency as seen by Red Bank: total debt, the debt projected at the maturity
+    /// of each pending unbond, the Luna value of pending unbonds at bLuna Hub's current exchange
+    /// rate, withdrawable Luna, the reserve fund, and the resulting coverage ratio.
+    /// Response: `CreditReportResponse`
+    CreditReport {},
+    /// Every unbonding request at bLuna Hub not yet withdrawn, with its expected release time,
+    /// expected Luna and the positions it belongs to, and whether `FinalizeArb` would withdraw any
+    /// Luna now. Response: `ScheduleResponse`
+    Schedule {},
+    /// bLuna retained as inventory and its cost basis. Response: `crate::position::Inventory`
+    Inventory {},
+    /// The arb plan being executed in tranches, or the last one if it has ended; `null` if no plan
+    /// has been scheduled. Response: `Option<crate::plan::ArbPlan>`
+    ArbPlan {},
+    /// The amount of Luna the liquidity desk would pay for the specified amount of bLuna now.
+    /// Response: `QuoteResponse`
+    Quote { amount: Uint128 },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -44,6 +235,122 @@ pub struct StatusResponse {
     pub debt: mars_core::red_bank::UserAssetDebtResponse,
     pub unbond_requests: basset::hub::UnbondRequestsResponse,
     pub withdrawable_unbonded: basset::hub::WithdrawableUnbondedResponse,
+    pub first_loss_capital: Uint128,
+    pub bad_debt: Uint128,
+    pub carried_loss: Uint128,
+    pub prepaid: Uint128,
+    pub deposited: Uint128,
+    pub deposit_interest: Uint128,
+    pub paused: bool,
+    pub owner_last_active: u64,
 }
 
-pub type MigrateMsg = Empty;
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReserveResponse {
+    pub balance: Uint128,
+    pub target: Uint128,
+    pub history: Vec<crate::reserve::ReserveChange>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClaimableResponse {
+    pub address: String,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FinalizableResponse {
+    pub finalizable: bool,
+    pub withdrawable: Uint128,
+    pub estimated_bounty: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CreditReportResponse {
+    /// Luna debt owed to Red Bank, including accrued interest
+    pub total_debt: Uint128,
+    /// UST debt owed to Red Bank, including accrued interest
+    pub ust_debt: Uint128,
+    /// Luna value of the UST debt at Astroport router's current price
+    pub ust_debt_value: Uint128,
+    /// Pending unbonds, in ascending order of batch ids
+    pub maturities: Vec<Maturity>,
+    /// Luna value of all pending unbonds at bLuna Hub's current exchange rate
+    pub pending_unbond_value: Uint128,
+    /// Luna value of bLuna held for batch unbonding or retained in the inventory, at bLuna Hub's
+    /// current exchange rate
+    pub held_value: Uint128,
+    /// Amount of unbonded Luna that can be withdrawn from bLuna Hub
+    pub withdrawable: Uint128,
+    /// Balance of the reserve fund
+    pub reserve: Uint128,
+    /// Sum of pending unbond value, held value, withdrawable Luna and reserve over total debt,
+    /// including the Luna value of the UST debt; `None` if there is no debt
+    pub coverage_ratio: Option<Decimal>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Maturity {
+    /// Id of the bLuna Hub unbonding batch
+    pub batch_id: u64,
+    /// Estimated timestamp (in seconds) when the unbonding finishes
+    pub matures_at: u64,
+    /// Luna value of the bLuna being unbonded, at bLuna Hub's current exchange rate
+    pub luna_value: Uint128,
+    /// Total debt, including the Luna value of the UST debt, projected at the maturity, accruing
+    /// interest at Red Bank's current borrow rates
+    pub projected_debt: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ScheduleResponse {
+    /// Unbonding requests not yet withdrawn, in ascending order of batch ids
+    pub tranches: Vec<Tranche>,
+    /// Amount of unbonded Luna that can be withdrawn from bLuna Hub
+    pub withdrawable: Uint128,
+    /// Whether calling `FinalizeArb` now would withdraw any unbonded Luna
+    pub ready_now: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Tranche {
+    /// Id of the bLuna Hub unbonding batch
+    pub batch_id: u64,
+    /// Amount of bLuna being unbonded
+    pub bluna_amount: Uint128,
+    /// Estimated timestamp (in seconds) when the unbonded Luna is released
+    pub expected_release: u64,
+    /// Amount of Luna expected, at the batch's withdraw rate if released, or bLuna Hub's current
+    /// exchange rate otherwise
+    pub expected_luna: Uint128,
+    /// Whether bLuna Hub has released the batch, i.e. the Luna is withdrawable
+    pub released: bool,
+    /// Ids of the positions whose bLuna was submitted to this batch
+    pub position_ids: Vec<u64>,
+    /// Amount of UST borrowed by the positions whose bLuna was submitted to this batch, to be
+    /// repaid by swapping the unbonded Luna for UST
+    pub ust_borrowed: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QuoteResponse {
+    /// Amount of bLuna to sell
+    pub bluna_amount: Uint128,
+    /// bLuna Hub's current exchange rate
+    pub exchange_rate: Decimal,
+    /// Peg recovery fee charged by bLuna Hub on unbonding, if the exchange rate is below its
+    /// threshold; zero otherwise
+    pub peg_fee: Decimal,
+    /// The desk's discount to the exchange rate
+    pub discount: Decimal,
+    /// Amount of Luna that would be paid
+    pub luna_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {
+    /// If provided, replaces the stored config. Required when migrating from a version whose config
+    /// has fewer or differently typed fields, as the stored config can then no longer be loaded;
+    /// migration fails if it is not provided in that case.
+    pub config: Option<Config<String>>,
+}
@@ -0,0 +1,41 @@
+use cosmwasm_std::{Decimal, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Progress of an arb plan
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanStatus {
+    /// Tranches are still to be executed
+    Active,
+    /// The plan's total amount has been executed
+    Finished,
+    /// A tranche no longer made the minimum profit, so the rest of the plan was abandoned
+    DiscountClosed,
+    /// The owner cancelled the rest of the plan
+    Cancelled,
+}
+
+/// A large arbitrage operation split into tranches executed across blocks, so that the pool's
+/// discount can refill between tranches
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ArbPlan {
+    /// Total amount of Luna to borrow and swap
+    pub total_amount: Uint128,
+    /// The maximum amount of Luna to borrow and swap per tranche
+    pub tranche_size: Uint128,
+    /// The minimum profit (bLuna amount / Luna amount - 1) that each tranche must make
+    pub min_profit: Decimal,
+    /// The minimum number of blocks between two tranches
+    pub interval_blocks: u64,
+    /// Amount of Luna borrowed and swapped so far
+    pub executed: Uint128,
+    /// Number of tranches executed so far
+    pub tranches_executed: u32,
+    /// Block height when the plan was scheduled
+    pub scheduled_at: u64,
+    /// Block height when the last tranche was executed; `None` if no tranche has been executed
+    pub last_executed_at: Option<u64>,
+    /// Progress of the plan
+    pub status: PlanStatus,
+}
@@ -0,0 +1,84 @@
+use cosmwasm_std::{Addr, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ProfitShare;
+
+/// An arbitrage position: Luna borrowed from Red Bank, swapped for bLuna, and unbonded at bLuna Hub
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Position {
+    /// Unique identifier of the position
+    pub id: u64,
+    /// Amount of Luna debt backing this position
+    pub borrowed: Uint128,
+    /// Amount of UST debt backing this position, if it was opened with UST borrowed from Red Bank.
+    /// The position's debt is then settled by swapping unbonded Luna for UST, and its settlement's
+    /// debt, repaid and shortfall amounts are the Luna equivalent at the swap's price.
+    #[serde(default)]
+    pub ust_borrowed: Uint128,
+    /// Amount of bLuna unbonded at bLuna Hub
+    pub unbonded: Uint128,
+    /// Id of the bLuna Hub unbonding batch that the bLuna was submitted to
+    pub batch_id: u64,
+    /// Whether the bLuna is held by the contract, yet to be submitted for unbonding. Batch id is
+    /// zero while held.
+    #[serde(default)]
+    pub held: bool,
+    /// Timestamp (in seconds) when the position was opened
+    pub opened_at: u64,
+    /// Profit shares in force when the position was opened, according to which the position's
+    /// profit is distributed
+    #[serde(default)]
+    pub profit_shares: Vec<ProfitShare<Addr>>,
+    /// Outcome of the position once the unbonded Luna has been withdrawn; `None` if still unbonding
+    pub settlement: Option<Settlement>,
+    /// If the position records an emergency exit, i.e. bLuna held by the contract being sold rather
+    /// than unbonded, the outcome of the sale compared to unbonding
+    #[serde(default)]
+    pub exit: Option<Exit>,
+}
+
+/// Outcome of a position once the unbonded Luna has been withdrawn
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Settlement {
+    /// Timestamp (in seconds) when the position was settled
+    pub settled_at: u64,
+    /// Amount of unbonded Luna attributed to this position
+    pub received: Uint128,
+    /// Amount of Luna debt attributed to this position, including accrued interest and the portion
+    /// prepaid from the reserve fund
+    pub debt: Uint128,
+    /// Amount of the debt repaid to Red Bank using the Luna received
+    pub repaid: Uint128,
+    /// Amount of the debt owed to Red Bank that the Luna received was insufficient to repay
+    pub shortfall: Uint128,
+    /// Portion of the debt that was prepaid from the reserve fund
+    #[serde(default)]
+    pub prepaid: Uint128,
+    /// Amount of the prepaid debt reimbursed to the reserve fund using the Luna received
+    #[serde(default)]
+    pub reimbursed: Uint128,
+    /// Amount of Luna remaining after repaying the debt and reimbursing the reserve fund
+    pub profit: Uint128,
+}
+
+/// Outcome of selling bLuna in an emergency exit, compared to unbonding it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Exit {
+    /// Amount of bLuna sold
+    pub sold: Uint128,
+    /// Amount of Luna that unbonding the bLuna would have returned, at bLuna Hub's exchange rate at
+    /// the time of the sale
+    pub expected: Uint128,
+    /// Amount by which the Luna received from the sale falls short of the expected amount
+    pub loss: Uint128,
+}
+
+/// bLuna retained from arbs rather than unbonded, e.g. to be sold later at a premium
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Inventory {
+    /// Amount of bLuna retained
+    pub amount: Uint128,
+    /// Amount of Luna debt backing the bLuna retained
+    pub cost_basis: Uint128,
+}
@@ -0,0 +1,42 @@
+use cosmwasm_std::Uint128;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Reason for a change in the reserve fund's balance
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReserveChangeKind {
+    /// A portion of the profit was retained in the reserve
+    ProfitRetained,
+    /// Luna was drawn from the reserve to cover a shortfall
+    ShortfallCovered,
+    /// Profit was used to recover carried loss, and added to the reserve
+    LossRecovered,
+    /// Luna was drawn from the reserve to pay the caller of a triggered arb
+    BountyPaid,
+    /// Luna was drawn from the reserve to prepay the debt of open positions
+    DebtPrepaid,
+    /// Settled positions reimbursed the reserve for the prepaid portion of their debt
+    PrepaymentReimbursed,
+    /// Interest earned on idle Luna deposited in Red Bank was added to the reserve
+    InterestEarned,
+    /// Luna was drawn from the reserve to be swapped for UST to repay UST bad debt
+    UstBadDebtRepaid,
+    /// Luna to be swapped for UST to repay UST debt was added to the reserve, as the swap failed
+    UstRepaymentFailed,
+}
+
+/// A record of a change in the reserve fund's balance
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReserveChange {
+    /// Unique identifier of the change
+    pub id: u64,
+    /// Timestamp (in seconds) when the change happened
+    pub timestamp: u64,
+    /// Reason for the change
+    pub kind: ReserveChangeKind,
+    /// Amount of Luna added to or drawn from the reserve
+    pub amount: Uint128,
+    /// Balance of the reserve after the change
+    pub balance: Uint128,
+}
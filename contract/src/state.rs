@@ -1,11 +1,129 @@
-use cosmwasm_std::{Addr, Uint128};
-use cw_storage_plus::Item;
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use cw_storage_plus::{Item, Map, U64Key};
 
 use crate::config::Config;
+use crate::plan::ArbPlan;
+use crate::position::{Inventory, Position};
+use crate::reserve::ReserveChange;
 
 /// The configurations of this contract
 pub const CONFIG: Item<Config<Addr>> = Item::new("config");
 
+/// Timestamp (in seconds) of the owner's last execute; used to find out whether the recovery
+/// address can claim ownership
+pub const OWNER_LAST_ACTIVE: Item<u64> = Item::new("owner_last_active");
+
+/// Total number of positions ever opened; used to assign ids to new positions
+pub const POSITION_COUNT: Item<u64> = Item::new("position_count");
+
+/// Positions whose bLuna is still being unbonded, indexed by position ids
+pub const POSITIONS: Map<U64Key, Position> = Map::new("positions");
+
+/// Positions that have been settled, indexed by position ids
+pub const CLOSED_POSITIONS: Map<U64Key, Position> = Map::new("closed_positions");
+
+/// Ids of the positions opened by the liquidity desk that may still be open; used to find out the
+/// desk's outstanding debt
+pub const DESK_POSITION_IDS: Item<Vec<u64>> = Item::new("desk_position_ids");
+
+/// bLuna retained from arbs as inventory, and the debt backing it
+pub const INVENTORY: Item<Inventory> = Item::new("inventory");
+
+/// Amount of Luna deposited by the owner as first-loss capital, to be used to cover shortfalls
+pub const FIRST_LOSS_CAPITAL: Item<Uint128> = Item::new("first_loss_capital");
+
+/// Amount of first-loss capital that has been used to cover shortfalls or repay bad debt, and is
+/// yet to be replenished from recovered losses
+pub const FIRST_LOSS_DRAWN: Item<Uint128> = Item::new("first_loss_drawn");
+
+/// Total amount of shortfalls not covered by first-loss capital, i.e. debt owed to Red Bank that is
+/// not backed by any position
+pub const BAD_DEBT: Item<Uint128> = Item::new("bad_debt");
+
+/// Total amount of UST debt that unbonded Luna was insufficient to repay, i.e. UST debt owed to Red
+/// Bank that is not backed by any position
+pub const UST_BAD_DEBT: Item<Uint128> = Item::new("ust_bad_debt");
+
+/// Total amount of losses, i.e. shortfalls, that have not yet been recovered from later profits.
+/// Profit shares are only paid once the carried loss is fully recovered.
+pub const CARRIED_LOSS: Item<Uint128> = Item::new("carried_loss");
+
+/// Amount of Luna in the reserve fund, retained from profit to cover future shortfalls
+pub const RESERVE: Item<Uint128> = Item::new("reserve");
+
+/// Total number of changes ever made to the reserve fund; used to assign ids to new records
+pub const RESERVE_CHANGE_COUNT: Item<u64> = Item::new("reserve_change_count");
+
+/// History of changes in the reserve fund's balance, indexed by record ids
+pub const RESERVE_HISTORY: Map<U64Key, ReserveChange> = Map::new("reserve_history");
+
+/// Amount of debt of open positions that has been prepaid from the reserve fund, to be reimbursed
+/// to the reserve as the positions are settled
+pub const PREPAID: Item<Uint128> = Item::new("prepaid");
+
+/// Amount of idle Luna deposited in Red Bank, excluding accrued interest
+pub const DEPOSITED: Item<Uint128> = Item::new("deposited");
+
+/// Whether new arbs are paused due to a forced unwind. While paused, profit from settled positions
+/// is used to repay debt first.
+pub const PAUSED: Item<bool> = Item::new("paused");
+
+/// The arb plan being executed in tranches, or the last one if it has ended
+pub const ARB_PLAN: Item<ArbPlan> = Item::new("arb_plan");
+
+/// Timestamp (in seconds) when an arb was last triggered; used to enforce the strategy's cooldown
+pub const LAST_TRIGGERED: Item<u64> = Item::new("last_triggered");
+
+/// Amount of Luna profit credited to each account and yet to be claimed, if payout mode is `Pull`
+pub const CLAIMABLE: Map<&Addr, Uint128> = Map::new("claimable");
+
 /// The minimum amount of bLuna to receive after a swap. We need to temporarily save it in storage
 /// so that it can be accessed when handling the submsg execution result.
 pub const MINIMUM_RECEIVE: Item<Uint128> = Item::new("minimum_receive");
+
+/// The amount of Luna debt backing the position being opened. We need to temporarily save it in
+/// storage so that it can be accessed when handling the swap submsg execution result.
+pub const PENDING_BORROWED: Item<Uint128> = Item::new("pending_borrowed");
+
+/// The amount of UST debt backing the position being opened. We need to temporarily save it in
+/// storage so that it can be accessed when handling the routed swap submsg execution result.
+pub const PENDING_UST_BORROWED: Item<Uint128> = Item::new("pending_ust_borrowed");
+
+/// Pairs through which the asset returned by the current leg of a cross-LSD arb is to be swapped,
+/// in order. We need to temporarily save them in storage so that the next leg can be executed when
+/// handling the swap submsg execution result.
+pub const PENDING_LEGS: Item<Vec<Addr>> = Item::new("pending_legs");
+
+/// Portion of the bLuna to be retained as inventory from the swap being executed. We need to
+/// temporarily save it in storage so that it can be accessed when handling the swap submsg
+/// execution result.
+pub const PENDING_HOLD_RATIO: Item<Decimal> = Item::new("pending_hold_ratio");
+
+/// Recipients, Astroport pairs and Luna amounts of payouts being converted or sent to contracts, in
+/// the order that the submsgs are executed; the pair is `None` for contract recipients. We need to
+/// temporarily save them in storage so that the payouts can be recorded, or sent as plain
+/// transfers if they fail, when handling the submsg execution results.
+pub const PENDING_PAYOUTS: Item<Vec<(Addr, Option<Addr>, Uint128)>> = Item::new("pending_payouts");
+
+/// The account that called `FinalizeArb`, to be paid the keeper bounty. We need to temporarily save
+/// it in storage so that it can be accessed when handling the withdrawal submsg execution result.
+pub const KEEPER: Item<Addr> = Item::new("keeper");
+
+/// Amount of bLuna being sold in an emergency exit or a forced unwind. We need to temporarily save
+/// it in storage so that the sale can be recorded when handling the swap submsg execution result.
+pub const PENDING_EXIT: Item<Uint128> = Item::new("pending_exit");
+
+/// Amount of bLuna inventory being sold, and its portion of the inventory's cost basis. We need to
+/// temporarily save them in storage so that the sale can be settled when handling the swap submsg
+/// execution result.
+pub const PENDING_SALE: Item<(Uint128, Uint128)> = Item::new("pending_sale");
+
+/// Amount of Luna being swapped for UST to repay UST debt, and the UST debt of the positions being
+/// closed that it is meant to repay. We need to temporarily save them in storage so that the UST
+/// debt left unpaid can be recorded, or the Luna added to the reserve fund if the swap fails, when
+/// handling the swap submsg execution result.
+pub const PENDING_UST_REPAYMENT: Item<(Uint128, Uint128)> = Item::new("pending_ust_repayment");
+
+/// The minimum profit and reinvest ratio of a rollover. We need to temporarily save them in storage
+/// so that they can be accessed when handling the withdrawal submsg execution result.
+pub const ROLLOVER_PARAMS: Item<(Decimal, Decimal)> = Item::new("rollover_params");
@@ -0,0 +1,35 @@
+use cosmwasm_std::{to_binary, Addr, QuerierResult};
+use mars_core::address_provider::msg::QueryMsg;
+use mars_core::address_provider::MarsContract;
+
+#[derive(Default)]
+pub struct AddressProviderQuerier {
+    /// Address of mock Mars address provider contract to be used in queries
+    pub address: String,
+    /// Addresses of Mars contracts
+    pub addresses: Vec<(MarsContract, String)>,
+}
+
+impl AddressProviderQuerier {
+    pub fn handle_query(&self, contract_addr: &String, query: QueryMsg) -> QuerierResult {
+        if contract_addr != &self.address {
+            panic!(
+                "[mock]: made an address provider query but contract address is incorrect; is {}, should be {}",
+                contract_addr,
+                self.address
+            );
+        }
+
+        match query {
+            QueryMsg::Address { contract } => {
+                if let Some((_, address)) = self.addresses.iter().find(|(c, _)| c == &contract) {
+                    Ok(to_binary(&Addr::unchecked(address)).into()).into()
+                } else {
+                    panic!("[mock]: address of {:?} is not set", contract);
+                }
+            },
+
+            _ => panic!("[mock]: address provider query is unimplemented")
+        }
+    }
+}
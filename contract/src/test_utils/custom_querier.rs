@@ -1,17 +1,34 @@
+use astroport::pair::QueryMsg as PairQueryMsg;
+use astroport::router::QueryMsg as RouterQueryMsg;
 use cosmwasm_std::testing::MockQuerier;
+use basset::hub::{QueryMsg as HubQueryMsg, UnbondHistory};
 use cosmwasm_std::{
-    from_binary, from_slice, Empty, Querier, QuerierResult, QueryRequest, SystemError, WasmQuery,
+    from_binary, from_slice, Coin, Decimal, Empty, Querier, QuerierResult, QueryRequest,
+    SystemError, Uint128, WasmQuery,
 };
+use cw20::Cw20QueryMsg;
 
+use mars_core::address_provider::msg::QueryMsg as AddressProviderQueryMsg;
+use mars_core::address_provider::MarsContract;
 use mars_core::asset::Asset;
+use mars_core::ma_token::msg::QueryMsg as MaTokenQueryMsg;
 use mars_core::red_bank::msg::QueryMsg as RedBankQueryMsg;
-use mars_core::red_bank::UserAssetDebtResponse;
+use mars_core::red_bank::{ConfigResponse, Market, UserAssetDebtResponse};
 
-use super::RedBankQuerier;
+use super::{
+    AddressProviderQuerier, Cw20Querier, HubQuerier, MaTokenQuerier, PairQuerier, RedBankQuerier,
+    RouterQuerier,
+};
 
 pub struct CustomQuerier {
     base: MockQuerier<Empty>,
     red_bank_querier: RedBankQuerier,
+    address_provider_querier: AddressProviderQuerier,
+    hub_querier: HubQuerier,
+    pair_querier: PairQuerier,
+    router_querier: RouterQuerier,
+    cw20_querier: Cw20Querier,
+    ma_token_querier: MaTokenQuerier,
 }
 
 impl Querier for CustomQuerier {
@@ -35,6 +52,12 @@ impl Default for CustomQuerier {
         Self {
             base: MockQuerier::new(&[]),
             red_bank_querier: RedBankQuerier::default(),
+            address_provider_querier: AddressProviderQuerier::default(),
+            hub_querier: HubQuerier::default(),
+            pair_querier: PairQuerier::default(),
+            router_querier: RouterQuerier::default(),
+            cw20_querier: Cw20Querier::default(),
+            ma_token_querier: MaTokenQuerier::default(),
         }
     }
 }
@@ -49,6 +72,32 @@ impl CustomQuerier {
                         .handle_query(contract_addr, red_bank_query);
                 }
 
+                if let Ok(address_provider_query) = from_binary::<AddressProviderQueryMsg>(msg) {
+                    return self
+                        .address_provider_querier
+                        .handle_query(contract_addr, address_provider_query);
+                }
+
+                if let Ok(hub_query) = from_binary::<HubQueryMsg>(msg) {
+                    return self.hub_querier.handle_query(contract_addr, hub_query);
+                }
+
+                if let Ok(pair_query) = from_binary::<PairQueryMsg>(msg) {
+                    return self.pair_querier.handle_query(contract_addr, pair_query);
+                }
+
+                if let Ok(router_query) = from_binary::<RouterQueryMsg>(msg) {
+                    return self.router_querier.handle_query(contract_addr, router_query);
+                }
+
+                if let Ok(cw20_query) = from_binary::<Cw20QueryMsg>(msg) {
+                    return self.cw20_querier.handle_query(contract_addr, cw20_query);
+                }
+
+                if let Ok(ma_token_query) = from_binary::<MaTokenQueryMsg>(msg) {
+                    return self.ma_token_querier.handle_query(contract_addr, ma_token_query);
+                }
+
                 panic!("[mock]: unsupported wasm query: {:?}", msg);
             },
 
@@ -70,4 +119,125 @@ impl CustomQuerier {
             .user_asset_debt
             .insert((user_address.into(), asset.get_attributes().0), debt);
     }
+
+    pub fn set_red_bank_config(&mut self, config: ConfigResponse) {
+        self.red_bank_querier.config = Some(config);
+    }
+
+    pub fn set_red_bank_market(&mut self, asset: Asset, market: Market) {
+        self.red_bank_querier.markets.insert(asset.get_attributes().0, market);
+    }
+
+    pub fn set_address_provider_address<T: Into<String>>(&mut self, address: T) {
+        self.address_provider_querier.address = address.into();
+    }
+
+    pub fn set_mars_contract_address<T: Into<String>>(
+        &mut self,
+        contract: MarsContract,
+        address: T,
+    ) {
+        self.address_provider_querier.addresses.push((contract, address.into()));
+    }
+
+    pub fn set_hub_address<T: Into<String>>(&mut self, address: T) {
+        self.hub_querier.address = address.into();
+    }
+
+    pub fn set_hub_current_batch(&mut self, batch_id: u64) {
+        self.hub_querier.current_batch = batch_id;
+    }
+
+    pub fn set_hub_exchange_rate(&mut self, exchange_rate: Decimal) {
+        self.hub_querier.exchange_rate = exchange_rate;
+    }
+
+    pub fn set_hub_peg_recovery(&mut self, peg_recovery_fee: Decimal, er_threshold: Decimal) {
+        self.hub_querier.peg_recovery_fee = peg_recovery_fee;
+        self.hub_querier.er_threshold = er_threshold;
+    }
+
+    pub fn set_hub_epochs(
+        &mut self,
+        last_unbonded_time: u64,
+        epoch_period: u64,
+        unbonding_period: u64,
+    ) {
+        self.hub_querier.last_unbonded_time = last_unbonded_time;
+        self.hub_querier.epoch_period = epoch_period;
+        self.hub_querier.unbonding_period = unbonding_period;
+    }
+
+    pub fn set_hub_unbond_requests<T: Into<String>>(
+        &mut self,
+        user_address: T,
+        requests: Vec<(u64, Uint128)>,
+    ) {
+        self.hub_querier.unbond_requests.insert(user_address.into(), requests);
+    }
+
+    pub fn set_hub_withdrawable_unbonded<T: Into<String>>(
+        &mut self,
+        user_address: T,
+        amount: Uint128,
+    ) {
+        self.hub_querier.withdrawable_unbonded.insert(user_address.into(), amount);
+    }
+
+    pub fn set_hub_history(&mut self, history: Vec<UnbondHistory>) {
+        self.hub_querier.history = history;
+    }
+
+    pub fn set_pair_address<T: Into<String>>(&mut self, address: T) {
+        self.pair_querier.address = address.into();
+    }
+
+    pub fn set_pair_pools(&mut self, offer_pool: Uint128, ask_pool: Uint128) {
+        self.pair_querier.pools = (offer_pool, ask_pool);
+    }
+
+    pub fn set_other_pair_pools<T: Into<String>>(&mut self, address: T, pools: &[(&str, u128)]) {
+        let pools = pools
+            .iter()
+            .map(|(label, amount)| (label.to_string(), Uint128::new(*amount)))
+            .collect();
+        self.pair_querier.other_pools.insert(address.into(), pools);
+    }
+
+    pub fn set_router_address<T: Into<String>>(&mut self, address: T) {
+        self.router_querier.address = address.into();
+    }
+
+    pub fn set_router_price<T: Into<String>, U: Into<String>>(
+        &mut self,
+        offer: T,
+        ask: U,
+        price: Decimal,
+    ) {
+        self.router_querier.prices.insert((offer.into(), ask.into()), price);
+    }
+
+    pub fn set_native_balance(&mut self, address: &str, balance: &[Coin]) {
+        self.base.update_balance(address, balance.to_vec());
+    }
+
+    pub fn set_cw20_balance<T: Into<String>, U: Into<String>>(
+        &mut self,
+        token_address: T,
+        user_address: U,
+        balance: Uint128,
+    ) {
+        self.cw20_querier.balances.insert((token_address.into(), user_address.into()), balance);
+    }
+
+    pub fn set_ma_token_underlying_balance<T: Into<String>, U: Into<String>>(
+        &mut self,
+        token_address: T,
+        user_address: U,
+        balance: Uint128,
+    ) {
+        self.ma_token_querier
+            .underlying_balances
+            .insert((token_address.into(), user_address.into()), balance);
+    }
 }
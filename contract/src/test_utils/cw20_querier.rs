@@ -0,0 +1,30 @@
+use std::collections::HashMap;
+
+use cosmwasm_std::{to_binary, QuerierResult, Uint128};
+use cw20::{BalanceResponse, Cw20QueryMsg};
+
+#[derive(Default)]
+pub struct Cw20Querier {
+    /// Each user's balance of a specific token. The 1st key is the token address; the 2nd key is
+    /// the user address.
+    pub balances: HashMap<(String, String), Uint128>,
+}
+
+impl Cw20Querier {
+    pub fn handle_query(&self, contract_addr: &String, query: Cw20QueryMsg) -> QuerierResult {
+        match query {
+            Cw20QueryMsg::Balance { address } => {
+                let res = BalanceResponse {
+                    balance: self
+                        .balances
+                        .get(&(contract_addr.clone(), address))
+                        .cloned()
+                        .unwrap_or_else(Uint128::zero),
+                };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            _ => panic!("[mock]: CW20 query is unimplemented")
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use basset::hub::{
+    AllHistoryResponse, CurrentBatchResponse, Parameters, QueryMsg, StateResponse, UnbondHistory,
+    UnbondRequestsResponse, WithdrawableUnbondedResponse,
+};
+use cosmwasm_std::{to_binary, Decimal, QuerierResult, Uint128};
+
+#[derive(Default)]
+pub struct HubQuerier {
+    /// Address of mock bLuna Hub contract to be used in queries
+    pub address: String,
+    /// Id of the unbonding batch currently accepting unbonding requests
+    pub current_batch: u64,
+    /// Exchange rate between bLuna and Luna
+    pub exchange_rate: Decimal,
+    /// Fee charged on unbonding while the exchange rate is below the threshold
+    pub peg_recovery_fee: Decimal,
+    /// Exchange rate below which the peg recovery fee is charged
+    pub er_threshold: Decimal,
+    /// Timestamp (in seconds) when the last unbonding batch was submitted
+    pub last_unbonded_time: u64,
+    /// Length (in seconds) of an epoch, i.e. the interval between unbonding batches
+    pub epoch_period: u64,
+    /// Length (in seconds) of the unbonding period
+    pub unbonding_period: u64,
+    /// Each user's pending unbonding requests, as pairs of batch ids and bLuna amounts. The key is
+    /// the user address.
+    pub unbond_requests: HashMap<String, Vec<(u64, Uint128)>>,
+    /// Amount of unbonded Luna each user can withdraw. The key is the user address.
+    pub withdrawable_unbonded: HashMap<String, Uint128>,
+    /// History of unbonding batches, in ascending order of batch ids
+    pub history: Vec<UnbondHistory>,
+}
+
+impl HubQuerier {
+    pub fn handle_query(&self, contract_addr: &String, query: QueryMsg) -> QuerierResult {
+        if contract_addr != &self.address {
+            panic!(
+                "[mock]: made a bLuna Hub query but contract address is incorrect; is {}, should be {}",
+                contract_addr,
+                self.address
+            );
+        }
+
+        match query {
+            QueryMsg::CurrentBatch {} => {
+                let res = CurrentBatchResponse {
+                    id: self.current_batch,
+                    requested_with_fee: Uint128::zero(),
+                };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            QueryMsg::State {} => {
+                let res = StateResponse {
+                    exchange_rate: self.exchange_rate,
+                    total_bond_amount: Uint128::zero(),
+                    last_index_modification: 0,
+                    prev_hub_balance: Uint128::zero(),
+                    actual_unbonded_amount: Uint128::zero(),
+                    last_unbonded_time: self.last_unbonded_time,
+                    last_processed_batch: 0,
+                };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            QueryMsg::Parameters {} => {
+                let res = Parameters {
+                    epoch_period: self.epoch_period,
+                    underlying_coin_denom: String::from("uluna"),
+                    unbonding_period: self.unbonding_period,
+                    peg_recovery_fee: self.peg_recovery_fee,
+                    er_threshold: self.er_threshold,
+                    reward_denom: String::from("uusd"),
+                };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            QueryMsg::UnbondRequests { address } => {
+                let requests = self.unbond_requests.get(&address).cloned().unwrap_or_default();
+                let res = UnbondRequestsResponse { address, requests };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            QueryMsg::WithdrawableUnbonded { address } => {
+                let res = WithdrawableUnbondedResponse {
+                    withdrawable: self
+                        .withdrawable_unbonded
+                        .get(&address)
+                        .cloned()
+                        .unwrap_or_else(Uint128::zero),
+                };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            QueryMsg::AllHistory { start_from, limit } => {
+                let res = AllHistoryResponse {
+                    history: self
+                        .history
+                        .iter()
+                        .filter(|history| start_from.map_or(true, |start| history.batch_id > start))
+                        .take(limit.unwrap_or(10).min(100) as usize)
+                        .cloned()
+                        .collect(),
+                };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            _ => panic!("[mock]: bLuna Hub query is unimplemented")
+        }
+    }
+}
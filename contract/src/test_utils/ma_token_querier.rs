@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+
+use cosmwasm_std::{to_binary, QuerierResult, Uint128};
+use cw20::BalanceResponse;
+use mars_core::ma_token::msg::QueryMsg;
+
+#[derive(Default)]
+pub struct MaTokenQuerier {
+    /// Amount of underlying asset each user's maToken balance is worth. The 1st key is the maToken
+    /// address; the 2nd key is the user address.
+    pub underlying_balances: HashMap<(String, String), Uint128>,
+}
+
+impl MaTokenQuerier {
+    pub fn handle_query(&self, contract_addr: &String, query: QueryMsg) -> QuerierResult {
+        match query {
+            QueryMsg::UnderlyingAssetBalance { address } => {
+                let res = BalanceResponse {
+                    balance: self
+                        .underlying_balances
+                        .get(&(contract_addr.clone(), address))
+                        .cloned()
+                        .unwrap_or_else(Uint128::zero),
+                };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            _ => panic!("[mock]: maToken query is unimplemented")
+        }
+    }
+}
@@ -1,5 +1,17 @@
+mod address_provider_querier;
 mod custom_querier;
+mod cw20_querier;
+mod hub_querier;
+mod ma_token_querier;
+mod pair_querier;
 mod red_bank_querier;
+mod router_querier;
 
+pub use address_provider_querier::AddressProviderQuerier;
 pub use custom_querier::CustomQuerier;
+pub use cw20_querier::Cw20Querier;
+pub use hub_querier::HubQuerier;
+pub use ma_token_querier::MaTokenQuerier;
+pub use pair_querier::PairQuerier;
 pub use red_bank_querier::RedBankQuerier;
+pub use router_querier::RouterQuerier;
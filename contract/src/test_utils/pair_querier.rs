@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use astroport::asset::{AssetInfo, PairInfo};
+use astroport::factory::PairType;
+use astroport::pair::{QueryMsg, SimulationResponse};
+use cosmwasm_std::{to_binary, Addr, QuerierResult, Uint128};
+
+#[derive(Default)]
+pub struct PairQuerier {
+    /// Address of mock Astroport pair contract to be used in queries
+    pub address: String,
+    /// Amounts of the offer asset and the ask asset in the pool. Swaps are simulated following the
+    /// constant product formula, without commission.
+    pub pools: (Uint128, Uint128),
+    /// Pools of other pairs, keyed by the pair's address. Each pool's amounts are keyed by the
+    /// asset's denom or contract address, so that swaps can be simulated in either direction.
+    pub other_pools: HashMap<String, HashMap<String, Uint128>>,
+}
+
+impl PairQuerier {
+    pub fn handle_query(&self, contract_addr: &String, query: QueryMsg) -> QuerierResult {
+        if let Some(pools) = self.other_pools.get(contract_addr) {
+            return match query {
+                QueryMsg::Simulation { offer_asset } => {
+                    let offer_label = label(&offer_asset.info);
+                    let offer_pool = pools[&offer_label];
+                    let ask_pool = pools
+                        .iter()
+                        .find(|(label, _)| **label != offer_label)
+                        .map(|(_, amount)| *amount)
+                        .unwrap();
+                    simulate(offer_pool, ask_pool, offer_asset.amount)
+                },
+
+                QueryMsg::Pair {} => {
+                    let mut labels = pools.keys().map(|label| info(label));
+                    let asset_infos = [labels.next().unwrap(), labels.next().unwrap()];
+                    pair(contract_addr, asset_infos)
+                },
+
+                _ => panic!("[mock]: pair query is unimplemented")
+            };
+        }
+
+        if contract_addr != &self.address {
+            panic!(
+                "[mock]: made a pair query but contract address is incorrect; is {}, should be {}",
+                contract_addr,
+                self.address
+            );
+        }
+
+        match query {
+            QueryMsg::Simulation { offer_asset } => {
+                let (offer_pool, ask_pool) = self.pools;
+                simulate(offer_pool, ask_pool, offer_asset.amount)
+            },
+
+            // The default pair is the Luna-bLuna pair
+            QueryMsg::Pair {} => pair(contract_addr, [info("uluna"), info("bluna_token")]),
+
+            _ => panic!("[mock]: pair query is unimplemented")
+        }
+    }
+}
+
+fn pair(contract_addr: &str, asset_infos: [AssetInfo; 2]) -> QuerierResult {
+    let res = PairInfo {
+        asset_infos,
+        contract_addr: Addr::unchecked(contract_addr),
+        liquidity_token: Addr::unchecked("liquidity_token"),
+        pair_type: PairType::Xyk {},
+    };
+    Ok(to_binary(&res).into()).into()
+}
+
+fn simulate(offer_pool: Uint128, ask_pool: Uint128, offer_amount: Uint128) -> QuerierResult {
+    let res = SimulationResponse {
+        return_amount: ask_pool.multiply_ratio(offer_amount, offer_pool + offer_amount),
+        spread_amount: Uint128::zero(),
+        commission_amount: Uint128::zero(),
+    };
+    Ok(to_binary(&res).into()).into()
+}
+
+fn label(info: &AssetInfo) -> String {
+    match info {
+        AssetInfo::Token { contract_addr } => contract_addr.to_string(),
+        AssetInfo::NativeToken { denom } => denom.clone(),
+    }
+}
+
+/// Native denoms are assumed to start with `u`, e.g. `uluna`; anything else is a token address
+fn info(label: &str) -> AssetInfo {
+    if label.starts_with('u') {
+        AssetInfo::NativeToken {
+            denom: label.to_string(),
+        }
+    } else {
+        AssetInfo::Token {
+            contract_addr: Addr::unchecked(label),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use cosmwasm_std::{QuerierResult, to_binary};
-use mars_core::red_bank::UserAssetDebtResponse;
+use mars_core::red_bank::{ConfigResponse, Market, UserAssetDebtResponse};
 use mars_core::red_bank::msg::QueryMsg;
 
 #[derive(Default)]
@@ -11,6 +11,11 @@ pub struct RedBankQuerier {
     /// Each user's debt info of a specific asset. The 1st key is the user address; the 2nd key is
     /// the asset's label generated by `mars_core::asset::Asset::get_attributes` method.
     pub user_asset_debt: HashMap<(String, String), UserAssetDebtResponse>,
+    /// Red Bank's config
+    pub config: Option<ConfigResponse>,
+    /// Markets of each asset. The key is the asset's label generated by
+    /// `mars_core::asset::Asset::get_attributes` method.
+    pub markets: HashMap<String, Market>,
 }
 
 impl RedBankQuerier {
@@ -33,6 +38,23 @@ impl RedBankQuerier {
                 }
             },
 
+            QueryMsg::Config {} => {
+                if let Some(config) = &self.config {
+                    Ok(to_binary(config).into()).into()
+                } else {
+                    panic!("[mock]: config is not set");
+                }
+            },
+
+            QueryMsg::Market { asset } => {
+                let asset_label = asset.get_attributes().0;
+                if let Some(market) = self.markets.get(&asset_label) {
+                    Ok(to_binary(market).into()).into()
+                } else {
+                    panic!("[mock]: market is not set");
+                }
+            },
+
             _ => panic!("[mock]: Red Bank query is unimplemented")
         }
     }
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use astroport::asset::AssetInfo;
+use astroport::router::{QueryMsg, SimulateSwapOperationsResponse, SwapOperation};
+use cosmwasm_std::{to_binary, Decimal, QuerierResult};
+
+#[derive(Default)]
+pub struct RouterQuerier {
+    /// Address of mock Astroport router contract to be used in queries
+    pub address: String,
+    /// Price of each hop, i.e. amount of the ask asset returned per unit of the offer asset, keyed
+    /// by the offer asset's and the ask asset's denom or contract address
+    pub prices: HashMap<(String, String), Decimal>,
+}
+
+impl RouterQuerier {
+    pub fn handle_query(&self, contract_addr: &String, query: QueryMsg) -> QuerierResult {
+        if contract_addr != &self.address {
+            panic!(
+                "[mock]: made a router query but contract address is incorrect; is {}, should be {}",
+                contract_addr,
+                self.address
+            );
+        }
+
+        match query {
+            QueryMsg::SimulateSwapOperations { offer_amount, operations } => {
+                let amount = operations.iter().fold(offer_amount, |amount, operation| {
+                    match operation {
+                        SwapOperation::AstroSwap { offer_asset_info, ask_asset_info } => {
+                            let key = (label(offer_asset_info), label(ask_asset_info));
+                            let price = self
+                                .prices
+                                .get(&key)
+                                .unwrap_or_else(|| panic!("[mock]: price of {:?} not set", key));
+                            amount * *price
+                        },
+                        _ => panic!("[mock]: router operation is unimplemented"),
+                    }
+                });
+                let res = SimulateSwapOperationsResponse { amount };
+                Ok(to_binary(&res).into()).into()
+            },
+
+            _ => panic!("[mock]: router query is unimplemented")
+        }
+    }
+}
+
+fn label(info: &AssetInfo) -> String {
+    match info {
+        AssetInfo::Token { contract_addr } => contract_addr.to_string(),
+        AssetInfo::NativeToken { denom } => denom.clone(),
+    }
+}
@@ -21,6 +21,10 @@ const argv = yargs(process.argv)
       demandOption: false,
       default: "0.05",
     },
+    "hold-ratio": {
+      type: "string",
+      demandOption: false,
+    },
   })
   .parseSync();
 
@@ -33,6 +37,7 @@ const argv = yargs(process.argv)
       execute_arb: {
         amount: argv["amount"],
         minimum_profit: argv["minimum_profit"],
+        hold_ratio: argv["hold_ratio"],
       },
     }),
   ]);